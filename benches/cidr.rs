@@ -0,0 +1,189 @@
+//! Compare the longest prefix match of the `CidrTable` with a binary
+//! Patricia trie on a synthetic IPv4 routing table, whose prefix lengths
+//! roughly follow the ones of a BGP full table.
+//!
+//! Run with `cargo bench --bench cidr`.
+
+use std::{hint::black_box, net::Ipv4Addr};
+
+use art_chibald::CidrTable;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const NB_ROUTES: u64 = 100_000;
+const NB_LOOKUPS: u64 = 10_000;
+
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+/// Clear the bits of `addr` after the `len` first.
+fn mask(addr: u32, len: u8) -> u32 {
+    addr & u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0)
+}
+
+fn routes() -> Vec<(u32, u8)> {
+    (1..=NB_ROUTES)
+        .map(|i| {
+            let rng = xorshift(i.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let len = match rng % 100 {
+                0..=59 => 24,
+                60..=69 => 23,
+                70..=79 => 22,
+                80..=87 => 20 + (rng >> 8) % 2,
+                88..=94 => 16 + (rng >> 8) % 4,
+                _ => 8 + (rng >> 8) % 8,
+            } as u8;
+            // the routes are clustered in a few /8 like the allocated space
+            let addr = (rng >> 32) as u32 & 0x3fff_ffff | 0x4000_0000;
+            (mask(addr, len), len)
+        })
+        .collect()
+}
+
+/// A path compressed binary trie, the classic structure of routing tables.
+struct Patricia {
+    nodes: Vec<PatriciaNode>,
+}
+
+struct PatriciaNode {
+    prefix: u32,
+    len: u8,
+    value: Option<u64>,
+    /// Index of the children, 0 when there is none.
+    children: [u32; 2],
+}
+
+fn bit(addr: u32, i: u8) -> usize {
+    (addr >> (31 - i) & 1) as usize
+}
+
+impl Patricia {
+    fn new() -> Self {
+        Patricia {
+            nodes: vec![PatriciaNode {
+                prefix: 0,
+                len: 0,
+                value: None,
+                children: [0; 2],
+            }],
+        }
+    }
+
+    fn push(&mut self, prefix: u32, len: u8, value: Option<u64>) -> u32 {
+        self.nodes.push(PatriciaNode {
+            prefix,
+            len,
+            value,
+            children: [0; 2],
+        });
+        (self.nodes.len() - 1) as u32
+    }
+
+    fn insert(&mut self, prefix: u32, len: u8, value: u64) {
+        let mut node = 0;
+        loop {
+            if self.nodes[node].len == len {
+                self.nodes[node].value = Some(value);
+                return;
+            }
+            let side = bit(prefix, self.nodes[node].len);
+            let child = self.nodes[node].children[side];
+            if child == 0 {
+                self.nodes[node].children[side] = self.push(prefix, len, Some(value));
+                return;
+            }
+            let (child_prefix, child_len) = {
+                let child = &self.nodes[child as usize];
+                (child.prefix, child.len)
+            };
+            let common = ((prefix ^ child_prefix).leading_zeros() as u8)
+                .min(len)
+                .min(child_len);
+            if common == child_len {
+                node = child as usize;
+                continue;
+            }
+            // split the edge where the prefixes diverge
+            let split = if common == len {
+                self.push(prefix, len, Some(value))
+            } else {
+                let split = self.push(mask(prefix, common), common, None);
+                let leaf = self.push(prefix, len, Some(value));
+                self.nodes[split as usize].children[bit(prefix, common)] = leaf;
+                split
+            };
+            self.nodes[split as usize].children[bit(child_prefix, common)] = child;
+            self.nodes[node].children[side] = split;
+            return;
+        }
+    }
+
+    fn longest_match(&self, addr: u32) -> Option<u64> {
+        let mut node = &self.nodes[0];
+        let mut best = node.value;
+        while node.len < 32 {
+            let child = node.children[bit(addr, node.len)];
+            if child == 0 {
+                break;
+            }
+            node = &self.nodes[child as usize];
+            if mask(addr, node.len) != node.prefix {
+                break;
+            }
+            best = node.value.or(best);
+        }
+        best
+    }
+}
+
+fn longest_match(c: &mut Criterion) {
+    let routes = routes();
+    let mut table = CidrTable::new();
+    let mut patricia = Patricia::new();
+    for (i, (prefix, len)) in routes.iter().enumerate() {
+        table.insert(Ipv4Addr::from(*prefix), *len, i as u64);
+        patricia.insert(*prefix, *len, i as u64);
+    }
+    // half of the addresses are covered by a route
+    let addrs: Vec<u32> = (1..=NB_LOOKUPS)
+        .map(|i| {
+            let rng = xorshift(i.wrapping_mul(0x2545_f491_4f6c_dd1d));
+            match i % 2 {
+                0 => routes[rng as usize % routes.len()].0 | (rng >> 40) as u32 & 0xff,
+                _ => (rng >> 32) as u32,
+            }
+        })
+        .collect();
+    for addr in &addrs {
+        let route = table.longest_match(Ipv4Addr::from(*addr));
+        assert_eq!(
+            route.map(|route| route.value),
+            patricia.longest_match(*addr)
+        );
+    }
+
+    let mut group = c.benchmark_group("longest_match");
+    group.bench_function("CidrTable", |b| {
+        b.iter(|| {
+            addrs
+                .iter()
+                .filter_map(|addr| table.longest_match(Ipv4Addr::from(black_box(*addr))))
+                .count()
+        })
+    });
+    group.bench_function("Patricia", |b| {
+        b.iter(|| {
+            addrs
+                .iter()
+                .filter_map(|addr| patricia.longest_match(black_box(*addr)))
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, longest_match);
+criterion_main!(benches);
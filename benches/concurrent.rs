@@ -0,0 +1,99 @@
+//! Compare the two synchronized trees on a read-heavy workload: every
+//! thread looks up random keys and overwrites one of them every twenty
+//! operations, in a tree preloaded with all the keys.
+//!
+//! Run with `cargo bench --bench concurrent`.
+
+use std::{hint::black_box, thread};
+
+use art_chibald::{ConcurrentArt, RowexArt};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const NB_KEYS: u64 = 100_000;
+const NB_OPS: u64 = 50_000;
+const WRITE_EVERY: u64 = 20;
+
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+fn key(i: u64) -> [u8; 8] {
+    xorshift(i.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1).to_be_bytes()
+}
+
+/// The operations shared by both trees.
+trait Tree: Sync {
+    fn get(&self, key: &[u8]) -> Option<u64>;
+    fn insert(&self, key: &[u8], value: u64) -> Option<u64>;
+}
+
+impl Tree for ConcurrentArt {
+    fn get(&self, key: &[u8]) -> Option<u64> {
+        self.get(key)
+    }
+
+    fn insert(&self, key: &[u8], value: u64) -> Option<u64> {
+        self.insert(key, value)
+    }
+}
+
+impl Tree for RowexArt {
+    fn get(&self, key: &[u8]) -> Option<u64> {
+        self.get(key)
+    }
+
+    fn insert(&self, key: &[u8], value: u64) -> Option<u64> {
+        self.insert(key, value)
+    }
+}
+
+fn run(tree: &impl Tree, nb_threads: u64) -> usize {
+    thread::scope(|scope| {
+        let threads: Vec<_> = (0..nb_threads)
+            .map(|thread| {
+                scope.spawn(move || {
+                    let mut found = 0;
+                    let mut rng = thread + 1;
+                    for op in 0..NB_OPS {
+                        rng = xorshift(rng);
+                        let key = key(rng % NB_KEYS);
+                        if op % WRITE_EVERY == 0 {
+                            tree.insert(&key, op);
+                        } else {
+                            found += usize::from(tree.get(black_box(&key)).is_some());
+                        }
+                    }
+                    found
+                })
+            })
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).sum()
+    })
+}
+
+fn read_heavy(c: &mut Criterion) {
+    let olc = ConcurrentArt::new();
+    let rowex = RowexArt::new();
+    for i in 0..NB_KEYS {
+        olc.insert(&key(i), i);
+        rowex.insert(&key(i), i);
+    }
+
+    let mut group = c.benchmark_group("read_heavy");
+    group.sample_size(20);
+    for nb_threads in [1, 4, 8] {
+        group.bench_function(format!("OLC/{nb_threads} threads"), |b| {
+            b.iter(|| run(&olc, nb_threads))
+        });
+        group.bench_function(format!("ROWEX/{nb_threads} threads"), |b| {
+            b.iter(|| run(&rowex, nb_threads))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, read_heavy);
+criterion_main!(benches);
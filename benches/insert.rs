@@ -0,0 +1,71 @@
+//! Measure the insertion in a fresh tree and in a tree already holding
+//! the keys, where no node has to change.
+//!
+//! Run with `cargo bench --bench insert`.
+
+use std::hint::black_box;
+
+use art_chibald::Art;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const NB_KEYS: u64 = 100_000;
+
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+fn datasets() -> [(&'static str, Vec<Vec<u8>>); 2] {
+    [
+        (
+            "random u64",
+            (1..=NB_KEYS)
+                .map(|i| {
+                    xorshift(i.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+                        .to_be_bytes()
+                        .to_vec()
+                })
+                .collect(),
+        ),
+        (
+            "session keys",
+            (0..NB_KEYS)
+                .map(|i| format!("user:{}:session", xorshift(i + 1) % 100_000_000).into_bytes())
+                .collect(),
+        ),
+    ]
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.sample_size(20);
+    for (name, keys) in datasets() {
+        group.bench_function(format!("{name}/fresh"), |b| {
+            b.iter(|| {
+                let mut art = Art::new();
+                for (i, key) in keys.iter().enumerate() {
+                    art.insert(key, i as u64);
+                }
+                art
+            })
+        });
+
+        let mut full = Art::new();
+        for (i, key) in keys.iter().enumerate() {
+            full.insert(key, i as u64);
+        }
+        group.bench_function(format!("{name}/overwrite"), |b| {
+            b.iter(|| {
+                for (i, key) in keys.iter().enumerate() {
+                    black_box(full.insert(key, i as u64 + 1));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, insert);
+criterion_main!(benches);
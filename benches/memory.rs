@@ -0,0 +1,109 @@
+//! Report the memory used by the tree for a few datasets.
+//!
+//! Run with `cargo bench --bench memory`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use art_chibald::{ArenaArt, Art};
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const NB_KEYS: u64 = 1_000_000;
+
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+/// Insert the `keys` in `art` and report the memory it allocated.
+fn report<T>(
+    name: &str,
+    mut art: T,
+    insert: impl Fn(&mut T, &[u8], u64) -> Option<u64>,
+    keys: impl Iterator<Item = Vec<u8>>,
+) {
+    let mut nb_keys = 0;
+    let mut key_bytes = 0;
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let before_allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    for (i, key) in keys.enumerate() {
+        nb_keys += 1;
+        key_bytes += key.len();
+        insert(&mut art, &key, i as u64);
+    }
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - before;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before_allocations;
+    println!(
+        "{name:<16} {nb_keys:>9} keys {:>12} bytes {:>7.1} bytes/key ({:.1} of keys) {:>5.2} allocations/key",
+        allocated,
+        allocated as f64 / nb_keys as f64,
+        key_bytes as f64 / nb_keys as f64,
+        allocations as f64 / nb_keys as f64,
+    );
+    drop(art);
+}
+
+type Keys = Box<dyn Iterator<Item = Vec<u8>>>;
+
+fn datasets() -> [(&'static str, Keys); 3] {
+    [
+        (
+            "dense u64",
+            Box::new((0..NB_KEYS).map(|i| i.to_be_bytes().to_vec())),
+        ),
+        (
+            "random u64",
+            Box::new((1..=NB_KEYS).map(|i| {
+                xorshift(i.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+                    .to_be_bytes()
+                    .to_vec()
+            })),
+        ),
+        (
+            "session keys",
+            Box::new(
+                (0..NB_KEYS).map(|i| {
+                    format!("user:{}:session", xorshift(i + 1) % 100_000_000).into_bytes()
+                }),
+            ),
+        ),
+    ]
+}
+
+fn main() {
+    println!(
+        "size_of::<Node>() = {}",
+        std::mem::size_of::<art_chibald::Node>()
+    );
+    println!("Art");
+    for (name, keys) in datasets() {
+        report(name, Art::new(), Art::insert, keys);
+    }
+    println!("ArenaArt");
+    for (name, keys) in datasets() {
+        report(name, ArenaArt::new(), ArenaArt::insert, keys);
+    }
+}
@@ -0,0 +1,51 @@
+//! Compare the SIMD searches of the Node16 with a linear scan.
+//!
+//! Run with `cargo bench --bench node16`.
+
+use std::hint::black_box;
+
+use art_chibald::bench::{find_key, find_key_linear, insert_position, insert_position_linear};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+type Search = fn(&[u8; 16], usize, u8) -> usize;
+
+fn bench(c: &mut Criterion, name: &str, simd: Search, linear: Search) {
+    let mut group = c.benchmark_group(name);
+    for len in [5, 10, 16] {
+        let mut keys = [0; 16];
+        for (i, key) in keys.iter_mut().take(len).enumerate() {
+            *key = (i * 16) as u8;
+        }
+        // a mix of hits and misses spread over the whole node
+        let needles: Vec<u8> = (0..=255).step_by(7).collect();
+        for (search, f) in [("simd", simd), ("linear", linear)] {
+            group.bench_with_input(BenchmarkId::new(search, len), &len, |b, &len| {
+                b.iter(|| {
+                    needles
+                        .iter()
+                        .map(|needle| f(black_box(&keys), len, *needle))
+                        .sum::<usize>()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn search(c: &mut Criterion) {
+    bench(
+        c,
+        "find_key",
+        |keys, len, key| find_key(keys, len, key).unwrap_or(16),
+        |keys, len, key| find_key_linear(keys, len, key).unwrap_or(16),
+    );
+    bench(
+        c,
+        "insert_position",
+        insert_position,
+        insert_position_linear,
+    );
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
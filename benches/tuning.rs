@@ -0,0 +1,68 @@
+//! Measure the lookups on integer keys before and after the tree rewrote
+//! its hot dense subtrees into multi-level nodes.
+//!
+//! Run with `cargo bench --bench tuning`.
+
+use std::hint::black_box;
+
+use art_chibald::Art;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const NB_KEYS: u64 = 1_000_000;
+const NB_LOOKUPS: u64 = 100_000;
+
+fn xorshift(mut x: u64) -> u64 {
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+fn datasets() -> [(&'static str, Vec<u64>); 3] {
+    [
+        ("dense u64", (0..NB_KEYS).collect()),
+        ("sparse u64", (0..NB_KEYS).map(|i| i * 7).collect()),
+        (
+            "random u64",
+            (1..=NB_KEYS)
+                .map(|i| xorshift(i.wrapping_mul(0x9e37_79b9_7f4a_7c15)))
+                .collect(),
+        ),
+    ]
+}
+
+fn lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup");
+    group.sample_size(20);
+    for (name, keys) in datasets() {
+        let lookups: Vec<[u8; 8]> = (0..NB_LOOKUPS)
+            .map(|i| keys[(xorshift(i + 1) % NB_KEYS) as usize].to_be_bytes())
+            .collect();
+        let mut art = Art::new();
+        for (i, key) in keys.iter().enumerate() {
+            art.insert(&key.to_be_bytes(), i as u64);
+        }
+
+        let mut run = |variant: &str, art: &Art| {
+            group.bench_function(format!("{name}/{variant}"), |b| {
+                b.iter(|| {
+                    for key in &lookups {
+                        black_box(art.get(key));
+                    }
+                })
+            });
+        };
+        run("plain", &art);
+        art.track_accesses();
+        for key in &lookups {
+            art.get(key);
+        }
+        let multi_levels = art.optimize();
+        println!("{name}: {multi_levels} multi-level nodes");
+        run("optimized", &art);
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookup);
+criterion_main!(benches);
@@ -0,0 +1,807 @@
+//! A variant of the [`Art`](crate::Art) storing its nodes in arenas.
+//!
+//! The nodes of each type live in their own slab and refer to their children
+//! with `u32` indices instead of pointers. When a node grows, its slot in the
+//! slab of its previous type is put in a free list and reused by the next node
+//! of that type. The leaves copy their key in a single buffer, so inserting
+//! only allocates when one of the vectors grows, and the whole tree is made
+//! of a handful of plain vectors.
+//!
+//! Removing a key frees its leaf and compacts the nodes above it like
+//! [`Art::remove`](crate::Art::remove) does, and their slots are reused the
+//! same way. The key bytes of the removed leaves are reclaimed by copying
+//! the live keys to a new buffer once they make up less than half of it.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::num::NonZeroU32;
+
+use crate::{
+    common_len, node16::Node16, node256::Node256, node4::Node4, node48::Node48, ArtError,
+    PREFIX_LEN,
+};
+
+/// The index of a node in the slab of its type, the type is stored in the
+/// three highest bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct NodeId(NonZeroU32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Leaf = 1,
+    Node4,
+    Node16,
+    Node48,
+    Node256,
+}
+
+const KIND_SHIFT: u32 = 29;
+
+/// The number of nodes of each type an [`ArenaArt`] can hold.
+const MAX_NODES: usize = 1 << KIND_SHIFT;
+
+/// The number of key bytes an [`ArenaArt`] can hold.
+const MAX_KEY_BYTES: usize = u32::MAX as usize;
+
+impl NodeId {
+    fn new(kind: Kind, index: u32) -> Self {
+        // the insertions check that the slabs have room first
+        debug_assert!(
+            (index as usize) < MAX_NODES,
+            "Too many nodes in an ArenaArt"
+        );
+        // the kind is never zero
+        NodeId(NonZeroU32::new((kind as u32) << KIND_SHIFT | index).unwrap())
+    }
+
+    fn kind(self) -> Kind {
+        match self.0.get() >> KIND_SHIFT {
+            1 => Kind::Leaf,
+            2 => Kind::Node4,
+            3 => Kind::Node16,
+            4 => Kind::Node48,
+            5 => Kind::Node256,
+            kind => unreachable!("Invalid node kind {kind}"),
+        }
+    }
+
+    fn index(self) -> usize {
+        (self.0.get() & ((1 << KIND_SHIFT) - 1)) as usize
+    }
+}
+
+/// A vector whose removed slots are reused by the next insertions.
+#[derive(Debug)]
+struct Slab<T> {
+    slots: Vec<Option<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Slab {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<T> Slab<T> {
+    fn insert(&mut self, value: T) -> u32 {
+        match self.free.pop() {
+            Some(index) => {
+                self.slots[index as usize] = Some(value);
+                index
+            }
+            None => {
+                self.slots.push(Some(value));
+                (self.slots.len() - 1) as u32
+            }
+        }
+    }
+
+    fn remove(&mut self, index: usize) -> T {
+        let value = self.slots[index].take().expect("Removed a free slot");
+        self.free.push(index as u32);
+        value
+    }
+
+    /// Tell if one more value can be inserted with an index a [`NodeId`] can
+    /// hold.
+    fn has_room(&self) -> bool {
+        !self.free.is_empty() || self.slots.len() < MAX_NODES
+    }
+
+    fn get(&self, index: usize) -> &T {
+        self.slots[index].as_ref().expect("Accessed a free slot")
+    }
+
+    fn get_mut(&mut self, index: usize) -> &mut T {
+        self.slots[index].as_mut().expect("Accessed a free slot")
+    }
+}
+
+/// A leaf refers to its key in [`ArenaArt::keys`].
+#[derive(Debug)]
+struct Leaf {
+    start: u32,
+    len: u32,
+    value: u64,
+}
+
+/// The header shared by all the inner nodes, see [`crate::Node`].
+#[derive(Debug, Default)]
+struct Header {
+    nb_childrens: u16,
+    prefix_len: u32,
+    prefix: [u8; PREFIX_LEN],
+    /// The leaf of the key ending right after the compressed path.
+    end: Option<NodeId>,
+}
+
+impl Header {
+    fn new(prefix: &[u8]) -> Self {
+        let mut header = Header::default();
+        header.set_prefix(prefix);
+        header
+    }
+
+    fn stored_prefix(&self) -> &[u8] {
+        &self.prefix[..(self.prefix_len as usize).min(PREFIX_LEN)]
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.prefix_len as usize > PREFIX_LEN
+    }
+
+    fn set_prefix(&mut self, prefix: &[u8]) {
+        self.prefix_len = prefix.len() as u32;
+        let stored = prefix.len().min(PREFIX_LEN);
+        self.prefix[..stored].copy_from_slice(&prefix[..stored]);
+    }
+}
+
+#[derive(Debug)]
+struct Inner<N> {
+    header: Header,
+    node: N,
+}
+
+/// An adaptive radix tree whose nodes are stored in per type slabs.
+///
+/// It stores the same entries as an [`Art`](crate::Art) but every value
+/// lives in a leaf.
+#[derive(Debug, Default)]
+pub struct ArenaArt {
+    root: Option<NodeId>,
+    /// The keys of all the leaves, one after the other.
+    keys: Vec<u8>,
+    /// The number of bytes of `keys` no leaf refers to anymore.
+    garbage: usize,
+    leaves: Slab<Leaf>,
+    node4: Slab<Inner<Node4<NodeId>>>,
+    node16: Slab<Inner<Node16<NodeId>>>,
+    node48: Slab<Inner<Node48<NodeId>>>,
+    node256: Slab<Inner<Node256<NodeId>>>,
+}
+
+impl ArenaArt {
+    pub fn new() -> ArenaArt {
+        Default::default()
+    }
+
+    /// Insert `value` under `key` and return the previous value, or leave
+    /// the tree untouched with [`ArtError::CapacityExceeded`] if it can't
+    /// index a new node or the bytes of `key`.
+    ///
+    /// A tree holds up to 2^29 nodes of each type and 4 GiB of keys.
+    pub fn try_insert(&mut self, key: &[u8], value: u64) -> Result<Option<u64>, ArtError> {
+        if self.keys.len() + key.len() > MAX_KEY_BYTES && self.garbage > 0 {
+            self.collect_keys();
+        }
+        // an insertion adds at most a leaf, a Node4 and a grown node
+        let has_room = self.keys.len() + key.len() <= MAX_KEY_BYTES
+            && self.leaves.has_room()
+            && self.node4.has_room()
+            && self.node16.has_room()
+            && self.node48.has_room()
+            && self.node256.has_room();
+        // replacing the value of a key doesn't need any room
+        if !has_room && self.get(key).is_none() {
+            return Err(ArtError::CapacityExceeded);
+        }
+        let (root, old_value) = match self.root {
+            Some(root) => self.insert_at(root, key, 0, value),
+            None => (self.new_leaf(key, value), None),
+        };
+        self.root = Some(root);
+        Ok(old_value)
+    }
+
+    /// Insert `value` under `key` and return the previous value.
+    ///
+    /// # Panics
+    ///
+    /// If the tree is full, see [`ArenaArt::try_insert`].
+    pub fn insert(&mut self, key: &[u8], value: u64) -> Option<u64> {
+        self.try_insert(key, value).expect("ArenaArt is full")
+    }
+
+    /// Remove the value stored under `key` and return it.
+    ///
+    /// The tree keeps the shape it would have without this key, and the
+    /// slots and key bytes it used are reused by the next insertions.
+    pub fn remove(&mut self, key: &[u8]) -> Option<u64> {
+        let (root, value) = self.remove_at(self.root?, key, 0)?;
+        self.root = root;
+        if self.garbage > self.keys.len() / 2 {
+            self.collect_keys();
+        }
+        Some(value)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<u64> {
+        let mut id = self.root?;
+        let mut depth = 0;
+        loop {
+            if id.kind() == Kind::Leaf {
+                // the skipped bytes of the truncated prefixes are verified here
+                let leaf = self.leaves.get(id.index());
+                return (self.leaf_key(id) == key).then_some(leaf.value);
+            }
+            let header = self.header(id);
+            let stored = header.stored_prefix();
+            if key.get(depth..depth + stored.len())? != stored {
+                return None;
+            }
+            depth += header.prefix_len as usize;
+            id = match key.get(depth) {
+                Some(byte) => {
+                    depth += 1;
+                    self.find_child(id, *byte)?
+                }
+                None if key.len() == depth => header.end?,
+                None => return None,
+            };
+        }
+    }
+
+    /// Insert `value` under `key` in the subtree `id`, reached after `depth`
+    /// bytes of `key`, and return the node that replaces `id`.
+    fn insert_at(
+        &mut self,
+        id: NodeId,
+        key: &[u8],
+        depth: usize,
+        value: u64,
+    ) -> (NodeId, Option<u64>) {
+        if id.kind() == Kind::Leaf {
+            let leaf_key = self.leaf_key(id);
+            if leaf_key == key {
+                let leaf = self.leaves.get_mut(id.index());
+                let old_value = core::mem::replace(&mut leaf.value, value);
+                return (id, Some(old_value));
+            }
+            let common = common_len(&key[depth..], &leaf_key[depth..]);
+            let split = depth + common;
+            let leaf_byte = leaf_key.get(split).copied();
+
+            let node = self.new_node4(&key[depth..split]);
+            let node = self.add_leaf(node, leaf_byte, id);
+            let leaf = self.new_leaf(key, value);
+            let node = self.add_leaf(node, key.get(split).copied(), leaf);
+            return (node, None);
+        }
+
+        let prefix_len = self.header(id).prefix_len as usize;
+        let path = self.path(id, depth);
+        let common = common_len(path, &key[depth..]);
+        if common < prefix_len {
+            // split the compressed path where it diverges from the key
+            let path = path.to_vec();
+            self.header_mut(id).set_prefix(&path[common + 1..]);
+            let node = self.new_node4(&path[..common]);
+            let node = self.add_child(node, path[common], id);
+            let leaf = self.new_leaf(key, value);
+            let node = self.add_leaf(node, key.get(depth + common).copied(), leaf);
+            return (node, None);
+        }
+
+        let depth = depth + prefix_len;
+        match key.get(depth) {
+            Some(&byte) => match self.find_child(id, byte) {
+                Some(child) => {
+                    let (new_child, old_value) = self.insert_at(child, key, depth + 1, value);
+                    if new_child != child {
+                        *self.child_mut(id, byte).unwrap() = Some(new_child);
+                    }
+                    (id, old_value)
+                }
+                None => {
+                    let leaf = self.new_leaf(key, value);
+                    (self.add_child(id, byte, leaf), None)
+                }
+            },
+            None => {
+                let old_value = match self.header(id).end {
+                    // the end is always a leaf with the same key
+                    Some(end) => self.insert_at(end, key, depth, value).1,
+                    None => {
+                        let leaf = self.new_leaf(key, value);
+                        self.header_mut(id).end = Some(leaf);
+                        None
+                    }
+                };
+                (id, old_value)
+            }
+        }
+    }
+
+    /// Remove `key` from the subtree `id`, reached after `depth` bytes of
+    /// `key`, and return the node that replaces `id`, if any is left, with
+    /// the removed value.
+    fn remove_at(&mut self, id: NodeId, key: &[u8], depth: usize) -> Option<(Option<NodeId>, u64)> {
+        if id.kind() == Kind::Leaf {
+            // the skipped bytes of the truncated prefixes are verified here
+            if self.leaf_key(id) != key {
+                return None;
+            }
+            let leaf = self.leaves.remove(id.index());
+            self.garbage += leaf.len as usize;
+            return Some((None, leaf.value));
+        }
+
+        let header = self.header(id);
+        let stored = header.stored_prefix();
+        if key.get(depth..depth + stored.len())? != stored {
+            return None;
+        }
+        let end = depth + header.prefix_len as usize;
+        let value = match key.get(end) {
+            Some(&byte) => {
+                let child = self.find_child(id, byte)?;
+                match self.remove_at(child, key, end + 1)? {
+                    (Some(new_child), value) => {
+                        if new_child != child {
+                            *self.child_mut(id, byte).unwrap() = Some(new_child);
+                        }
+                        // the node still has the same entries
+                        return Some((Some(id), value));
+                    }
+                    (None, value) => {
+                        self.remove_child(id, byte);
+                        value
+                    }
+                }
+            }
+            None if key.len() == end => {
+                let (_, value) = self.remove_at(header.end?, key, end)?;
+                self.header_mut(id).end = None;
+                value
+            }
+            None => return None,
+        };
+        Some((self.compact(id, depth), value))
+    }
+
+    /// Replace the inner node `id`, reached after `depth` bytes, by its
+    /// entry if a single one is left, or by a smaller node type if it has
+    /// few children, with the same slack as [`Art::remove`](crate::Art::remove).
+    /// Return the node that replaces `id`, if any is left.
+    fn compact(&mut self, id: NodeId, depth: usize) -> Option<NodeId> {
+        let header = self.header(id);
+        let (nb_childrens, end, prefix_len) = (header.nb_childrens, header.end, header.prefix_len);
+        let replacement = match (nb_childrens, end) {
+            (0, end) => end,
+            (1, None) => {
+                let (byte, child) = self.children(id).next().map(|(b, c)| (b, *c)).unwrap();
+                if child.kind() != Kind::Leaf {
+                    let end = depth + prefix_len as usize;
+                    let path = [self.path(id, depth), &[byte], self.path(child, end + 1)].concat();
+                    self.header_mut(child).set_prefix(&path);
+                }
+                Some(child)
+            }
+            _ => return Some(self.shrink(id)),
+        };
+        let index = id.index();
+        match id.kind() {
+            Kind::Node4 => drop(self.node4.remove(index)),
+            Kind::Node16 => drop(self.node16.remove(index)),
+            Kind::Node48 => drop(self.node48.remove(index)),
+            Kind::Node256 => drop(self.node256.remove(index)),
+            Kind::Leaf => unreachable!("Compacted a leaf"),
+        }
+        replacement
+    }
+
+    /// Shrink the inner node `id` to a smaller node type if it has few
+    /// children, and return the node that replaces it.
+    fn shrink(&mut self, id: NodeId) -> NodeId {
+        let index = id.index();
+        let len = self.header(id).nb_childrens;
+        match id.kind() {
+            Kind::Node16 if len <= 3 && self.node4.has_room() => {
+                let Inner { header, node } = self.node16.remove(index);
+                let node = Node4::from(node);
+                NodeId::new(Kind::Node4, self.node4.insert(Inner { header, node }))
+            }
+            Kind::Node48 if len <= 12 && self.node16.has_room() => {
+                let Inner { header, node } = self.node48.remove(index);
+                let node = Node16::from(node);
+                NodeId::new(Kind::Node16, self.node16.insert(Inner { header, node }))
+            }
+            Kind::Node256 if len <= 37 && self.node48.has_room() => {
+                let Inner { header, node } = self.node256.remove(index);
+                let node = Node48::from(node);
+                NodeId::new(Kind::Node48, self.node48.insert(Inner { header, node }))
+            }
+            _ => id,
+        }
+    }
+
+    /// Copy the keys of the leaves to a new buffer, dropping the bytes of
+    /// the removed ones.
+    fn collect_keys(&mut self) {
+        let mut keys = Vec::with_capacity(self.keys.len() - self.garbage);
+        for leaf in self.leaves.slots.iter_mut().flatten() {
+            let start = leaf.start as usize;
+            let new_start = keys.len() as u32;
+            keys.extend_from_slice(&self.keys[start..start + leaf.len as usize]);
+            leaf.start = new_start;
+        }
+        self.keys = keys;
+        self.garbage = 0;
+    }
+
+    fn new_leaf(&mut self, key: &[u8], value: u64) -> NodeId {
+        // the insertions check that the keys fit first
+        let start = self.keys.len() as u32;
+        self.keys.extend_from_slice(key);
+        let leaf = Leaf {
+            start,
+            len: key.len() as u32,
+            value,
+        };
+        NodeId::new(Kind::Leaf, self.leaves.insert(leaf))
+    }
+
+    fn new_node4(&mut self, prefix: &[u8]) -> NodeId {
+        let inner = Inner {
+            header: Header::new(prefix),
+            node: Node4::default(),
+        };
+        NodeId::new(Kind::Node4, self.node4.insert(inner))
+    }
+
+    fn leaf_key(&self, id: NodeId) -> &[u8] {
+        let leaf = self.leaves.get(id.index());
+        let start = leaf.start as usize;
+        &self.keys[start..start + leaf.len as usize]
+    }
+
+    /// Return the key of any leaf living under `id`.
+    fn any_leaf_key(&self, mut id: NodeId) -> &[u8] {
+        while id.kind() != Kind::Leaf {
+            id = match self.header(id).end {
+                Some(end) => end,
+                None => {
+                    *self
+                        .children(id)
+                        .next()
+                        .expect("Inner node without child")
+                        .1
+                }
+            };
+        }
+        self.leaf_key(id)
+    }
+
+    /// Return the compressed path of `id`, reached after `depth` bytes. For a
+    /// leaf, this is the remaining part of its key.
+    fn path(&self, id: NodeId, depth: usize) -> &[u8] {
+        if id.kind() == Kind::Leaf {
+            return &self.leaf_key(id)[depth..];
+        }
+        let header = self.header(id);
+        if header.is_truncated() {
+            &self.any_leaf_key(id)[depth..depth + header.prefix_len as usize]
+        } else {
+            header.stored_prefix()
+        }
+    }
+
+    /// Add `leaf` to the inner node `id`, under `byte`, or as its end when
+    /// its key ends there. Return the node that replaces `id`.
+    fn add_leaf(&mut self, id: NodeId, byte: Option<u8>, leaf: NodeId) -> NodeId {
+        match byte {
+            Some(byte) => self.add_child(id, byte, leaf),
+            None => {
+                self.header_mut(id).end = Some(leaf);
+                id
+            }
+        }
+    }
+
+    /// Add a child to the inner node `id`, growing it to the next node type if
+    /// it is full. Return the node that replaces `id`.
+    fn add_child(&mut self, id: NodeId, byte: u8, child: NodeId) -> NodeId {
+        let index = id.index();
+        let id = match id.kind() {
+            Kind::Node4 if self.node4.get(index).node.is_full() => {
+                let Inner { header, node } = self.node4.remove(index);
+                let mut node = Node16::from(node);
+                node.insert(byte, child);
+                NodeId::new(Kind::Node16, self.node16.insert(Inner { header, node }))
+            }
+            Kind::Node4 => {
+                self.node4.get_mut(index).node.insert(byte, child);
+                id
+            }
+            Kind::Node16 if self.node16.get(index).node.is_full() => {
+                let Inner { header, node } = self.node16.remove(index);
+                let mut node = Node48::from(node);
+                node.insert(byte, child);
+                NodeId::new(Kind::Node48, self.node48.insert(Inner { header, node }))
+            }
+            Kind::Node16 => {
+                self.node16.get_mut(index).node.insert(byte, child);
+                id
+            }
+            Kind::Node48 if self.node48.get(index).node.is_full() => {
+                let Inner { header, node } = self.node48.remove(index);
+                let mut node = Node256::from(node);
+                node.insert(byte, child);
+                NodeId::new(Kind::Node256, self.node256.insert(Inner { header, node }))
+            }
+            Kind::Node48 => {
+                self.node48.get_mut(index).node.insert(byte, child);
+                id
+            }
+            Kind::Node256 => {
+                self.node256.get_mut(index).node.insert(byte, child);
+                id
+            }
+            Kind::Leaf => unreachable!("Added a child to a leaf"),
+        };
+        self.header_mut(id).nb_childrens += 1;
+        id
+    }
+
+    /// Remove the child of the inner node `id` stored under `byte`.
+    fn remove_child(&mut self, id: NodeId, byte: u8) {
+        let index = id.index();
+        let child = match id.kind() {
+            Kind::Node4 => self.node4.get_mut(index).node.remove(byte),
+            Kind::Node16 => self.node16.get_mut(index).node.remove(byte),
+            Kind::Node48 => self.node48.get_mut(index).node.remove(byte),
+            Kind::Node256 => self.node256.get_mut(index).node.remove(byte),
+            Kind::Leaf => None,
+        };
+        if child.is_some() {
+            self.header_mut(id).nb_childrens -= 1;
+        }
+    }
+
+    fn header(&self, id: NodeId) -> &Header {
+        let index = id.index();
+        match id.kind() {
+            Kind::Node4 => &self.node4.get(index).header,
+            Kind::Node16 => &self.node16.get(index).header,
+            Kind::Node48 => &self.node48.get(index).header,
+            Kind::Node256 => &self.node256.get(index).header,
+            Kind::Leaf => unreachable!("A leaf has no header"),
+        }
+    }
+
+    fn header_mut(&mut self, id: NodeId) -> &mut Header {
+        let index = id.index();
+        match id.kind() {
+            Kind::Node4 => &mut self.node4.get_mut(index).header,
+            Kind::Node16 => &mut self.node16.get_mut(index).header,
+            Kind::Node48 => &mut self.node48.get_mut(index).header,
+            Kind::Node256 => &mut self.node256.get_mut(index).header,
+            Kind::Leaf => unreachable!("A leaf has no header"),
+        }
+    }
+
+    fn children(&self, id: NodeId) -> Box<dyn Iterator<Item = (u8, &NodeId)> + '_> {
+        let index = id.index();
+        match id.kind() {
+            Kind::Node4 => Box::new(self.node4.get(index).node.children()),
+            Kind::Node16 => Box::new(self.node16.get(index).node.children()),
+            Kind::Node48 => Box::new(self.node48.get(index).node.children()),
+            Kind::Node256 => Box::new(self.node256.get(index).node.children()),
+            Kind::Leaf => Box::new(core::iter::empty()),
+        }
+    }
+
+    fn find_child(&self, id: NodeId, byte: u8) -> Option<NodeId> {
+        let index = id.index();
+        match id.kind() {
+            Kind::Node4 => self.node4.get(index).node.find_child(byte).copied(),
+            Kind::Node16 => self.node16.get(index).node.find_child(byte).copied(),
+            Kind::Node48 => self.node48.get(index).node.find_child(byte).copied(),
+            Kind::Node256 => self.node256.get(index).node.find_child(byte).copied(),
+            Kind::Leaf => None,
+        }
+    }
+
+    fn child_mut(&mut self, id: NodeId, byte: u8) -> Option<&mut Option<NodeId>> {
+        let index = id.index();
+        match id.kind() {
+            Kind::Node4 => self.node4.get_mut(index).node.child_mut(byte),
+            Kind::Node16 => self.node16.get_mut(index).node.child_mut(byte),
+            Kind::Node48 => self.node48.get_mut(index).node.child_mut(byte),
+            Kind::Node256 => self.node256.get_mut(index).node.child_mut(byte),
+            Kind::Leaf => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::test_rng;
+
+    #[test]
+    fn insert_many_values() {
+        let mut art = ArenaArt::new();
+        let mut map = std::collections::BTreeMap::new();
+        for (i, rng) in (0..10_000).zip(test_rng()) {
+            let mut key = match (rng >> 32) as usize % 3 {
+                0 => b"a long shared prefix".to_vec(),
+                1 => b"a long shared prefiX".to_vec(),
+                _ => Vec::new(),
+            };
+            key.extend_from_slice(&rng.to_be_bytes()[..(rng >> 60) as usize % 9]);
+            assert_eq!(art.insert(&key, i), map.insert(key, i));
+        }
+        for (key, value) in &map {
+            assert_eq!(art.get(key), Some(*value));
+        }
+        assert_eq!(art.get(b"a long shared prefix: missing"), None);
+        assert_eq!(art.get(b"a long"), None);
+    }
+
+    #[test]
+    fn reuse_free_slots() {
+        let mut art = ArenaArt::new();
+        for i in 0..=4 {
+            art.insert(&[b'a', i], u64::from(i));
+        }
+        // the Node4 grew into a Node16 and its slot was freed
+        insta::assert_debug_snapshot!((art.node4.slots.len(), &art.node4.free), @r###"
+        (
+            1,
+            [
+                0,
+            ],
+        )
+        "###);
+        for i in 0..=1 {
+            art.insert(&[b'b', i], u64::from(i));
+        }
+        insta::assert_debug_snapshot!((art.node4.slots.len(), &art.node4.free), @r###"
+        (
+            2,
+            [],
+        )
+        "###);
+        insta::assert_debug_snapshot!(art.root.map(NodeId::kind), @r###"
+        Some(
+            Node4,
+        )
+        "###);
+        assert_eq!(art.get(b"b\x01"), Some(1));
+        assert_eq!(art.get(b"a\x04"), Some(4));
+    }
+
+    /// The number of nodes of each type in use.
+    fn live_nodes(art: &ArenaArt) -> [usize; 5] {
+        fn live<T>(slab: &Slab<T>) -> usize {
+            slab.slots.len() - slab.free.len()
+        }
+        [
+            live(&art.leaves),
+            live(&art.node4),
+            live(&art.node16),
+            live(&art.node48),
+            live(&art.node256),
+        ]
+    }
+
+    #[test]
+    fn remove_values() {
+        let mut art = ArenaArt::new();
+        let mut map = std::collections::BTreeMap::new();
+        for (i, rng) in (0..20_000).zip(test_rng()) {
+            let mut key = match (rng >> 32) as usize % 3 {
+                0 => b"a long shared prefix".to_vec(),
+                1 => b"a long shared prefiX".to_vec(),
+                _ => Vec::new(),
+            };
+            key.extend_from_slice(&rng.to_be_bytes()[..(rng >> 60) as usize % 4]);
+            if i % 3 == 0 {
+                assert_eq!(art.remove(&key), map.remove(&key), "{key:?}");
+            } else {
+                assert_eq!(art.insert(&key, i), map.insert(key, i));
+            }
+        }
+        for (key, value) in &map {
+            assert_eq!(art.get(key), Some(*value));
+        }
+        assert!(art.keys.len() <= 2 * map.keys().map(Vec::len).sum::<usize>());
+
+        // the tree has as many nodes as without the removed keys, some of
+        // them just didn't shrink yet
+        let mut fresh = ArenaArt::new();
+        for (key, value) in &map {
+            fresh.insert(key, *value);
+        }
+        let [leaves, inner @ ..] = live_nodes(&art);
+        let [fresh_leaves, fresh_inner @ ..] = live_nodes(&fresh);
+        assert_eq!(leaves, fresh_leaves);
+        assert_eq!(
+            inner.iter().sum::<usize>(),
+            fresh_inner.iter().sum::<usize>()
+        );
+
+        for key in map.keys() {
+            assert!(art.remove(key).is_some());
+            assert_eq!(art.remove(key), None);
+        }
+        assert_eq!(art.root, None);
+        assert_eq!(live_nodes(&art), [0; 5]);
+        assert!(art.keys.is_empty());
+    }
+
+    #[test]
+    fn compact_nodes() {
+        let mut art = ArenaArt::new();
+        for i in 0..=4 {
+            art.insert(&[b'a', i], u64::from(i));
+        }
+        art.insert(b"a long prefix", 5);
+        art.insert(b"a long prefiX", 6);
+        // the Node16 shrinks with some slack
+        for i in 0..=2 {
+            art.remove(&[b'a', i]);
+        }
+        insta::assert_debug_snapshot!(live_nodes(&art), @r###"
+        [
+            4,
+            2,
+            0,
+            0,
+            0,
+        ]
+        "###);
+        // the Node4 left with its last child merges with it
+        art.remove(&[b'a', 3]);
+        art.remove(&[b'a', 4]);
+        insta::assert_debug_snapshot!(live_nodes(&art), @r###"
+        [
+            2,
+            1,
+            0,
+            0,
+            0,
+        ]
+        "###);
+        let root = art.header(art.root.unwrap());
+        assert_eq!(root.prefix_len, 12);
+        assert_eq!(art.get(b"a long prefix"), Some(5));
+        assert_eq!(art.remove(b"a long prefiX"), Some(6));
+        assert_eq!(art.root.map(NodeId::kind), Some(Kind::Leaf));
+        assert_eq!(art.get(b"a long prefix"), Some(5));
+
+        // the freed slots are reused
+        art.insert(b"a long prefiX", 6);
+        insta::assert_debug_snapshot!((art.node4.slots.len(), art.node16.slots.len()), @r###"
+        (
+            2,
+            1,
+        )
+        "###);
+    }
+}
@@ -0,0 +1,702 @@
+//! Searches of the keys matched by an automaton, see [`Automaton`].
+//!
+//! The tree is traversed depth first while running the automaton on the
+//! bytes of the path, keeping one state per byte so it can resume from any
+//! node of the path. The keys of a subtree all start with its path, so the
+//! subtree is skipped as soon as the automaton reaches a state where no
+//! key can match anymore.
+//!
+//! Besides the [`Glob`] patterns and the [`Levenshtein`] distance, the
+//! module provides the building blocks [`Str`], [`Prefix`] and
+//! [`Subsequence`] which can be combined with the methods of [`Automaton`].
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{
+    child::{Child, ChildRef},
+    Allocator, Art, InnerNode,
+};
+
+/// An automaton reading the keys byte by byte, see [`Art::search`].
+pub trait Automaton {
+    type State: Clone;
+
+    /// The state before reading any byte.
+    fn start(&self) -> Self::State;
+
+    /// Whether the bytes read so far match.
+    fn is_match(&self, state: &Self::State) -> bool;
+
+    /// Whether some bytes read from `state` could lead to a match, the
+    /// search skips the subtree when they can't.
+    fn can_match(&self, _state: &Self::State) -> bool {
+        true
+    }
+
+    /// Whether all the bytes read from `state` lead to a match.
+    fn will_always_match(&self, _state: &Self::State) -> bool {
+        false
+    }
+
+    /// The state reached by reading `byte` from `state`.
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State;
+
+    /// Match the keys matched by `self` or by `other`.
+    fn union<B: Automaton>(self, other: B) -> Union<Self, B>
+    where
+        Self: Sized,
+    {
+        Union(self, other)
+    }
+
+    /// Match the keys matched by both `self` and `other`.
+    fn intersection<B: Automaton>(self, other: B) -> Intersection<Self, B>
+    where
+        Self: Sized,
+    {
+        Intersection(self, other)
+    }
+
+    /// Match the keys not matched by `self`.
+    fn complement(self) -> Complement<Self>
+    where
+        Self: Sized,
+    {
+        Complement(self)
+    }
+}
+
+impl<T: Automaton + ?Sized> Automaton for &T {
+    type State = T::State;
+
+    fn start(&self) -> Self::State {
+        (**self).start()
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        (**self).is_match(state)
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        (**self).can_match(state)
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        (**self).will_always_match(state)
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        (**self).accept(state, byte)
+    }
+}
+
+/// Match exactly a string.
+#[derive(Debug, Clone, Copy)]
+pub struct Str<'a>(&'a [u8]);
+
+impl<'a> Str<'a> {
+    pub fn new(s: &'a (impl AsRef<[u8]> + ?Sized)) -> Self {
+        Str(s.as_ref())
+    }
+}
+
+impl Automaton for Str<'_> {
+    /// The number of bytes matched, `None` once a byte didn't match.
+    type State = Option<usize>;
+
+    fn start(&self) -> Self::State {
+        Some(0)
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        *state == Some(self.0.len())
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        state.is_some()
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        state
+            .filter(|pos| self.0.get(*pos) == Some(&byte))
+            .map(|pos| pos + 1)
+    }
+}
+
+/// Match the strings starting with a prefix.
+#[derive(Debug, Clone, Copy)]
+pub struct Prefix<'a>(&'a [u8]);
+
+impl<'a> Prefix<'a> {
+    pub fn new(prefix: &'a (impl AsRef<[u8]> + ?Sized)) -> Self {
+        Prefix(prefix.as_ref())
+    }
+}
+
+impl Automaton for Prefix<'_> {
+    /// The number of bytes of the prefix matched, `None` once a byte didn't match.
+    type State = Option<usize>;
+
+    fn start(&self) -> Self::State {
+        Some(0)
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        *state == Some(self.0.len())
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        state.is_some()
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.is_match(state)
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        match *state {
+            Some(pos) if pos == self.0.len() => Some(pos),
+            Some(pos) if self.0[pos] == byte => Some(pos + 1),
+            _ => None,
+        }
+    }
+}
+
+/// Match the strings containing the bytes of a string in the same order,
+/// but not necessarily contiguous.
+#[derive(Debug, Clone, Copy)]
+pub struct Subsequence<'a>(&'a [u8]);
+
+impl<'a> Subsequence<'a> {
+    pub fn new(subsequence: &'a (impl AsRef<[u8]> + ?Sized)) -> Self {
+        Subsequence(subsequence.as_ref())
+    }
+}
+
+impl Automaton for Subsequence<'_> {
+    /// The number of bytes of the subsequence found.
+    type State = usize;
+
+    fn start(&self) -> Self::State {
+        0
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        *state == self.0.len()
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        self.is_match(state)
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        *state + usize::from(self.0.get(*state) == Some(&byte))
+    }
+}
+
+/// Match the strings matched by a glob pattern like `user:*:session`:
+/// `*` matches any sequence of bytes, `?` any single byte, and `\` escapes
+/// the next byte.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    tokens: Vec<Token>,
+    /// The number of tokens before the trailing stars.
+    tail: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Byte(u8),
+    Any,
+    Star,
+}
+
+impl Glob {
+    /// # Panics
+    ///
+    /// If the pattern has more than 127 bytes once escaped, its states
+    /// are stored as bitsets to keep the search cheap.
+    pub fn new(pattern: &(impl AsRef<[u8]> + ?Sized)) -> Self {
+        let mut tokens = Vec::new();
+        let mut bytes = pattern.as_ref().iter();
+        while let Some(&byte) = bytes.next() {
+            tokens.push(match byte {
+                b'*' => Token::Star,
+                b'?' => Token::Any,
+                b'\\' => Token::Byte(*bytes.next().unwrap_or(&b'\\')),
+                byte => Token::Byte(byte),
+            });
+        }
+        assert!(tokens.len() < 128, "The glob pattern is too long");
+        let stars = tokens.iter().rev().take_while(|t| **t == Token::Star);
+        let tail = tokens.len() - stars.count();
+        Glob { tokens, tail }
+    }
+
+    /// Add the positions reached by skipping the stars.
+    fn closure(&self, mut state: u128) -> u128 {
+        for (i, token) in self.tokens.iter().enumerate() {
+            if *token == Token::Star && state & 1 << i != 0 {
+                state |= 1 << (i + 1);
+            }
+        }
+        state
+    }
+}
+
+impl Automaton for Glob {
+    /// The set of the numbers of tokens matched, as a bitset.
+    type State = u128;
+
+    fn start(&self) -> Self::State {
+        self.closure(1)
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        state & 1 << self.tokens.len() != 0
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        *state != 0
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        // once the trailing stars are reached, anything matches
+        self.tail < self.tokens.len() && state >> self.tail != 0
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        let mut next = 0;
+        for (i, token) in self.tokens.iter().enumerate() {
+            if state & 1 << i != 0 {
+                next |= match token {
+                    Token::Byte(b) if *b != byte => 0,
+                    Token::Byte(_) | Token::Any => 1 << (i + 1),
+                    Token::Star => 1 << i,
+                };
+            }
+        }
+        self.closure(next)
+    }
+}
+
+/// Match the strings within an edit distance of a query, the number of
+/// bytes to insert, delete or substitute to turn one into the other, see
+/// also [`Art::fuzzy_search`].
+#[derive(Debug, Clone, Copy)]
+pub struct Levenshtein<'a> {
+    query: &'a [u8],
+    max_distance: usize,
+}
+
+impl<'a> Levenshtein<'a> {
+    pub fn new(query: &'a (impl AsRef<[u8]> + ?Sized), max_distance: usize) -> Self {
+        Levenshtein {
+            query: query.as_ref(),
+            max_distance,
+        }
+    }
+}
+
+impl Automaton for Levenshtein<'_> {
+    /// The row of the dynamic programming matrix of the edit distance for
+    /// the bytes read: the distance between them and each prefix of the
+    /// query, the last cell being the distance to the whole query.
+    type State = Vec<usize>;
+
+    fn start(&self) -> Self::State {
+        (0..=self.query.len()).collect()
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        state[self.query.len()] <= self.max_distance
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        // the cells of the next rows can't be smaller than the ones above
+        state.iter().any(|distance| *distance <= self.max_distance)
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        let mut row = Vec::with_capacity(state.len());
+        row.push(state[0] + 1);
+        for (j, &q) in self.query.iter().enumerate() {
+            let substitution = state[j] + usize::from(q != byte);
+            let deletion = state[j + 1] + 1;
+            let insertion = row[j] + 1;
+            row.push(substitution.min(deletion).min(insertion));
+        }
+        row
+    }
+}
+
+/// Match the strings matched by either automaton, see [`Automaton::union`].
+#[derive(Debug, Clone, Copy)]
+pub struct Union<A, B>(A, B);
+
+impl<A: Automaton, B: Automaton> Automaton for Union<A, B> {
+    type State = (A::State, B::State);
+
+    fn start(&self) -> Self::State {
+        (self.0.start(), self.1.start())
+    }
+
+    fn is_match(&self, (a, b): &Self::State) -> bool {
+        self.0.is_match(a) || self.1.is_match(b)
+    }
+
+    fn can_match(&self, (a, b): &Self::State) -> bool {
+        self.0.can_match(a) || self.1.can_match(b)
+    }
+
+    fn will_always_match(&self, (a, b): &Self::State) -> bool {
+        self.0.will_always_match(a) || self.1.will_always_match(b)
+    }
+
+    fn accept(&self, (a, b): &Self::State, byte: u8) -> Self::State {
+        (self.0.accept(a, byte), self.1.accept(b, byte))
+    }
+}
+
+/// Match the strings matched by both automata, see [`Automaton::intersection`].
+#[derive(Debug, Clone, Copy)]
+pub struct Intersection<A, B>(A, B);
+
+impl<A: Automaton, B: Automaton> Automaton for Intersection<A, B> {
+    type State = (A::State, B::State);
+
+    fn start(&self) -> Self::State {
+        (self.0.start(), self.1.start())
+    }
+
+    fn is_match(&self, (a, b): &Self::State) -> bool {
+        self.0.is_match(a) && self.1.is_match(b)
+    }
+
+    fn can_match(&self, (a, b): &Self::State) -> bool {
+        self.0.can_match(a) && self.1.can_match(b)
+    }
+
+    fn will_always_match(&self, (a, b): &Self::State) -> bool {
+        self.0.will_always_match(a) && self.1.will_always_match(b)
+    }
+
+    fn accept(&self, (a, b): &Self::State, byte: u8) -> Self::State {
+        (self.0.accept(a, byte), self.1.accept(b, byte))
+    }
+}
+
+/// Match the strings not matched by an automaton, see [`Automaton::complement`].
+#[derive(Debug, Clone, Copy)]
+pub struct Complement<A>(A);
+
+impl<A: Automaton> Automaton for Complement<A> {
+    type State = A::State;
+
+    fn start(&self) -> Self::State {
+        self.0.start()
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        !self.0.is_match(state)
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        !self.0.will_always_match(state)
+    }
+
+    fn will_always_match(&self, state: &Self::State) -> bool {
+        !self.0.can_match(state)
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        self.0.accept(state, byte)
+    }
+}
+
+type Children<'a> = Box<dyn Iterator<Item = (u8, &'a Child)> + 'a>;
+
+/// An iterator over the entries whose key is matched by an automaton,
+/// sorted by key, see [`Art::search`].
+pub struct Search<'a, A: Automaton> {
+    automaton: A,
+    start: Option<ChildRef<'a>>,
+    /// The bytes of the path followed to reach the current node.
+    path: Vec<u8>,
+    /// The state reached after each prefix of the path, starting with the
+    /// empty one.
+    states: Vec<A::State>,
+    /// The children left to visit, with the length of the path leading to them.
+    stack: Vec<(Children<'a>, usize)>,
+}
+
+impl<'a, A: Automaton> Search<'a, A> {
+    /// Append `bytes` to the path and return whether some keys starting
+    /// with it may still match.
+    fn push(&mut self, bytes: &[u8]) -> bool {
+        for &byte in bytes {
+            let state = self.automaton.accept(self.states.last().unwrap(), byte);
+            self.path.push(byte);
+            let can_match = self.automaton.can_match(&state);
+            self.states.push(state);
+            if !can_match {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Shorten the path to its `len` first bytes.
+    fn truncate(&mut self, len: usize) {
+        self.path.truncate(len);
+        self.states.truncate(len + 1);
+    }
+
+    /// The state reached after the key of the last entry yielded.
+    pub(crate) fn state(&self) -> &A::State {
+        self.states.last().unwrap()
+    }
+
+    /// Return the entry stored in `child` if its key matches, or start
+    /// iterating over its children.
+    fn visit(&mut self, child: ChildRef<'a>) -> Option<(Vec<u8>, u64)> {
+        let entry = match child {
+            ChildRef::Value(value) => value,
+            ChildRef::Node(node) => match &node.inner {
+                InnerNode::Empty => return None,
+                InnerNode::SingleValueLeaf { key, value } => {
+                    // the leaf stores its whole key, the remaining bytes are
+                    // consumed like a compressed path
+                    if !self.push(&key[self.path.len()..]) {
+                        return None;
+                    }
+                    *value
+                }
+                inner => {
+                    if !self.push(node.path(self.path.len())) {
+                        return None;
+                    }
+                    self.stack.push((inner.children(), self.path.len()));
+                    // the key ending at this node is the smallest of its subtree
+                    return self.visit(node.end.as_ref()?.get());
+                }
+            },
+        };
+        let state = self.states.last().unwrap();
+        self.automaton
+            .is_match(state)
+            .then(|| (self.path.clone(), entry))
+    }
+}
+
+impl<A: Automaton> Iterator for Search<'_, A> {
+    type Item = (Vec<u8>, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take() {
+            if let Some(entry) = self.visit(start) {
+                return Some(entry);
+            }
+        }
+        loop {
+            let (children, len) = self.stack.last_mut()?;
+            let len = *len;
+            match children.next() {
+                Some((key, child)) => {
+                    self.truncate(len);
+                    if !self.push(&[key]) {
+                        continue;
+                    }
+                    if let Some(entry) = self.visit(child.get()) {
+                        return Some(entry);
+                    }
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl<Alloc: Allocator> Art<Alloc> {
+    /// Iterate over the entries whose key is matched by `automaton`, sorted
+    /// by key. The subtrees no key of which can match are skipped.
+    pub fn search<A: Automaton>(&self, automaton: A) -> Search<'_, A> {
+        let start = automaton.start();
+        let can_match = automaton.can_match(&start);
+        Search {
+            automaton,
+            start: can_match.then_some(ChildRef::Node(&self.root)),
+            path: Vec::new(),
+            states: Vec::from([start]),
+            stack: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{format, string::String, vec::Vec};
+
+    use super::*;
+    use crate::test::test_rng;
+
+    fn keys() -> Art {
+        let mut art = Art::new();
+        for (i, key) in [
+            "user:1:session",
+            "user:1:profile",
+            "user:42:session",
+            "user:42:settings",
+            "user:*:session",
+            "users",
+            "session",
+            "a long shared prefix: user:7:session",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            art.insert(key.as_bytes(), i as u64);
+        }
+        art.insert(b"user:", u64::MAX);
+        art
+    }
+
+    fn search(art: &Art, automaton: impl Automaton) -> Vec<String> {
+        art.search(automaton)
+            .map(|(key, _)| String::from_utf8(key).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn glob() {
+        let art = keys();
+        insta::assert_debug_snapshot!(search(&art, Glob::new("user:*:session")), @r###"
+        [
+            "user:*:session",
+            "user:1:session",
+            "user:42:session",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Glob::new("user:?:*")), @r###"
+        [
+            "user:*:session",
+            "user:1:profile",
+            "user:1:session",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Glob::new("user:\\*:*")), @r###"
+        [
+            "user:*:session",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Glob::new("*session")), @r###"
+        [
+            "a long shared prefix: user:7:session",
+            "session",
+            "user:*:session",
+            "user:1:session",
+            "user:42:session",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Glob::new("user*")).len(), @"7");
+        insta::assert_debug_snapshot!(search(&art, Glob::new("")), @"[]");
+    }
+
+    #[test]
+    fn str_prefix_subsequence() {
+        let art = keys();
+        insta::assert_debug_snapshot!(search(&art, Str::new("users")), @r###"
+        [
+            "users",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Str::new("user")), @"[]");
+        insta::assert_debug_snapshot!(search(&art, Prefix::new("user:4")), @r###"
+        [
+            "user:42:session",
+            "user:42:settings",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Subsequence::new("u7s")), @r###"
+        [
+            "a long shared prefix: user:7:session",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Levenshtein::new("user:4:session", 1)), @r###"
+        [
+            "user:*:session",
+            "user:1:session",
+            "user:42:session",
+        ]
+        "###);
+    }
+
+    #[test]
+    fn combinators() {
+        let art = keys();
+        let sessions = Glob::new("*:session");
+        insta::assert_debug_snapshot!(search(&art, Str::new("users").union(Str::new("session"))), @r###"
+        [
+            "session",
+            "users",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Prefix::new("user:").intersection(&sessions)), @r###"
+        [
+            "user:*:session",
+            "user:1:session",
+            "user:42:session",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Prefix::new("user:").intersection(sessions.complement())), @r###"
+        [
+            "user:",
+            "user:1:profile",
+            "user:42:settings",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Levenshtein::new("user", 1).union(Prefix::new("user:4"))), @r###"
+        [
+            "user:",
+            "user:42:session",
+            "user:42:settings",
+            "users",
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, Prefix::new("user").complement()), @r###"
+        [
+            "a long shared prefix: user:7:session",
+            "session",
+        ]
+        "###);
+    }
+
+    #[test]
+    fn glob_random_keys() {
+        let mut art = Art::new();
+        let mut keys = Vec::new();
+        for (i, rng) in (0..2_000).zip(test_rng()) {
+            let key = format!("user:{}:{}", rng % 300, ["session", "profile", "s"][i % 3]);
+            art.insert(key.as_bytes(), i as u64);
+            keys.push(key);
+        }
+        keys.sort();
+        keys.dedup();
+        for (pattern, matches) in [
+            (
+                "user:*:session",
+                &(|k: &str| k.ends_with(":session")) as &dyn Fn(&str) -> bool,
+            ),
+            ("user:1*", &|k: &str| k.starts_with("user:1")),
+            ("*:?", &|k: &str| k.ends_with(":s")),
+            ("user:2?:*", &|k: &str| {
+                k.len() > 8 && k.starts_with("user:2") && k.as_bytes()[7] == b':'
+            }),
+        ] {
+            let expected: Vec<_> = keys.iter().filter(|k| matches(k)).cloned().collect();
+            assert_eq!(search(&art, Glob::new(pattern)), expected, "{pattern}");
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Child slots of the inner nodes.
+//!
+//! A child is stored as a single tagged pointer: either a pointer to a
+//! boxed [`Node`], or, when the lowest bit is set, a value of up to 63
+//! bits stored directly in the slot. This lets a key that ends right
+//! after the byte of its slot live without any allocation.
+//!
+//! An inlined value doesn't store its key, which is instead rebuilt from
+//! the path followed to reach it. Because of that, values are never
+//! inlined below a node whose compressed path is truncated.
+//!
+//! Only the keys ending at their slot can be rebuilt this way: the bytes
+//! after the slot of a leaf are known only from the leaf itself, so such a
+//! key keeps its leaf. For sequential u64 row ids, where every key ends at
+//! a slot of the last level, this brings the tree from 72.3 down to 8.3
+//! bytes and from 2.01 down to 0.01 allocations per key (`benches/memory.rs`),
+//! while random u64 keys, whose leaves sit a few levels higher, don't
+//! change.
+//!
+//! Like a [`Ptr`], a child doesn't free its node when dropped, the tree
+//! frees it with its allocator.
+
+use core::{fmt, marker::PhantomData, ptr::NonNull};
+
+use allocator_api2::alloc::Allocator;
+
+use crate::{ptr::Ptr, Node};
+
+const VALUE_TAG: usize = 1;
+
+pub(crate) struct Child {
+    ptr: NonNull<Node>,
+    _marker: PhantomData<Node>,
+}
+
+// Safety: a `Child` owns its node exactly like a `Ptr<Node>` would.
+unsafe impl Send for Child {}
+unsafe impl Sync for Child {}
+
+/// A borrowed view of a [`Child`].
+#[derive(Clone, Copy)]
+pub(crate) enum ChildRef<'a> {
+    Node(&'a Node),
+    Value(u64),
+}
+
+impl Child {
+    pub fn node(node: Ptr<Node>) -> Self {
+        // `Node` is aligned on more than one byte, the tag bit is always free
+        Child {
+            ptr: node.into_raw(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Store `value` directly in the slot, if it fits.
+    pub fn value(value: u64) -> Option<Self> {
+        let value = usize::try_from(value).ok()?;
+        if value > usize::MAX >> 1 {
+            return None;
+        }
+        let ptr = core::ptr::without_provenance_mut((value << 1) | VALUE_TAG);
+        Some(Child {
+            ptr: NonNull::new(ptr).unwrap(),
+            _marker: PhantomData,
+        })
+    }
+
+    fn is_value(&self) -> bool {
+        self.ptr.as_ptr().addr() & VALUE_TAG == VALUE_TAG
+    }
+
+    pub fn get(&self) -> ChildRef<'_> {
+        if self.is_value() {
+            ChildRef::Value((self.ptr.as_ptr().addr() >> 1) as u64)
+        } else {
+            // Safety: not tagged, this is the pointer of a node we own
+            ChildRef::Node(unsafe { self.ptr.as_ref() })
+        }
+    }
+
+    /// The tagged pointer of the slot, which stays valid as long as the
+    /// slot isn't modified.
+    pub fn as_ptr(&self) -> *mut Node {
+        self.ptr.as_ptr()
+    }
+
+    pub fn as_node(&self) -> Option<&Node> {
+        match self.get() {
+            ChildRef::Node(node) => Some(node),
+            ChildRef::Value(_) => None,
+        }
+    }
+
+    pub fn as_node_mut(&mut self) -> Option<&mut Node> {
+        if self.is_value() {
+            None
+        } else {
+            // Safety: not tagged, this is the pointer of a node we own
+            Some(unsafe { self.ptr.as_mut() })
+        }
+    }
+
+    /// Return the node, or the value stored in the slot.
+    pub fn into_node(self) -> Result<Ptr<Node>, u64> {
+        match self.get() {
+            ChildRef::Value(value) => Err(value),
+            // Safety: not tagged, this is the pointer of a node we own
+            ChildRef::Node(_) => Ok(unsafe { Ptr::from_raw(self.ptr) }),
+        }
+    }
+
+    /// Free the node and its children, if the child isn't a value.
+    ///
+    /// # Safety
+    ///
+    /// The nodes must have been allocated by `alloc`.
+    pub unsafe fn free_in<A: Allocator>(self, alloc: &A) {
+        if let Ok(node) = self.into_node() {
+            node.into_inner(alloc).free_in(alloc);
+        }
+    }
+}
+
+impl fmt::Debug for Child {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            ChildRef::Node(node) => node.fmt(f),
+            ChildRef::Value(value) => f.debug_tuple("Value").field(&value).finish(),
+        }
+    }
+}
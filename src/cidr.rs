@@ -0,0 +1,660 @@
+//! A routing table of IPv4 and IPv6 prefixes, see [`CidrTable`].
+//!
+//! The length of a prefix is counted in bits while the nodes branch on
+//! whole bytes, so the routes are stored in two parts of the tree, told
+//! apart by the first byte of their keys:
+//!
+//! - the exact key of a route is the family of its address with the
+//!   [`EXACT`] bit set, the bytes of the address with the host bits cleared
+//!   and the length. It stores the value of the route.
+//! - a route of `8 * k + r` bits, with `0 < r <= 8`, covers `2^(8 - r)`
+//!   values of the byte `k` of the addresses. It is expanded in a lookup
+//!   entry for each of them, whose key is the family and the `k + 1` first
+//!   bytes of the address, and which stores the length and the value of
+//!   the longest route of this level covering it, see [`pack`]. The
+//!   default route `/0` has a single entry, whose key is the family.
+//!
+//! The lookup keys of the routes covering an address are then prefixes of
+//! the address, so a longest prefix match is a walk down at most one node
+//! per byte of the address, see [`Art::all_prefixes_of`].
+
+use core::{
+    iter::Chain,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::Range,
+};
+
+use crate::{prefixes::PrefixWalk, Allocator, Art, ArtError, Global, Iter};
+
+/// The bit set in the first byte of the exact keys of the routes.
+const EXACT: u8 = 0x80;
+
+/// A route of a [`CidrTable`]: the value stored for the `len` first bits
+/// of `addr`, whose other bits are always zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub addr: IpAddr,
+    pub len: u8,
+    pub value: u64,
+}
+
+/// A routing table mapping IPv4 and IPv6 prefixes to a `u64`, e.g. the
+/// index of a next hop.
+pub struct CidrTable<A: Allocator = Global> {
+    art: Art<A>,
+}
+
+impl CidrTable {
+    pub fn new() -> CidrTable {
+        CidrTable::new_in(Global)
+    }
+}
+
+impl Default for CidrTable {
+    fn default() -> Self {
+        CidrTable::new()
+    }
+}
+
+impl<A: Allocator> CidrTable<A> {
+    /// Create a table whose nodes are allocated by `alloc`, see [`Art::new_in`].
+    pub fn new_in(alloc: A) -> Self {
+        CidrTable {
+            art: Art::new_in(alloc),
+        }
+    }
+
+    /// Insert the route to `addr/len` and return its previous value, or
+    /// leave the table untouched if the allocator fails. The bits of `addr`
+    /// after the `len` first are ignored.
+    ///
+    /// # Panics
+    ///
+    /// If `len` is longer than the address.
+    pub fn try_insert(
+        &mut self,
+        addr: impl Into<IpAddr>,
+        len: u8,
+        value: u64,
+    ) -> Result<Option<u64>, ArtError> {
+        let key = Key::route(addr.into(), len);
+        let addr = key.addr();
+        let old_value = self.art.try_insert(&key, value)?;
+        if old_value.is_some() {
+            for entry in lookup_keys(addr, len) {
+                if self.entry_len(&entry) == Some(len) {
+                    self.refresh(&entry);
+                }
+            }
+            return Ok(old_value);
+        }
+        for (i, entry) in lookup_keys(addr, len).enumerate() {
+            if self.entry_len(&entry) >= Some(len) {
+                continue;
+            }
+            if let Err(error) = self.art.try_insert(&entry, pack(len, value)) {
+                self.art.remove(&key);
+                for entry in lookup_keys(addr, len).take(i) {
+                    self.refresh(&entry);
+                }
+                return Err(error);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Insert the route to `addr/len` and return its previous value. The
+    /// bits of `addr` after the `len` first are ignored.
+    ///
+    /// # Panics
+    ///
+    /// If `len` is longer than the address, or if the allocator fails.
+    pub fn insert(&mut self, addr: impl Into<IpAddr>, len: u8, value: u64) -> Option<u64> {
+        self.try_insert(addr, len, value).expect("Out of memory")
+    }
+
+    /// Return the value of the route to exactly `addr/len`.
+    ///
+    /// # Panics
+    ///
+    /// If `len` is longer than the address.
+    pub fn get(&self, addr: impl Into<IpAddr>, len: u8) -> Option<u64> {
+        self.art.get(&Key::route(addr.into(), len))
+    }
+
+    /// Remove the route to `addr/len` and return its value.
+    ///
+    /// # Panics
+    ///
+    /// If `len` is longer than the address.
+    pub fn remove(&mut self, addr: impl Into<IpAddr>, len: u8) -> Option<u64> {
+        let key = Key::route(addr.into(), len);
+        let value = self.art.remove(&key)?;
+        for entry in lookup_keys(key.addr(), len) {
+            if self.entry_len(&entry) == Some(len) {
+                self.refresh(&entry);
+            }
+        }
+        Some(value)
+    }
+
+    /// Return the length of the route stored in the lookup entry `entry`.
+    fn entry_len(&self, entry: &Key) -> Option<u8> {
+        Some(unpack(self.art.get(entry)?).0)
+    }
+
+    /// Store in the lookup entry `entry` the longest route of its level
+    /// covering it, or remove it if there is none left.
+    ///
+    /// The entry must already be in the tree, replacing its value then
+    /// doesn't allocate.
+    fn refresh(&mut self, entry: &Key) {
+        let addr = addr_of(entry[0], &entry[1..]);
+        let best = levels(entry.len()).rev().find_map(|len| {
+            let value = self.art.get(&Key::route(addr, len))?;
+            Some((len, value))
+        });
+        match best {
+            Some((len, value)) => {
+                let old_value = self.art.try_insert(entry, pack(len, value));
+                debug_assert!(matches!(old_value, Ok(Some(_))));
+            }
+            None => {
+                self.art.remove(entry);
+            }
+        }
+    }
+
+    /// Return the most specific route covering `addr`.
+    pub fn longest_match(&self, addr: impl Into<IpAddr>) -> Option<Route> {
+        let addr = addr.into();
+        let (_, entry) = self.art.longest_prefix_match(&Key::lookup(addr))?;
+        let (len, value) = unpack(entry);
+        let key = Key::route(addr, len);
+        let value = value.or_else(|| self.art.get(&key));
+        Some(Route {
+            addr: key.addr(),
+            len,
+            value: value.expect("Lookup entry without a route"),
+        })
+    }
+
+    /// Iterate over the routes covering `addr`, from the least specific to
+    /// the most specific.
+    pub fn matches(&self, addr: impl Into<IpAddr>) -> Matches<'_, A> {
+        let addr = addr.into();
+        Matches {
+            art: &self.art,
+            addr,
+            key: Key::lookup(addr),
+            walk: PrefixWalk::new(&self.art.root),
+            lens: 0..0,
+        }
+    }
+
+    /// Iterate over all the routes, the IPv4 ones first, each sorted by
+    /// address and then by length.
+    pub fn iter(&self) -> Routes<'_> {
+        let v4 = self.art.prefix_iter(&[EXACT | 4]);
+        let v6 = self.art.prefix_iter(&[EXACT | 6]);
+        Routes(v4.chain(v6))
+    }
+}
+
+/// An iterator over the routes covering an address, see [`CidrTable::matches`].
+pub struct Matches<'a, A: Allocator = Global> {
+    art: &'a Art<A>,
+    addr: IpAddr,
+    key: Key,
+    walk: PrefixWalk<'a>,
+    /// The lengths left to look up in the level of the last entry found.
+    lens: Range<u8>,
+}
+
+impl<A: Allocator> Iterator for Matches<'_, A> {
+    type Item = Route;
+
+    fn next(&mut self) -> Option<Route> {
+        loop {
+            let Some(len) = self.lens.next() else {
+                // the entry gives the longest route of its level covering
+                // the address, the shorter ones may cover it too
+                let (depth, entry) = self.walk.next(&self.key)?;
+                self.lens = levels(depth).start..unpack(entry).0 + 1;
+                continue;
+            };
+            let key = Key::route(self.addr, len);
+            if let Some(value) = self.art.get(&key) {
+                return Some(Route {
+                    addr: key.addr(),
+                    len,
+                    value,
+                });
+            }
+        }
+    }
+}
+
+/// An iterator over all the routes of a table, see [`CidrTable::iter`].
+pub struct Routes<'a>(Chain<Iter<'a>, Iter<'a>>);
+
+impl Iterator for Routes<'_> {
+    type Item = Route;
+
+    fn next(&mut self) -> Option<Route> {
+        let (key, value) = self.0.next()?;
+        let (len, key) = key.split_last().unwrap();
+        Some(Route {
+            addr: addr_of(key[0] & !EXACT, &key[1..]),
+            len: *len,
+            value,
+        })
+    }
+}
+
+/// A key of the tree, either the exact key of a route or a lookup key.
+#[derive(Clone, Copy)]
+struct Key {
+    bytes: [u8; 1 + 16 + 1],
+    len: usize,
+}
+
+impl Key {
+    /// Return the key of the lookups of `addr`: its family followed by its
+    /// bytes. The lookup entries of the routes covering it are prefixes of
+    /// this key.
+    fn lookup(addr: IpAddr) -> Key {
+        let mut bytes = [0; 1 + 16 + 1];
+        let len = match addr {
+            IpAddr::V4(addr) => {
+                bytes[0] = 4;
+                bytes[1..5].copy_from_slice(&addr.octets());
+                5
+            }
+            IpAddr::V6(addr) => {
+                bytes[0] = 6;
+                bytes[1..17].copy_from_slice(&addr.octets());
+                17
+            }
+        };
+        Key { bytes, len }
+    }
+
+    /// Return the exact key of the route `addr/len`, the host bits of
+    /// `addr` are ignored.
+    fn route(addr: IpAddr, len: u8) -> Key {
+        assert!(
+            len <= max_len(addr),
+            "The prefix length {len} is too long for {addr}"
+        );
+        let mut key = Key::lookup(mask(addr, len));
+        key.bytes[0] |= EXACT;
+        key.bytes[key.len] = len;
+        key.len += 1;
+        key
+    }
+
+    /// Return the address of an exact key.
+    fn addr(&self) -> IpAddr {
+        addr_of(self.bytes[0] & !EXACT, &self.bytes[1..self.len - 1])
+    }
+}
+
+impl core::ops::Deref for Key {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Iterate over the lookup keys of the route `addr/len`, whose host bits
+/// are cleared: the family and the bytes of `addr` up to the one where the
+/// route ends, which takes all the values covered by the route.
+fn lookup_keys(addr: IpAddr, len: u8) -> impl Iterator<Item = Key> {
+    let mut key = Key::lookup(addr);
+    let len = usize::from(len);
+    // the byte the route ends in, the family byte for the default route
+    let last = len.div_ceil(8);
+    key.len = last + 1;
+    (0..1_u16 << (8 * last - len)).map(move |i| {
+        let mut key = key;
+        key.bytes[last] |= i as u8;
+        key
+    })
+}
+
+/// Return the value of the lookup entry of the route of `len` bits storing
+/// `value`: the length in the lowest byte and, unless it is too large, the
+/// value plus one above it, so most lookups don't need the exact key of
+/// the route. It is kept below 2^63 so it fits in a slot like the length
+/// alone would, and replacing it never allocates.
+fn pack(len: u8, value: u64) -> u64 {
+    match value.checked_add(1) {
+        Some(value) if value < 1 << 55 => value << 8 | u64::from(len),
+        _ => u64::from(len),
+    }
+}
+
+/// Return the length of the route of a lookup entry, and its value unless
+/// it has to be read in the exact key of the route, see [`pack`].
+fn unpack(entry: u64) -> (u8, Option<u64>) {
+    (entry as u8, (entry >> 8).checked_sub(1))
+}
+
+/// Return the lengths of the routes whose lookup entries have keys of
+/// `depth` bytes, the default route is alone in its level.
+fn levels(depth: usize) -> Range<u8> {
+    match depth {
+        1 => 0..1,
+        depth => {
+            let last = 8 * (depth - 1) as u8;
+            last - 7..last + 1
+        }
+    }
+}
+
+/// Return the address of the `family` whose first bytes are `bytes`.
+fn addr_of(family: u8, bytes: &[u8]) -> IpAddr {
+    let mut octets = [0; 16];
+    octets[..bytes.len()].copy_from_slice(bytes);
+    match family {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(&octets[..4]).unwrap())),
+        _ => IpAddr::V6(Ipv6Addr::from(octets)),
+    }
+}
+
+fn max_len(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Clear the bits of `addr` after the `len` first.
+fn mask(addr: IpAddr, len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(addr) & mask))
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::test::test_rng;
+
+    fn v4(s: &str) -> Ipv4Addr {
+        s.parse().unwrap()
+    }
+
+    fn v6(s: &str) -> Ipv6Addr {
+        s.parse().unwrap()
+    }
+
+    fn table() -> CidrTable {
+        let mut table = CidrTable::new();
+        table.insert(v4("0.0.0.0"), 0, 0);
+        table.insert(v4("10.0.0.0"), 8, 1);
+        table.insert(v4("10.16.0.0"), 12, 2);
+        table.insert(v4("10.20.16.0"), 20, 3);
+        table.insert(v4("10.20.17.0"), 24, 4);
+        table.insert(v4("10.20.17.42"), 32, u64::MAX);
+        // the host bits are ignored
+        table.insert(v4("192.168.1.1"), 16, 5);
+        table.insert(v6("2001:db8::"), 32, 6);
+        table.insert(v6("2001:db8:1234::"), 46, 7);
+        table
+    }
+
+    #[test]
+    fn longest_match() {
+        let table = table();
+        insta::assert_debug_snapshot!(table.longest_match(v4("10.20.17.1")), @r###"
+        Some(
+            Route {
+                addr: 10.20.17.0,
+                len: 24,
+                value: 4,
+            },
+        )
+        "###);
+        insta::assert_debug_snapshot!(table.longest_match(v4("10.20.31.255")), @r###"
+        Some(
+            Route {
+                addr: 10.20.16.0,
+                len: 20,
+                value: 3,
+            },
+        )
+        "###);
+        insta::assert_debug_snapshot!(table.longest_match(v4("10.20.32.0")), @r###"
+        Some(
+            Route {
+                addr: 10.16.0.0,
+                len: 12,
+                value: 2,
+            },
+        )
+        "###);
+        insta::assert_debug_snapshot!(table.longest_match(v4("8.8.8.8")), @r###"
+        Some(
+            Route {
+                addr: 0.0.0.0,
+                len: 0,
+                value: 0,
+            },
+        )
+        "###);
+        insta::assert_debug_snapshot!(table.longest_match(v6("2001:db8:1237::1")), @r###"
+        Some(
+            Route {
+                addr: 2001:db8:1234::,
+                len: 46,
+                value: 7,
+            },
+        )
+        "###);
+        insta::assert_debug_snapshot!(table.longest_match(v6("2001:db8:1238::1")), @r###"
+        Some(
+            Route {
+                addr: 2001:db8::,
+                len: 32,
+                value: 6,
+            },
+        )
+        "###);
+        // the default route only covers the IPv4 addresses
+        insta::assert_debug_snapshot!(table.longest_match(v6("::1")), @"None");
+    }
+
+    #[test]
+    fn matches() {
+        let table = table();
+        let lens = |addr: Ipv4Addr| {
+            table
+                .matches(addr)
+                .map(|route| route.len)
+                .collect::<Vec<_>>()
+        };
+        insta::assert_debug_snapshot!(lens(v4("10.20.17.42")), @r###"
+        [
+            0,
+            8,
+            12,
+            20,
+            24,
+            32,
+        ]
+        "###);
+        insta::assert_debug_snapshot!(lens(v4("10.20.17.43")), @r###"
+        [
+            0,
+            8,
+            12,
+            20,
+            24,
+        ]
+        "###);
+        insta::assert_debug_snapshot!(lens(v4("10.31.0.0")), @r###"
+        [
+            0,
+            8,
+            12,
+        ]
+        "###);
+        insta::assert_debug_snapshot!(lens(v4("11.0.0.0")), @r###"
+        [
+            0,
+        ]
+        "###);
+    }
+
+    #[test]
+    fn iter_and_remove() {
+        let mut table = table();
+        insta::assert_debug_snapshot!(table.iter().map(|route| (route.addr, route.len)).collect::<Vec<_>>(), @r###"
+        [
+            (
+                0.0.0.0,
+                0,
+            ),
+            (
+                10.0.0.0,
+                8,
+            ),
+            (
+                10.16.0.0,
+                12,
+            ),
+            (
+                10.20.16.0,
+                20,
+            ),
+            (
+                10.20.17.0,
+                24,
+            ),
+            (
+                10.20.17.42,
+                32,
+            ),
+            (
+                192.168.0.0,
+                16,
+            ),
+            (
+                2001:db8::,
+                32,
+            ),
+            (
+                2001:db8:1234::,
+                46,
+            ),
+        ]
+        "###);
+        assert_eq!(table.get(v4("192.168.0.0"), 16), Some(5));
+        assert_eq!(table.get(v4("192.168.0.0"), 17), None);
+        // too large to be stored with the lookup entries
+        assert_eq!(
+            table.longest_match(v4("10.20.17.42")).unwrap().value,
+            u64::MAX
+        );
+        assert_eq!(table.insert(v4("10.20.17.0"), 24, 8), Some(4));
+        assert_eq!(table.longest_match(v4("10.20.17.1")).unwrap().value, 8);
+
+        assert_eq!(table.remove(v4("10.20.16.0"), 20), Some(3));
+        assert_eq!(table.remove(v4("10.20.16.0"), 20), None);
+        assert_eq!(table.longest_match(v4("10.20.31.255")).unwrap().len, 12);
+        assert_eq!(table.longest_match(v4("10.20.17.1")).unwrap().len, 24);
+        assert_eq!(table.remove(v4("0.0.0.0"), 0), Some(0));
+        assert_eq!(table.longest_match(v4("8.8.8.8")), None);
+    }
+
+    #[test]
+    #[should_panic = "The prefix length 33 is too long for 10.0.0.0"]
+    fn too_long() {
+        CidrTable::new().insert(v4("10.0.0.0"), 33, 0);
+    }
+
+    #[test]
+    fn random_routes() {
+        let mut table = CidrTable::new();
+        let mut routes = Vec::new();
+        let mut rng = test_rng();
+        let mut next = || rng.next().unwrap();
+        for i in 0..2_000 {
+            // few distinct high bits so the routes often cover each other
+            let addr = (next() as u32) & 0xf0ff_ffff;
+            let len = (next() % 33) as u8;
+            let addr = mask(IpAddr::V4(addr.into()), len);
+            table.insert(addr, len, i);
+            routes.retain(|route: &Route| (route.addr, route.len) != (addr, len));
+            routes.push(Route {
+                addr,
+                len,
+                value: i,
+            });
+        }
+        for (i, route) in routes.clone().iter().enumerate() {
+            if i % 3 == 0 {
+                assert_eq!(table.remove(route.addr, route.len), Some(route.value));
+                routes.retain(|r| r != route);
+            }
+        }
+        for _ in 0..2_000 {
+            let addr = IpAddr::V4(((next() as u32) & 0xf0ff_ffff).into());
+            let mut expected: Vec<_> = routes
+                .iter()
+                .filter(|route| mask(addr, route.len) == route.addr)
+                .copied()
+                .collect();
+            expected.sort_by_key(|route| route.len);
+            assert_eq!(table.matches(addr).collect::<Vec<_>>(), expected, "{addr}");
+            assert_eq!(table.longest_match(addr), expected.last().copied());
+        }
+    }
+
+    #[test]
+    fn out_of_memory() {
+        for budget in (0..100_000).step_by(3_989) {
+            let mut table = CidrTable::new_in(crate::test::Budget {
+                used: std::rc::Rc::default(),
+                budget,
+            });
+            let mut routes = std::collections::BTreeSet::new();
+            for (i, rng) in (0..200).zip(test_rng()) {
+                let addr = IpAddr::V4(((rng >> 32) as u32 & 0xf0ff_ffff).into());
+                // the routes ending early in a byte have the most lookup entries
+                let len = (8 + rng % 17) as u8;
+                let before: Vec<_> = table.art.iter().collect();
+                match table.try_insert(addr, len, i) {
+                    Ok(_) => {
+                        routes.insert((mask(addr, len), len));
+                    }
+                    Err(error) => {
+                        assert_eq!(error, ArtError::OutOfMemory);
+                        assert_eq!(table.art.iter().collect::<Vec<_>>(), before);
+                        break;
+                    }
+                }
+            }
+            for (addr, len) in routes {
+                assert_eq!(
+                    table.longest_match(addr).map(|route| route.addr),
+                    Some(addr)
+                );
+                assert!(table.remove(addr, len).is_some());
+            }
+            assert_eq!(table.art.iter().count(), 0);
+        }
+    }
+}
@@ -0,0 +1,1009 @@
+//! A variant of the [`Art`](crate::Art) that can be shared between threads,
+//! synchronized with Optimistic Lock Coupling as described in
+//! [The ART of Practical Synchronization][artsync].
+//!
+//! Every node holds a version lock. Readers never write to the nodes: they
+//! read the version of a node, read its content and check that the version
+//! didn't change before using what they read, restarting from the root
+//! otherwise. Writers take the same path and upgrade to a write lock only
+//! the nodes they modify: the node receiving a child, or the node and its
+//! parent when the node is replaced by a bigger one or by a split of its
+//! compressed path.
+//!
+//! Since readers may still be looking at a node after it was replaced, the
+//! replaced nodes and the removed leaves are freed by the epoch-based
+//! reclamation of the [`epoch`](crate::epoch) module once no operation can
+//! reach them anymore. The compressed path of a node is never modified, a
+//! split replaces the node with a copy so the path can be read without
+//! synchronization.
+//!
+//! [artsync]: https://db.in.tum.de/~leis/papers/artsync.pdf
+
+use alloc::boxed::Box;
+use core::{
+    hint::spin_loop,
+    ops::Deref,
+    sync::atomic::{
+        fence, AtomicU16, AtomicU64, AtomicU8, AtomicUsize,
+        Ordering::{AcqRel, Acquire, Relaxed, Release},
+    },
+};
+
+use crate::{
+    common_len,
+    epoch::{Collector, Guard},
+};
+
+/// The error of an optimistic operation that read a node modified
+/// concurrently and must start over from the root.
+#[derive(Debug)]
+pub(crate) struct Restart;
+
+const LOCKED: u64 = 0b10;
+const OBSOLETE: u64 = 0b01;
+
+/// A lock whose version is incremented by every writer, bit 1 is set while
+/// a writer holds it and bit 0 once the node was replaced.
+#[derive(Debug, Default)]
+pub(crate) struct VersionLock(AtomicU64);
+
+impl VersionLock {
+    /// Wait for the writer to release the lock and return the version.
+    pub fn read(&self) -> Result<u64, Restart> {
+        loop {
+            let version = self.0.load(Acquire);
+            if version & OBSOLETE != 0 {
+                return Err(Restart);
+            }
+            if version & LOCKED == 0 {
+                return Ok(version);
+            }
+            spin_loop();
+        }
+    }
+
+    /// Check that nothing was written since `version` was read.
+    pub fn check(&self, version: u64) -> Result<(), Restart> {
+        fence(Acquire);
+        match self.0.load(Relaxed) == version {
+            true => Ok(()),
+            false => Err(Restart),
+        }
+    }
+
+    /// Take the write lock if nothing was written since `version` was read.
+    pub fn upgrade(&self, version: u64) -> Result<(), Restart> {
+        match self
+            .0
+            .compare_exchange(version, version + LOCKED, Acquire, Relaxed)
+        {
+            Ok(_) => {
+                fence(Release);
+                Ok(())
+            }
+            Err(_) => Err(Restart),
+        }
+    }
+
+    /// Wait for the lock and take it, unless the node was replaced.
+    pub fn lock(&self) -> Result<(), Restart> {
+        loop {
+            let version = self.read()?;
+            if self.upgrade(version).is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn unlock(&self) {
+        self.0.fetch_add(LOCKED, Release);
+    }
+
+    /// Release the lock of a node that was replaced in its parent.
+    pub fn unlock_obsolete(&self) {
+        self.0.fetch_add(LOCKED + OBSOLETE, Release);
+    }
+}
+
+/// A leaf stores its whole key and its value, a new value replaces the
+/// leaf so a reference to the value stays valid while the leaf is reachable.
+pub(crate) struct Leaf {
+    pub key: Box<[u8]>,
+    pub value: u64,
+}
+
+impl Leaf {
+    pub fn new_raw(key: &[u8], value: u64) -> usize {
+        let leaf = Box::new(Leaf {
+            key: key.into(),
+            value,
+        });
+        Box::into_raw(leaf) as usize | 1
+    }
+
+    /// # Safety
+    /// `raw` must point to a leaf owned by the caller.
+    pub unsafe fn free(raw: usize) {
+        drop(Box::from_raw((raw & !1) as *mut Leaf));
+    }
+}
+
+/// The content of a child slot: a null pointer, a pointer to a leaf with
+/// its lowest bit set or a pointer to a node.
+#[derive(Clone, Copy)]
+pub(crate) enum Slot<'a, N> {
+    Empty,
+    Leaf(&'a Leaf),
+    Node(&'a N),
+}
+
+impl<N> Slot<'_, N> {
+    /// # Safety
+    /// `raw` must have been read from a child slot while pinning a guard
+    /// that lives for `'a`.
+    pub unsafe fn from_raw<'a>(raw: usize) -> Slot<'a, N> {
+        match raw {
+            0 => Slot::Empty,
+            raw if raw & 1 == 1 => Slot::Leaf(&*((raw & !1) as *const Leaf)),
+            raw => Slot::Node(&*(raw as *const N)),
+        }
+    }
+}
+
+/// The children of a Node4 or a Node16, sorted by key.
+struct Sorted<const N: usize> {
+    count: AtomicU8,
+    keys: [AtomicU8; N],
+    children: [AtomicUsize; N],
+}
+
+impl<const N: usize> Default for Sorted<N> {
+    fn default() -> Self {
+        Sorted {
+            count: AtomicU8::new(0),
+            keys: core::array::from_fn(|_| AtomicU8::new(0)),
+            children: core::array::from_fn(|_| AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<const N: usize> Sorted<N> {
+    fn len(&self) -> usize {
+        // a torn read is caught by the version check
+        (self.count.load(Relaxed) as usize).min(N)
+    }
+
+    fn position(&self, byte: u8) -> Option<usize> {
+        (0..self.len()).find(|i| self.keys[*i].load(Relaxed) == byte)
+    }
+
+    fn find_child(&self, byte: u8) -> usize {
+        self.position(byte)
+            .map_or(0, |i| self.children[i].load(Acquire))
+    }
+
+    fn insert(&self, byte: u8, child: usize) {
+        let len = self.len();
+        let pos = (0..len)
+            .find(|i| self.keys[*i].load(Relaxed) > byte)
+            .unwrap_or(len);
+        for i in (pos..len).rev() {
+            self.keys[i + 1].store(self.keys[i].load(Relaxed), Relaxed);
+            self.children[i + 1].store(self.children[i].load(Relaxed), Release);
+        }
+        self.keys[pos].store(byte, Relaxed);
+        self.children[pos].store(child, Release);
+        self.count.store(len as u8 + 1, Relaxed);
+    }
+
+    fn change(&self, byte: u8, child: usize) {
+        if let Some(i) = self.position(byte) {
+            self.children[i].store(child, Release);
+        }
+    }
+
+    fn remove(&self, byte: u8) {
+        let len = self.len();
+        if let Some(pos) = self.position(byte) {
+            for i in pos..len - 1 {
+                self.keys[i].store(self.keys[i + 1].load(Relaxed), Relaxed);
+                self.children[i].store(self.children[i + 1].load(Relaxed), Release);
+            }
+            self.children[len - 1].store(0, Release);
+            self.count.store(len as u8 - 1, Relaxed);
+        }
+    }
+
+    fn for_each(&self, mut f: impl FnMut(u8, usize)) {
+        for i in 0..self.len() {
+            f(self.keys[i].load(Relaxed), self.children[i].load(Acquire));
+        }
+    }
+}
+
+/// Up to 48 children, indexed by a table of 256 bytes.
+struct Indexed {
+    count: AtomicU8,
+    /// The slot of the child of each byte plus one, zero when there is none.
+    index: [AtomicU8; 256],
+    children: [AtomicUsize; 48],
+}
+
+impl Default for Indexed {
+    fn default() -> Self {
+        Indexed {
+            count: AtomicU8::new(0),
+            index: core::array::from_fn(|_| AtomicU8::new(0)),
+            children: core::array::from_fn(|_| AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl Indexed {
+    fn slot(&self, byte: u8) -> Option<usize> {
+        match self.index[byte as usize].load(Relaxed) {
+            0 => None,
+            // a torn read is caught by the version check
+            slot => Some((slot as usize - 1).min(47)),
+        }
+    }
+
+    fn find_child(&self, byte: u8) -> usize {
+        self.slot(byte)
+            .map_or(0, |slot| self.children[slot].load(Acquire))
+    }
+
+    fn insert(&self, byte: u8, child: usize) {
+        // the slots of the removed children are reused
+        let slot = (0..48)
+            .find(|i| self.children[*i].load(Relaxed) == 0)
+            .expect("Inserting in a full Node48");
+        self.children[slot].store(child, Release);
+        self.index[byte as usize].store(slot as u8 + 1, Relaxed);
+        self.count.fetch_add(1, Relaxed);
+    }
+
+    fn change(&self, byte: u8, child: usize) {
+        if let Some(slot) = self.slot(byte) {
+            self.children[slot].store(child, Release);
+        }
+    }
+
+    fn remove(&self, byte: u8) {
+        if let Some(slot) = self.slot(byte) {
+            self.index[byte as usize].store(0, Relaxed);
+            self.children[slot].store(0, Release);
+            self.count.fetch_sub(1, Relaxed);
+        }
+    }
+
+    fn for_each(&self, mut f: impl FnMut(u8, usize)) {
+        for byte in 0..=255 {
+            if let Some(slot) = self.slot(byte) {
+                f(byte, self.children[slot].load(Acquire));
+            }
+        }
+    }
+}
+
+/// One child for each byte.
+struct Direct {
+    count: AtomicU16,
+    children: [AtomicUsize; 256],
+}
+
+impl Default for Direct {
+    fn default() -> Self {
+        Direct {
+            count: AtomicU16::new(0),
+            children: core::array::from_fn(|_| AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl Direct {
+    fn find_child(&self, byte: u8) -> usize {
+        self.children[byte as usize].load(Acquire)
+    }
+
+    fn insert(&self, byte: u8, child: usize) {
+        self.children[byte as usize].store(child, Release);
+        self.count.fetch_add(1, Relaxed);
+    }
+
+    fn change(&self, byte: u8, child: usize) {
+        self.children[byte as usize].store(child, Release);
+    }
+
+    fn remove(&self, byte: u8) {
+        self.children[byte as usize].store(0, Release);
+        self.count.fetch_sub(1, Relaxed);
+    }
+
+    fn for_each(&self, mut f: impl FnMut(u8, usize)) {
+        for byte in 0..=255 {
+            match self.children[byte as usize].load(Acquire) {
+                0 => (),
+                child => f(byte, child),
+            }
+        }
+    }
+}
+
+enum Inner {
+    Node4(Sorted<4>),
+    Node16(Sorted<16>),
+    Node48(Box<Indexed>),
+    Node256(Box<Direct>),
+}
+
+impl Inner {
+    fn find_child(&self, byte: u8) -> usize {
+        match self {
+            Inner::Node4(node) => node.find_child(byte),
+            Inner::Node16(node) => node.find_child(byte),
+            Inner::Node48(node) => node.find_child(byte),
+            Inner::Node256(node) => node.find_child(byte),
+        }
+    }
+
+    /// The number of children, only exact while holding the lock or after
+    /// a successful version check.
+    fn len(&self) -> usize {
+        match self {
+            Inner::Node4(node) => node.len(),
+            Inner::Node16(node) => node.len(),
+            Inner::Node48(node) => node.count.load(Relaxed) as usize,
+            Inner::Node256(node) => node.count.load(Relaxed) as usize,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        match self {
+            Inner::Node4(_) => self.len() >= 4,
+            Inner::Node16(_) => self.len() >= 16,
+            Inner::Node48(_) => self.len() >= 48,
+            Inner::Node256(_) => false,
+        }
+    }
+
+    /// Replace the child of a byte.
+    fn change(&self, byte: u8, child: usize) {
+        match self {
+            Inner::Node4(node) => node.change(byte, child),
+            Inner::Node16(node) => node.change(byte, child),
+            Inner::Node48(node) => node.change(byte, child),
+            Inner::Node256(node) => node.change(byte, child),
+        }
+    }
+
+    fn remove(&self, byte: u8) {
+        match self {
+            Inner::Node4(node) => node.remove(byte),
+            Inner::Node16(node) => node.remove(byte),
+            Inner::Node48(node) => node.remove(byte),
+            Inner::Node256(node) => node.remove(byte),
+        }
+    }
+
+    /// An empty node of the same type, or of the next one if `grow`.
+    fn empty(&self, grow: bool) -> Inner {
+        match (self, grow) {
+            (Inner::Node4(_), false) => Inner::Node4(Sorted::default()),
+            (Inner::Node4(_), true) | (Inner::Node16(_), false) => Inner::Node16(Sorted::default()),
+            (Inner::Node16(_), true) | (Inner::Node48(_), false) => Inner::Node48(Box::default()),
+            (Inner::Node48(_) | Inner::Node256(_), _) => Inner::Node256(Box::default()),
+        }
+    }
+}
+
+impl Children for Inner {
+    fn insert(&self, byte: u8, child: usize) {
+        match self {
+            Inner::Node4(node) => node.insert(byte, child),
+            Inner::Node16(node) => node.insert(byte, child),
+            Inner::Node48(node) => node.insert(byte, child),
+            Inner::Node256(node) => node.insert(byte, child),
+        }
+    }
+
+    fn for_each(&self, f: impl FnMut(u8, usize)) {
+        match self {
+            Inner::Node4(node) => node.for_each(f),
+            Inner::Node16(node) => node.for_each(f),
+            Inner::Node48(node) => node.for_each(f),
+            Inner::Node256(node) => node.for_each(f),
+        }
+    }
+}
+
+/// The layout of the children of a node, the rest of the node is shared by
+/// the concurrent trees.
+pub(crate) trait Children {
+    /// Add a child for a byte that has none, the node must not be full.
+    fn insert(&self, byte: u8, child: usize);
+
+    fn for_each(&self, f: impl FnMut(u8, usize));
+}
+
+pub(crate) struct Node<I> {
+    pub lock: VersionLock,
+    /// The compressed path, never modified once the node is reachable.
+    pub prefix: Box<[u8]>,
+    /// The leaf whose key ends after the compressed path.
+    pub end: AtomicUsize,
+    pub inner: I,
+}
+
+impl Node<Inner> {
+    /// A copy of a locked node with another compressed path, and a bigger
+    /// type if `grow`.
+    fn copy(&self, prefix: &[u8], grow: bool) -> Self {
+        let node = Node::new(prefix, self.inner.empty(grow));
+        node.end.store(self.end.load(Relaxed), Relaxed);
+        self.inner
+            .for_each(|byte, child| node.inner.insert(byte, child));
+        node
+    }
+}
+
+impl<I: Children> Node<I> {
+    pub fn new(prefix: &[u8], inner: I) -> Self {
+        Node {
+            lock: VersionLock::default(),
+            prefix: prefix.into(),
+            end: AtomicUsize::new(0),
+            inner,
+        }
+    }
+
+    /// Store `leaf`, whose key is `key`, in the slot reached after `depth`
+    /// bytes of the key.
+    pub fn put(&self, key: &[u8], depth: usize, leaf: usize) {
+        match key.get(depth) {
+            Some(byte) => self.inner.insert(*byte, leaf),
+            None => self.end.store(leaf, Release),
+        }
+    }
+
+    pub fn into_raw(self) -> usize {
+        Box::into_raw(Box::new(self)) as usize
+    }
+
+    /// Free the node once no guard can reach it, its children must have been
+    /// moved to its replacement or removed.
+    ///
+    /// # Safety
+    /// The node must have been unlinked while its parent was locked.
+    pub unsafe fn retire(&self, guard: &Guard) {
+        guard.defer(self as *const Self as usize, Self::free_shallow);
+    }
+
+    /// Free the node and all its subtree.
+    ///
+    /// # Safety
+    /// `raw` must point to a node owned by the caller.
+    unsafe fn free(raw: usize) {
+        let node = Box::from_raw(raw as *mut Self);
+        free_child::<I>(node.end.load(Relaxed));
+        node.inner.for_each(|_, child| free_child::<I>(child));
+    }
+
+    /// Free a node whose children were moved to its replacement.
+    ///
+    /// # Safety
+    /// `raw` must point to a node owned by the caller.
+    unsafe fn free_shallow(raw: usize) {
+        drop(Box::from_raw(raw as *mut Self));
+    }
+}
+
+/// # Safety
+/// `raw` must be an empty slot or point to a child owned by the caller.
+unsafe fn free_child<I: Children>(raw: usize) {
+    match raw {
+        0 => (),
+        raw if raw & 1 == 1 => Leaf::free(raw),
+        raw => Node::<I>::free(raw),
+    }
+}
+
+/// The root of a concurrent tree, a Node256 that is never replaced so every
+/// other node has a parent. Dropping it frees the whole tree.
+pub(crate) struct Root<I: Children>(Box<Node<I>>);
+
+impl<I: Children> Root<I> {
+    pub fn new(inner: I) -> Self {
+        Root(Box::new(Node::new(&[], inner)))
+    }
+
+    pub fn child<'g>(&self, raw: usize, _guard: &'g Guard) -> Slot<'g, Node<I>> {
+        // SAFETY: the unlinked nodes are freed after the guard is dropped
+        unsafe { Slot::from_raw(raw) }
+    }
+}
+
+impl<I: Children> Deref for Root<I> {
+    type Target = Node<I>;
+
+    fn deref(&self) -> &Node<I> {
+        &self.0
+    }
+}
+
+impl<I: Children> Drop for Root<I> {
+    fn drop(&mut self) {
+        // SAFETY: the tree is owned and no reader is left
+        unsafe {
+            free_child::<I>(self.0.end.load(Relaxed));
+            self.0.inner.for_each(|_, child| free_child::<I>(child));
+        }
+    }
+}
+
+/// An adaptive radix tree that can be read and modified from many threads
+/// through a shared reference.
+///
+/// Lookups don't take any lock and insertions only lock the nodes they
+/// modify. Removal doesn't shrink the nodes.
+pub struct ConcurrentArt {
+    root: Root<Inner>,
+    collector: Collector,
+}
+
+impl Default for ConcurrentArt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConcurrentArt {
+    pub fn new() -> Self {
+        ConcurrentArt {
+            root: Root::new(Inner::Node256(Box::default())),
+            collector: Collector::default(),
+        }
+    }
+
+    /// Pin the current thread, the nodes and leaves can't be freed until
+    /// the guard is dropped.
+    pub fn pin(&self) -> Guard<'_> {
+        self.collector.pin()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<u64> {
+        self.get_ref(key, &self.pin()).copied()
+    }
+
+    /// Return a reference to the value of a key, valid as long as the guard
+    /// even if the key is updated or removed meanwhile.
+    pub fn get_ref<'g>(&'g self, key: &[u8], guard: &'g Guard) -> Option<&'g u64> {
+        assert!(
+            guard.belongs_to(&self.collector),
+            "The guard was pinned by another tree"
+        );
+        loop {
+            if let Ok(value) = self.try_get(key, guard) {
+                return value;
+            }
+        }
+    }
+
+    fn try_get<'g>(&'g self, key: &[u8], guard: &'g Guard) -> Result<Option<&'g u64>, Restart> {
+        let mut node = &*self.root;
+        let mut version = node.lock.read()?;
+        let mut depth = 0;
+        loop {
+            if !key[depth..].starts_with(&node.prefix) {
+                node.lock.check(version)?;
+                return Ok(None);
+            }
+            depth += node.prefix.len();
+            let child = match key.get(depth) {
+                Some(byte) => node.inner.find_child(*byte),
+                None => node.end.load(Acquire),
+            };
+            node.lock.check(version)?;
+            match self.root.child(child, guard) {
+                Slot::Empty => return Ok(None),
+                Slot::Leaf(leaf) => return Ok((*leaf.key == *key).then_some(&leaf.value)),
+                Slot::Node(child) => {
+                    let child_version = child.lock.read()?;
+                    node.lock.check(version)?;
+                    (node, version) = (child, child_version);
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Insert a key and its value, return the previous value of the key.
+    pub fn insert(&self, key: &[u8], value: u64) -> Option<u64> {
+        let guard = self.pin();
+        loop {
+            if let Ok(old) = self.try_insert(key, value, &guard) {
+                return old;
+            }
+        }
+    }
+
+    fn try_insert<'g>(
+        &'g self,
+        key: &[u8],
+        value: u64,
+        guard: &'g Guard,
+    ) -> Result<Option<u64>, Restart> {
+        // the parent of the current node, its version and the byte leading
+        // to the current node
+        let mut parent: Option<(&Node<Inner>, u64, u8)> = None;
+        let mut node = &*self.root;
+        let mut version = node.lock.read()?;
+        let mut depth = 0;
+        loop {
+            let common = common_len(&node.prefix, &key[depth..]);
+            if common < node.prefix.len() {
+                // the key leaves the compressed path, the node is replaced by
+                // a new node holding the shared part of the path
+                let (parent, parent_version, byte) =
+                    parent.expect("The root has no compressed path");
+                parent.lock.upgrade(parent_version)?;
+                if let Err(restart) = node.lock.upgrade(version) {
+                    parent.lock.unlock();
+                    return Err(restart);
+                }
+                let split = Node::new(&node.prefix[..common], Inner::Node4(Sorted::default()));
+                let copy = node.copy(&node.prefix[common + 1..], false);
+                split.inner.insert(node.prefix[common], copy.into_raw());
+                split.put(key, depth + common, Leaf::new_raw(key, value));
+                parent.inner.change(byte, split.into_raw());
+                node.lock.unlock_obsolete();
+                parent.lock.unlock();
+                // SAFETY: the node was unlinked while its parent was locked
+                unsafe { node.retire(guard) };
+                return Ok(None);
+            }
+            depth += common;
+
+            let Some(&byte) = key.get(depth) else {
+                node.lock.upgrade(version)?;
+                let old = node.end.swap(Leaf::new_raw(key, value), AcqRel);
+                node.lock.unlock();
+                return Ok(match self.root.child(old, guard) {
+                    Slot::Leaf(leaf) => {
+                        // SAFETY: the leaf was replaced while its node was locked
+                        unsafe { guard.defer(old, Leaf::free) };
+                        Some(leaf.value)
+                    }
+                    _ => None,
+                });
+            };
+            let child = node.inner.find_child(byte);
+            node.lock.check(version)?;
+            match self.root.child(child, guard) {
+                Slot::Empty if node.inner.is_full() => {
+                    // the node is replaced by a bigger one
+                    let (parent, parent_version, parent_byte) =
+                        parent.expect("The root is never full");
+                    parent.lock.upgrade(parent_version)?;
+                    if let Err(restart) = node.lock.upgrade(version) {
+                        parent.lock.unlock();
+                        return Err(restart);
+                    }
+                    let bigger = node.copy(&node.prefix, true);
+                    bigger.inner.insert(byte, Leaf::new_raw(key, value));
+                    parent.inner.change(parent_byte, bigger.into_raw());
+                    node.lock.unlock_obsolete();
+                    parent.lock.unlock();
+                    // SAFETY: the node was unlinked while its parent was locked
+                    unsafe { node.retire(guard) };
+                    return Ok(None);
+                }
+                Slot::Empty => {
+                    node.lock.upgrade(version)?;
+                    node.inner.insert(byte, Leaf::new_raw(key, value));
+                    node.lock.unlock();
+                    return Ok(None);
+                }
+                Slot::Leaf(leaf) => {
+                    node.lock.upgrade(version)?;
+                    if *leaf.key == *key {
+                        node.inner.change(byte, Leaf::new_raw(key, value));
+                        node.lock.unlock();
+                        // SAFETY: the leaf was replaced while its node was locked
+                        unsafe { guard.defer(child, Leaf::free) };
+                        return Ok(Some(leaf.value));
+                    }
+                    // both keys go in a new node holding their shared bytes
+                    let depth = depth + 1;
+                    let common = common_len(&leaf.key[depth..], &key[depth..]);
+                    let split =
+                        Node::new(&key[depth..depth + common], Inner::Node4(Sorted::default()));
+                    split.put(&leaf.key, depth + common, child);
+                    split.put(key, depth + common, Leaf::new_raw(key, value));
+                    node.inner.change(byte, split.into_raw());
+                    node.lock.unlock();
+                    return Ok(None);
+                }
+                Slot::Node(child) => {
+                    let child_version = child.lock.read()?;
+                    node.lock.check(version)?;
+                    parent = Some((node, version, byte));
+                    (node, version) = (child, child_version);
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Remove a key, return its value if it was present.
+    pub fn remove(&self, key: &[u8]) -> Option<u64> {
+        let guard = self.pin();
+        loop {
+            if let Ok(old) = self.try_remove(key, &guard) {
+                return old;
+            }
+        }
+    }
+
+    fn try_remove<'g>(&'g self, key: &[u8], guard: &'g Guard) -> Result<Option<u64>, Restart> {
+        let mut parent: Option<(&Node<Inner>, u64, u8)> = None;
+        let mut node = &*self.root;
+        let mut version = node.lock.read()?;
+        let mut depth = 0;
+        loop {
+            if !key[depth..].starts_with(&node.prefix) {
+                node.lock.check(version)?;
+                return Ok(None);
+            }
+            depth += node.prefix.len();
+            let byte = key.get(depth).copied();
+            let child = match byte {
+                Some(byte) => node.inner.find_child(byte),
+                None => node.end.load(Acquire),
+            };
+            let len = node.inner.len() + usize::from(node.end.load(Relaxed) != 0);
+            node.lock.check(version)?;
+            match self.root.child(child, guard) {
+                Slot::Empty => return Ok(None),
+                Slot::Leaf(leaf) if *leaf.key != *key => return Ok(None),
+                Slot::Leaf(leaf) => {
+                    match parent {
+                        // the node would be empty, it is unlinked from its parent
+                        Some((parent, parent_version, parent_byte)) if len == 1 => {
+                            parent.lock.upgrade(parent_version)?;
+                            if let Err(restart) = node.lock.upgrade(version) {
+                                parent.lock.unlock();
+                                return Err(restart);
+                            }
+                            parent.inner.remove(parent_byte);
+                            node.lock.unlock_obsolete();
+                            parent.lock.unlock();
+                            // SAFETY: the node was unlinked while its parent was locked
+                            unsafe { node.retire(guard) };
+                        }
+                        _ => {
+                            node.lock.upgrade(version)?;
+                            match byte {
+                                Some(byte) => node.inner.remove(byte),
+                                None => node.end.store(0, Release),
+                            }
+                            node.lock.unlock();
+                        }
+                    }
+                    // SAFETY: the leaf was unlinked while its node was locked
+                    unsafe { guard.defer(child, Leaf::free) };
+                    return Ok(Some(leaf.value));
+                }
+                Slot::Node(child) => {
+                    let child_version = child.lock.read()?;
+                    node.lock.check(version)?;
+                    // a node is only reached through a byte
+                    parent = byte.map(|byte| (node, version, byte));
+                    (node, version) = (child, child_version);
+                    depth += 1;
+                }
+            }
+        }
+    }
+}
+
+/// The tests shared by the concurrent trees, expanded in the test module of
+/// each tree.
+#[cfg(test)]
+macro_rules! concurrent_tests {
+    ($art:ident) => {
+        #[test]
+        fn send_sync() {
+            fn assert_send_sync<T: Send + Sync>() {}
+            assert_send_sync::<$art>();
+        }
+
+        #[test]
+        fn insert_get_remove() {
+            let art = $art::new();
+            assert_eq!(art.insert(b"hello", 1), None);
+            assert_eq!(art.insert(b"hell", 2), None);
+            assert_eq!(art.insert(b"hello world", 3), None);
+            assert_eq!(art.insert(b"help", 4), None);
+            assert_eq!(art.insert(b"", 5), None);
+            assert_eq!(art.insert(b"a very long key sharing a path", 6), None);
+            assert_eq!(art.insert(b"a very long key sharing another path", 7), None);
+            assert_eq!(art.insert(b"a very short key", 8), None);
+            assert_eq!(art.insert(b"hello", 9), Some(1));
+
+            assert_eq!(art.get(b"hello"), Some(9));
+            assert_eq!(art.get(b"hell"), Some(2));
+            assert_eq!(art.get(b"hello world"), Some(3));
+            assert_eq!(art.get(b"help"), Some(4));
+            assert_eq!(art.get(b""), Some(5));
+            assert_eq!(art.get(b"a very long key sharing a path"), Some(6));
+            assert_eq!(art.get(b"a very long key sharing another path"), Some(7));
+            assert_eq!(art.get(b"a very short key"), Some(8));
+            assert_eq!(art.get(b"hel"), None);
+            assert_eq!(art.get(b"hello world!"), None);
+            assert_eq!(art.get(b"a very long key"), None);
+
+            assert_eq!(art.remove(b"hell"), Some(2));
+            assert_eq!(art.remove(b"hell"), None);
+            assert_eq!(art.remove(b"a very long key sharing a path"), Some(6));
+            assert_eq!(art.remove(b"a very long"), None);
+            assert_eq!(art.get(b"hell"), None);
+            assert_eq!(art.get(b"hello"), Some(9));
+            assert_eq!(art.get(b"a very long key sharing another path"), Some(7));
+        }
+
+        #[test]
+        fn get_ref() {
+            let art = $art::new();
+            art.insert(b"hello", 1);
+            let guard = art.pin();
+            let value = art.get_ref(b"hello", &guard).unwrap();
+            // the leaf isn't freed while the guard is pinned
+            assert_eq!(art.insert(b"hello", 2), Some(1));
+            assert_eq!(art.remove(b"hello"), Some(2));
+            for i in 0..1_000_u64 {
+                art.insert(&i.to_be_bytes(), i);
+                art.remove(&i.to_be_bytes());
+            }
+            assert_eq!(*value, 1);
+            assert_eq!(art.get_ref(b"hello", &guard), None);
+        }
+
+        #[test]
+        #[should_panic = "The guard was pinned by another tree"]
+        fn get_ref_with_another_guard() {
+            let (art, other) = ($art::new(), $art::new());
+            art.get_ref(b"hello", &other.pin());
+        }
+
+        /// Every thread modifies its own keys and checks the answers against a
+        /// shared map, while the keys of all the threads share the same nodes.
+        #[test]
+        fn stress_against_btreemap() {
+            use std::{collections::BTreeMap, sync::Mutex, thread, vec::Vec};
+
+            use $crate::test::seeded_rng;
+
+            let art = $art::new();
+            let map = Mutex::new(BTreeMap::new());
+            let nb_threads = 8;
+            let key = |thread: u64, rng: u64| {
+                // few distinct bytes so the nodes are split, grown and emptied
+                // concurrently
+                let len = 1 + (rng >> 61) as usize;
+                let mut key: Vec<u8> = (0..len).map(|i| (rng >> (i * 3) & 7) as u8).collect();
+                key.insert(len / 2, thread as u8);
+                key
+            };
+            thread::scope(|scope| {
+                for thread in 0..nb_threads {
+                    let (art, map) = (&art, &map);
+                    scope.spawn(move || {
+                        for rng in seeded_rng(thread + 1).take(20_000) {
+                            let key = key(thread, rng);
+                            let value = rng >> 32;
+                            if rng.is_multiple_of(3) {
+                                let expected = map.lock().unwrap().remove(&key);
+                                assert_eq!(art.remove(&key), expected);
+                            } else {
+                                let expected = map.lock().unwrap().insert(key.clone(), value);
+                                assert_eq!(art.insert(&key, value), expected);
+                            }
+                            assert_eq!(art.get(&key), map.lock().unwrap().get(&key).copied());
+                        }
+                    });
+                }
+            });
+            let map = map.into_inner().unwrap();
+            for thread in 0..nb_threads {
+                for rng in seeded_rng(thread + 1).take(20_000) {
+                    let key = key(thread, rng);
+                    assert_eq!(art.get(&key), map.get(&key).copied());
+                }
+            }
+        }
+
+        /// Readers only ever see a value that was written for the key they
+        /// look up, and always find the keys that are never removed, while
+        /// writers grow and replace the nodes around them.
+        #[test]
+        fn stress_readers_and_writers() {
+            use std::thread;
+
+            use $crate::test::xorshift;
+
+            let art = $art::new();
+            let key = |i: u64| i.to_be_bytes()[5..].to_vec();
+            // a value is the key it was written for, plus a tag of its writer
+            let value = |i: u64, writer: u64| i << 8 | writer;
+            for i in (0..10_000).step_by(10) {
+                art.insert(&key(i), value(i, 0));
+            }
+            thread::scope(|scope| {
+                for writer in 0..4 {
+                    let art = &art;
+                    scope.spawn(move || {
+                        for round in 0..3 {
+                            for i in 0..10_000 {
+                                let i = xorshift(i + 1 + writer) % 10_000;
+                                if !i.is_multiple_of(10) && (i + round).is_multiple_of(5) {
+                                    art.remove(&key(i));
+                                } else {
+                                    art.insert(&key(i), value(i, writer));
+                                }
+                            }
+                        }
+                    });
+                }
+                for reader in 0..4 {
+                    let art = &art;
+                    scope.spawn(move || {
+                        for i in 0..100_000 {
+                            let i = xorshift(i + reader) % 10_000;
+                            match art.get(&key(i)) {
+                                Some(found) => {
+                                    assert_eq!(found >> 8, i);
+                                    assert!(found & 0xff < 4);
+                                }
+                                None => assert!(!i.is_multiple_of(10), "{i} is never removed"),
+                            }
+                        }
+                    });
+                }
+            });
+            for i in 0..10_000 {
+                if let Some(found) = art.get(&key(i)) {
+                    assert_eq!(found >> 8, i);
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+pub(crate) use concurrent_tests;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    concurrent_tests!(ConcurrentArt);
+
+    #[test]
+    fn grow_every_node_type() {
+        let art = ConcurrentArt::new();
+        for byte in 0..=255 {
+            assert_eq!(art.insert(&[b'a', byte], byte as u64), None);
+        }
+        for byte in 0..=255 {
+            assert_eq!(art.get(&[b'a', byte]), Some(byte as u64));
+        }
+        for byte in (0..=255).step_by(2) {
+            assert_eq!(art.remove(&[b'a', byte]), Some(byte as u64));
+        }
+        for byte in 0..=255 {
+            let expected = (byte % 2 == 1).then_some(byte as u64);
+            assert_eq!(art.get(&[b'a', byte]), expected);
+        }
+    }
+}
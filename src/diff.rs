@@ -0,0 +1,125 @@
+//! The differences between two trees, in the order of their keys.
+//!
+//! Two [`Art`]s don't share any node, so their entries are merged and
+//! compared one by one. The versions of a
+//! [`PersistentArt`](crate::PersistentArt) share their unchanged subtrees,
+//! which lets its diff skip them without visiting their entries.
+
+use alloc::vec::Vec;
+use core::{cmp::Ordering, iter::Peekable};
+
+use crate::{Allocator, Art, Iter};
+
+/// A difference between two trees, returned by [`Art::diff`] and
+/// [`PersistentArt::diff`](crate::PersistentArt::diff).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diff {
+    /// The key is only in the other tree.
+    Added(Vec<u8>, u64),
+    /// The key is only in this tree.
+    Removed(Vec<u8>, u64),
+    /// The key is in both trees with different values, the old one being
+    /// the value in this tree.
+    Changed(Vec<u8>, u64, u64),
+}
+
+/// Compare two sorted sequences of entries.
+pub(crate) struct Merge<I: Iterator, J: Iterator> {
+    old: Peekable<I>,
+    new: Peekable<J>,
+}
+
+impl<K, I, J> Merge<I, J>
+where
+    K: AsRef<[u8]>,
+    I: Iterator<Item = (K, u64)>,
+    J: Iterator<Item = (K, u64)>,
+{
+    pub fn new(old: I, new: J) -> Self {
+        Merge {
+            old: old.peekable(),
+            new: new.peekable(),
+        }
+    }
+}
+
+impl<K, I, J> Iterator for Merge<I, J>
+where
+    K: AsRef<[u8]>,
+    I: Iterator<Item = (K, u64)>,
+    J: Iterator<Item = (K, u64)>,
+{
+    type Item = Diff;
+
+    fn next(&mut self) -> Option<Diff> {
+        loop {
+            let ordering = match (self.old.peek(), self.new.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((old, _)), Some((new, _))) => old.as_ref().cmp(new.as_ref()),
+            };
+            match ordering {
+                Ordering::Less => {
+                    let (key, value) = self.old.next()?;
+                    return Some(Diff::Removed(key.as_ref().to_vec(), value));
+                }
+                Ordering::Greater => {
+                    let (key, value) = self.new.next()?;
+                    return Some(Diff::Added(key.as_ref().to_vec(), value));
+                }
+                Ordering::Equal => {
+                    let (key, old) = self.old.next()?;
+                    let (_, new) = self.new.next()?;
+                    if old != new {
+                        return Some(Diff::Changed(key.as_ref().to_vec(), old, new));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<A: Allocator> Art<A> {
+    /// The differences from `self` to `other`, sorted by key.
+    ///
+    /// Applying them to `self` gives `other`. Every entry of both trees is
+    /// visited, see [`PersistentArt::diff`](crate::PersistentArt::diff) to
+    /// skip the unchanged subtrees of two versions.
+    pub fn diff<'a, B: Allocator>(&'a self, other: &'a Art<B>) -> impl Iterator<Item = Diff> + 'a {
+        Merge::<Iter<'a>, Iter<'a>>::new(self.iter(), other.iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff() {
+        let mut old = Art::new();
+        let mut new = Art::new();
+        for (key, value) in [("apple", 1), ("banana", 2), ("cherry", 3), ("date", 4)] {
+            old.insert(key.as_bytes(), value);
+            new.insert(key.as_bytes(), value);
+        }
+        old.insert(b"applesauce", 5);
+        new.remove(b"banana");
+        new.insert(b"cherry", 30);
+        new.insert(b"banane", 6);
+        new.insert(b"", 7);
+
+        assert_eq!(
+            old.diff(&new).collect::<Vec<_>>(),
+            [
+                Diff::Added(b"".to_vec(), 7),
+                Diff::Removed(b"applesauce".to_vec(), 5),
+                Diff::Removed(b"banana".to_vec(), 2),
+                Diff::Added(b"banane".to_vec(), 6),
+                Diff::Changed(b"cherry".to_vec(), 3, 30),
+            ]
+        );
+        assert_eq!(old.diff(&old).count(), 0);
+        assert_eq!(Art::new().diff(&new).count(), new.iter().count());
+    }
+}
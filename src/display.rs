@@ -0,0 +1,192 @@
+//! Indented ASCII rendering of the tree, handy to explain the layout
+//! of an index or to find where a bad prefix was created:
+//!
+//! ```text
+//! "hell" [Node4]
+//! ├── END → 44
+//! ├── `a` → 43
+//! └── `o` → 42
+//! ```
+
+use alloc::{string::String, vec};
+use core::{fmt, iter::Peekable};
+
+use crate::{child::ChildRef, Allocator, Art, InnerNode, Node, Slots};
+
+/// Render an [`Art`] as an indented tree, see [`Art::display_tree`].
+pub struct DisplayTree<'a> {
+    root: &'a Node,
+}
+
+impl<A: Allocator> Art<A> {
+    /// Return an adaptor implementing `Display` by drawing the tree.
+    pub fn display_tree(&self) -> DisplayTree<'_> {
+        DisplayTree { root: &self.root }
+    }
+}
+
+impl fmt::Display for DisplayTree<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.root.path(0);
+        if !path.is_empty() {
+            write!(f, "{} ", Path(path))?;
+        }
+        describe(f, self.root)?;
+        draw_children(f, self.root, path.len())
+    }
+}
+
+/// A node whose children are being drawn, see [`draw_children`].
+struct Drawing<'a> {
+    /// The slots left to draw, the end first and then the children.
+    slots: Peekable<Slots<'a>>,
+    /// The number of key bytes consumed before reaching the children.
+    depth: usize,
+    /// The length of the indentation before the node was entered.
+    indent: usize,
+}
+
+/// Draw the children of `root`, `depth` is the number of key bytes consumed
+/// before reaching them.
+///
+/// The nodes being drawn are kept on an explicit stack so that deep trees
+/// don't overflow the call stack.
+fn draw_children(f: &mut fmt::Formatter<'_>, root: &Node, depth: usize) -> fmt::Result {
+    let mut indent = String::new();
+    // the value of the key ending at a node comes first, as the shortest key
+    let mut stack = vec![Drawing {
+        slots: root.slots().peekable(),
+        depth,
+        indent: 0,
+    }];
+    while let Some(top) = stack.last_mut() {
+        let Some((key, child)) = top.slots.next() else {
+            indent.truncate(top.indent);
+            stack.pop();
+            continue;
+        };
+        let last = top.slots.peek().is_none();
+        write!(f, "\n{indent}{}", if last { "└── " } else { "├── " })?;
+        match key {
+            Some(key) => write!(f, "{} ", Edge(key))?,
+            None => write!(f, "END ")?,
+        }
+        let child = match child.get() {
+            ChildRef::Node(child) => child,
+            ChildRef::Value(value) => {
+                write!(f, "→ {value}")?;
+                continue;
+            }
+        };
+        let depth = top.depth + usize::from(key.is_some());
+        let path = child.path(depth);
+        if !path.is_empty() {
+            write!(f, "{} ", Path(path))?;
+        }
+        describe(f, child)?;
+
+        let len = indent.len();
+        indent.push_str(if last { "    " } else { "│   " });
+        stack.push(Drawing {
+            slots: child.slots().peekable(),
+            depth: depth + path.len(),
+            indent: len,
+        });
+    }
+    Ok(())
+}
+
+fn describe(f: &mut fmt::Formatter<'_>, node: &Node) -> fmt::Result {
+    match node.inner {
+        InnerNode::SingleValueLeaf { value, .. } => write!(f, "→ {value}"),
+        ref inner => write!(f, "[{}]", inner.kind()),
+    }
+}
+
+/// Quote the byte of an edge when it's a graphic ASCII character, like the
+/// Graphviz export, otherwise display it in hexadecimal.
+struct Edge(u8);
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            b if b.is_ascii_graphic() => write!(f, "`{}`", b as char),
+            b => write!(f, "0x{b:02x}"),
+        }
+    }
+}
+
+/// Quote the path when it's valid UTF-8, otherwise display its bytes in hexadecimal.
+struct Path<'a>(&'a [u8]);
+
+impl fmt::Display for Path<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match core::str::from_utf8(self.0) {
+            Ok(s) => write!(f, "{s:?}"),
+            Err(_) => {
+                write!(f, "0x")?;
+                self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        insta::assert_snapshot!(Art::new().display_tree(), @"[Empty]");
+    }
+
+    #[test]
+    fn single_leaf() {
+        let mut art = Art::new();
+        art.insert(b"hello", 42);
+        insta::assert_snapshot!(art.display_tree(), @r###"
+        "hello" → 42
+        "###);
+    }
+
+    #[test]
+    fn nested() {
+        let mut art = Art::new();
+        art.insert(b"hello", 42);
+        art.insert(b"hella", 43);
+        art.insert(b"hell", 44);
+        art.insert(b"hey", 45);
+        art.insert(&[0xff, 0x00], 46);
+        art.insert(b" ", 47);
+        insta::assert_snapshot!(art.display_tree(), @r###"
+        [Node4]
+        ├── 0x20 → 47
+        ├── `h` "e" [Node4]
+        │   ├── `l` "l" [Node4]
+        │   │   ├── END → 44
+        │   │   ├── `a` → 43
+        │   │   └── `o` → 42
+        │   └── `y` → 45
+        └── 0xff "\0" → 46
+        "###);
+    }
+
+    #[test]
+    fn deep_trees() {
+        /// Count the branches drawn without keeping the output, whose
+        /// indentation grows with the square of the depth.
+        struct Branches(usize);
+
+        impl fmt::Write for Branches {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0 += usize::from(s == "└── ");
+                Ok(())
+            }
+        }
+
+        let (art, _) = crate::test::deep_chain(200_000);
+        let mut branches = Branches(0);
+        fmt::write(&mut branches, format_args!("{}", art.display_tree())).unwrap();
+        assert_eq!(branches.0, 200_000);
+    }
+}
@@ -0,0 +1,270 @@
+//! Export of the tree as a [Graphviz](https://graphviz.org/) graph.
+//!
+//! The `Debug` implementation flattens the children of a node on a
+//! single line, which becomes unreadable after a dozen keys. The
+//! graph produced here can be rendered with `dot -Tsvg`.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt::Write, iter::Peekable};
+
+use crate::{child::ChildRef, path_to_string, Allocator, Art, InnerNode, Slots};
+
+/// Restrict the part of the tree exported by [`Art::to_dot_with`].
+#[derive(Debug, Default, Clone)]
+pub struct DotOptions {
+    /// Don't export the nodes deeper than `max_depth` levels below the
+    /// first exported node. The children that are cut are replaced by `…`.
+    pub max_depth: Option<usize>,
+    /// Only export the subtree containing the keys starting with `prefix`.
+    pub prefix: Vec<u8>,
+}
+
+impl<A: Allocator> Art<A> {
+    /// Export the whole tree as a Graphviz graph.
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(&DotOptions::default())
+    }
+
+    /// Export the part of the tree selected by `options` as a Graphviz graph.
+    pub fn to_dot_with(&self, options: &DotOptions) -> String {
+        let mut dot = Dot {
+            out: String::from("digraph art {\n    node [shape=box, fontname=monospace];\n"),
+            next_id: 0,
+            max_depth: options.max_depth,
+        };
+        if let Some((child, depth)) = self.root.find_prefix(&options.prefix) {
+            dot.tree(child, depth);
+        }
+        dot.out.push_str("}\n");
+        dot.out
+    }
+}
+
+struct Dot {
+    out: String,
+    next_id: usize,
+    max_depth: Option<usize>,
+}
+
+/// A node whose children are being exported, see [`Dot::tree`].
+struct Pending<'a> {
+    id: usize,
+    /// The number of exported nodes above this one.
+    level: usize,
+    /// The number of key bytes consumed before reaching the children.
+    depth: usize,
+    /// The slots left to export, the end first and then the children.
+    slots: Peekable<Slots<'a>>,
+    /// The slot being exported, `None` for the end.
+    slot: Option<u8>,
+}
+
+impl Dot {
+    /// Export `root` and everything below it, `depth` is the number of key
+    /// bytes consumed before reaching it.
+    ///
+    /// The nodes are numbered in pre-order and the edges written once the
+    /// subtree they lead to is, with an explicit stack so that deep trees
+    /// don't overflow the call stack.
+    fn tree(&mut self, root: ChildRef, depth: usize) {
+        let mut stack = Vec::new();
+        let mut done = self.child(root, 0, depth, &mut stack);
+        while let Some(top) = stack.last_mut() {
+            if let Some(child_id) = done.take() {
+                let label = edge(top.slot);
+                writeln!(
+                    self.out,
+                    "    n{} -> n{child_id} [label=\"{label}\"];",
+                    top.id
+                )
+                .unwrap();
+            }
+            let Some((slot, child)) = top.slots.next() else {
+                done = stack.pop().map(|pending| pending.id);
+                continue;
+            };
+            top.slot = slot;
+            let (level, depth) = (top.level + 1, top.depth + usize::from(slot.is_some()));
+            done = self.child(child.get(), level, depth, &mut stack);
+        }
+    }
+
+    /// Write a child, and return its identifier once its subtree is written.
+    /// A node with children to export is pushed on `stack` instead.
+    fn child<'a>(
+        &mut self,
+        child: ChildRef<'a>,
+        level: usize,
+        depth: usize,
+        stack: &mut Vec<Pending<'a>>,
+    ) -> Option<usize> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let node = match child {
+            ChildRef::Node(node) => node,
+            ChildRef::Value(value) => {
+                // the value is stored in the slot of its parent
+                writeln!(
+                    self.out,
+                    "    n{id} [label=\"→ {value}\", shape=plaintext];"
+                )
+                .unwrap();
+                return Some(id);
+            }
+        };
+
+        let path = node.path(depth);
+        let mut label = node.inner.kind().to_string();
+        if !path.is_empty() {
+            write!(label, "\\n{}", escape(&path_to_string(path))).unwrap();
+        }
+        if let InnerNode::SingleValueLeaf { value, .. } = node.inner {
+            write!(label, "\\n→ {value}").unwrap();
+        }
+        writeln!(self.out, "    n{id} [label=\"{label}\"];").unwrap();
+
+        let mut slots = node.slots().peekable();
+        if slots.peek().is_none() {
+            return Some(id);
+        }
+        if self.max_depth.is_some_and(|max| level >= max) {
+            let elided = self.next_id;
+            self.next_id += 1;
+            writeln!(self.out, "    n{elided} [label=\"…\", shape=plaintext];").unwrap();
+            writeln!(self.out, "    n{id} -> n{elided} [style=dashed];").unwrap();
+            return Some(id);
+        }
+        stack.push(Pending {
+            id,
+            level,
+            depth: depth + path.len(),
+            slots,
+            slot: None,
+        });
+        None
+    }
+}
+
+/// Label the edge leading to the child under the byte `key`, or to the
+/// value of the key ending at the node.
+fn edge(key: Option<u8>) -> String {
+    match key {
+        Some(b) if b.is_ascii_graphic() => escape(&(b as char).to_string()),
+        Some(b) => format!("0x{b:02x}"),
+        None => String::from("END"),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn art() -> Art {
+        let mut art = Art::new();
+        art.insert(b"hello", 42);
+        art.insert(b"hella", 43);
+        art.insert(b"hell", 44);
+        art.insert(b"hey", 45);
+        art.insert(&[0xff, b'"'], 46);
+        art
+    }
+
+    #[test]
+    fn empty() {
+        insta::assert_snapshot!(Art::new().to_dot(), @r###"
+        digraph art {
+            node [shape=box, fontname=monospace];
+            n0 [label="Empty"];
+        }
+        "###);
+    }
+
+    #[test]
+    fn whole_tree() {
+        insta::assert_snapshot!(art().to_dot(), @r###"
+        digraph art {
+            node [shape=box, fontname=monospace];
+            n0 [label="Node4"];
+            n1 [label="Node4\ne"];
+            n2 [label="Node4\nl"];
+            n3 [label="→ 44", shape=plaintext];
+            n2 -> n3 [label="END"];
+            n4 [label="→ 43", shape=plaintext];
+            n2 -> n4 [label="a"];
+            n5 [label="→ 42", shape=plaintext];
+            n2 -> n5 [label="o"];
+            n1 -> n2 [label="l"];
+            n6 [label="→ 45", shape=plaintext];
+            n1 -> n6 [label="y"];
+            n0 -> n1 [label="h"];
+            n7 [label="Leaf\n\"\n→ 46"];
+            n0 -> n7 [label="0xff"];
+        }
+        "###);
+    }
+
+    #[test]
+    fn max_depth() {
+        let options = DotOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        insta::assert_snapshot!(art().to_dot_with(&options), @r###"
+        digraph art {
+            node [shape=box, fontname=monospace];
+            n0 [label="Node4"];
+            n1 [label="Node4\ne"];
+            n2 [label="…", shape=plaintext];
+            n1 -> n2 [style=dashed];
+            n0 -> n1 [label="h"];
+            n3 [label="Leaf\n\"\n→ 46"];
+            n0 -> n3 [label="0xff"];
+        }
+        "###);
+    }
+
+    #[test]
+    fn prefix() {
+        let options = DotOptions {
+            prefix: b"hel".to_vec(),
+            ..Default::default()
+        };
+        insta::assert_snapshot!(art().to_dot_with(&options), @r###"
+        digraph art {
+            node [shape=box, fontname=monospace];
+            n0 [label="Node4\nl"];
+            n1 [label="→ 44", shape=plaintext];
+            n0 -> n1 [label="END"];
+            n2 [label="→ 43", shape=plaintext];
+            n0 -> n2 [label="a"];
+            n3 [label="→ 42", shape=plaintext];
+            n0 -> n3 [label="o"];
+        }
+        "###);
+
+        let options = DotOptions {
+            prefix: b"world".to_vec(),
+            ..Default::default()
+        };
+        insta::assert_snapshot!(art().to_dot_with(&options), @r###"
+        digraph art {
+            node [shape=box, fontname=monospace];
+        }
+        "###);
+    }
+
+    #[test]
+    fn deep_trees() {
+        let (art, _) = crate::test::deep_chain(200_000);
+        // a line per node and per edge, plus the header and the closing brace
+        assert_eq!(art.to_dot().lines().count(), 2 * 200_000 + 4);
+    }
+}
@@ -0,0 +1,284 @@
+//! Epoch-based reclamation of the nodes and leaves unlinked from the
+//! concurrent trees.
+//!
+//! A reader may still hold a pointer to a node when a writer replaces it,
+//! so the node can only be freed once every operation that could have seen
+//! it is over. Each operation pins a [`Guard`], which records the global
+//! epoch it started in. The unlinked nodes are tagged with the epoch they
+//! were retired in, and the global epoch is only advanced once every pinned
+//! guard saw the current one. A node retired in epoch `e` is thus freed once
+//! the global epoch reaches `e + 2`: the guards pinned since can't have
+//! reached it.
+//!
+//! The guards are pinned through participants claimed from a shared list,
+//! so a thread doesn't need any thread local state to pin a guard. Claiming
+//! a participant and publishing the epoch are compare-and-swap loops that
+//! retry under contention: pinning is lock-free, not wait-free.
+
+use alloc::boxed::Box;
+use core::{
+    marker::PhantomData,
+    ptr::null_mut,
+    sync::atomic::{
+        AtomicBool, AtomicPtr, AtomicU64, AtomicUsize,
+        Ordering::{Acquire, Relaxed, Release, SeqCst},
+    },
+};
+
+/// Try to free the retired entries every time this many were retired.
+const COLLECT_EVERY: usize = 64;
+
+/// A slot pinning a guard, reused by the next guards once released.
+struct Participant {
+    in_use: AtomicBool,
+    /// Zero when unpinned, the pinned epoch shifted by one with the lowest
+    /// bit set otherwise.
+    epoch: AtomicU64,
+    next: *mut Participant,
+}
+
+/// A node or leaf unlinked from a tree, with the function freeing it.
+struct Garbage {
+    epoch: u64,
+    raw: usize,
+    free: unsafe fn(usize),
+    next: *mut Garbage,
+}
+
+/// The global epoch of a tree, its participants and its retired entries.
+pub(crate) struct Collector {
+    epoch: AtomicU64,
+    participants: AtomicPtr<Participant>,
+    garbage: AtomicPtr<Garbage>,
+    retired: AtomicUsize,
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Collector {
+            epoch: AtomicU64::new(0),
+            participants: AtomicPtr::new(null_mut()),
+            garbage: AtomicPtr::new(null_mut()),
+            retired: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Collector {
+    /// Pin a guard in the current epoch, retrying while the epoch advances.
+    pub fn pin(&self) -> Guard<'_> {
+        let participant = self.claim();
+        loop {
+            let epoch = self.epoch.load(SeqCst);
+            participant.epoch.store(epoch << 1 | 1, SeqCst);
+            // if the epoch advanced before the participant was visible, the
+            // guard may have missed an unlinking, pin again
+            if self.epoch.load(SeqCst) == epoch {
+                break;
+            }
+        }
+        Guard {
+            collector: self,
+            participant,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Claim a free participant, or add a new one to the list.
+    fn claim(&self) -> &Participant {
+        let mut current = self.participants.load(Acquire);
+        // SAFETY: the participants are only freed with the collector
+        while let Some(participant) = unsafe { current.as_ref() } {
+            if !participant.in_use.load(Relaxed)
+                && participant
+                    .in_use
+                    .compare_exchange(false, true, Acquire, Relaxed)
+                    .is_ok()
+            {
+                return participant;
+            }
+            current = participant.next;
+        }
+        let participant = Box::into_raw(Box::new(Participant {
+            in_use: AtomicBool::new(true),
+            epoch: AtomicU64::new(0),
+            next: self.participants.load(Relaxed),
+        }));
+        // SAFETY: the participant isn't shared until the exchange succeeds
+        unsafe {
+            while let Err(head) = self.participants.compare_exchange_weak(
+                (*participant).next,
+                participant,
+                Release,
+                Relaxed,
+            ) {
+                (*participant).next = head;
+            }
+            &*participant
+        }
+    }
+
+    /// Advance the global epoch if every pinned guard saw the current one,
+    /// return the global epoch.
+    fn try_advance(&self) -> u64 {
+        let epoch = self.epoch.load(SeqCst);
+        let mut current = self.participants.load(Acquire);
+        // SAFETY: the participants are only freed with the collector
+        while let Some(participant) = unsafe { current.as_ref() } {
+            let pinned = participant.epoch.load(SeqCst);
+            if pinned & 1 == 1 && pinned >> 1 != epoch {
+                return epoch;
+            }
+            current = participant.next;
+        }
+        match self
+            .epoch
+            .compare_exchange(epoch, epoch + 1, SeqCst, SeqCst)
+        {
+            Ok(_) => epoch + 1,
+            Err(epoch) => epoch,
+        }
+    }
+
+    fn push(&self, garbage: *mut Garbage) {
+        // SAFETY: the entry isn't shared until the exchange succeeds
+        unsafe {
+            (*garbage).next = self.garbage.load(Relaxed);
+            while let Err(head) =
+                self.garbage
+                    .compare_exchange_weak((*garbage).next, garbage, Release, Relaxed)
+            {
+                (*garbage).next = head;
+            }
+        }
+    }
+
+    /// Free the retired entries no pinned guard can reach anymore.
+    fn collect(&self) {
+        let epoch = self.try_advance();
+        let mut current = self.garbage.swap(null_mut(), Acquire);
+        while !current.is_null() {
+            // SAFETY: the entries taken from the list are owned
+            let next = unsafe { (*current).next };
+            if unsafe { (*current).epoch } + 2 <= epoch {
+                let garbage = unsafe { Box::from_raw(current) };
+                // SAFETY: the entry was unlinked two epochs ago
+                unsafe { (garbage.free)(garbage.raw) };
+            } else {
+                self.push(current);
+            }
+            current = next;
+        }
+    }
+}
+
+impl Drop for Collector {
+    fn drop(&mut self) {
+        // SAFETY: no guard borrows the collector anymore
+        unsafe {
+            let mut garbage = *self.garbage.get_mut();
+            while !garbage.is_null() {
+                let entry = Box::from_raw(garbage);
+                (entry.free)(entry.raw);
+                garbage = entry.next;
+            }
+            let mut participant = *self.participants.get_mut();
+            while !participant.is_null() {
+                participant = Box::from_raw(participant).next;
+            }
+        }
+    }
+}
+
+/// A proof that the current thread is in a critical section of a
+/// concurrent tree, returned by `pin`.
+///
+/// The nodes and leaves unlinked from the tree while a guard is alive are
+/// not freed before the guard is dropped, so the references obtained
+/// through it stay valid even if the entries are removed concurrently.
+/// Holding a guard for a long time delays the reclamation of the whole
+/// tree.
+pub struct Guard<'a> {
+    collector: &'a Collector,
+    participant: &'a Participant,
+    /// The guard pins the current thread.
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl Guard<'_> {
+    /// Whether the guard was pinned by `collector`.
+    pub(crate) fn belongs_to(&self, collector: &Collector) -> bool {
+        core::ptr::eq(self.collector, collector)
+    }
+
+    /// Free `raw` with `free` once no guard can reach it anymore.
+    ///
+    /// # Safety
+    /// `raw` must have been unlinked from the tree and must not be
+    /// retired twice.
+    pub(crate) unsafe fn defer(&self, raw: usize, free: unsafe fn(usize)) {
+        let garbage = Box::into_raw(Box::new(Garbage {
+            epoch: self.collector.epoch.load(SeqCst),
+            raw,
+            free,
+            next: null_mut(),
+        }));
+        self.collector.push(garbage);
+        if self
+            .collector
+            .retired
+            .fetch_add(1, Relaxed)
+            .is_multiple_of(COLLECT_EVERY)
+        {
+            self.collector.collect();
+        }
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.participant.epoch.store(0, SeqCst);
+        self.participant.in_use.store(false, Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe fn count(_: usize) {
+        FREED.fetch_add(1, SeqCst);
+    }
+
+    #[test]
+    fn reclaim() {
+        let collector = Collector::default();
+        let old = collector.pin();
+        for i in 0..1_000 {
+            // SAFETY: nothing is freed by count
+            unsafe { collector.pin().defer(i, count) };
+        }
+        // the old guard may still reach everything
+        assert_eq!(FREED.load(SeqCst), 0);
+        drop(old);
+        for i in 0..1_000 {
+            unsafe { collector.pin().defer(i, count) };
+        }
+        let freed = FREED.load(SeqCst);
+        assert!(freed >= 1_000, "{freed}");
+        // the guards released their participant
+        let mut participants = 0;
+        let mut current = collector.participants.load(SeqCst);
+        while let Some(participant) = unsafe { current.as_ref() } {
+            participants += 1;
+            current = participant.next;
+        }
+        assert_eq!(participants, 2);
+        drop(collector);
+        assert_eq!(FREED.load(SeqCst), 2_000);
+    }
+}
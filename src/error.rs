@@ -0,0 +1,99 @@
+//! Errors returned by the fallible operations of the tree.
+
+use core::fmt;
+
+/// The error returned by [`Art::try_insert`](crate::Art::try_insert) and
+/// [`ArenaArt::try_insert`](crate::ArenaArt::try_insert).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtError {
+    /// The allocator of the tree couldn't provide the memory of a node. The
+    /// entries of the tree are left untouched.
+    OutOfMemory,
+    /// An [`ArenaArt`](crate::ArenaArt) has no index left for a new node or
+    /// the bytes of a key. The entries of the tree are left untouched.
+    CapacityExceeded,
+}
+
+impl fmt::Display for ArtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArtError::OutOfMemory => write!(f, "the allocator of the tree is out of memory"),
+            ArtError::CapacityExceeded => write!(f, "the tree has no index left for a new entry"),
+        }
+    }
+}
+
+impl core::error::Error for ArtError {}
+
+/// The error returned when a tree can't be loaded by
+/// [`Art::deserialize_from`](crate::Art::deserialize_from).
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum FormatError {
+    /// The reader failed.
+    Io(std::io::Error),
+    /// The input ended before the end of the tree.
+    Truncated,
+    /// The input doesn't start like a serialized tree.
+    BadMagic,
+    /// The tree was written by a newer version of the format.
+    UnsupportedVersion(u16),
+    /// The values of the tree aren't `u64`s.
+    UnsupportedValueType(u8),
+    /// The input doesn't match its checksum.
+    ChecksumMismatch,
+    /// The input matches its checksum but doesn't describe a valid tree.
+    Corrupted(&'static str),
+    /// The allocator of the tree couldn't provide the memory of a node.
+    OutOfMemory,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Io(error) => write!(f, "failed to read the tree: {error}"),
+            FormatError::Truncated => write!(f, "the serialized tree is truncated"),
+            FormatError::BadMagic => write!(f, "the input is not a serialized tree"),
+            FormatError::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {version} of the format")
+            }
+            FormatError::UnsupportedValueType(tag) => write!(f, "unsupported value type {tag}"),
+            FormatError::ChecksumMismatch => write!(f, "the checksum of the tree doesn't match"),
+            FormatError::Corrupted(reason) => {
+                write!(f, "the serialized tree is corrupted: {reason}")
+            }
+            FormatError::OutOfMemory => write!(f, "the allocator of the tree is out of memory"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FormatError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FormatError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for FormatError {
+    fn from(error: std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::UnexpectedEof => FormatError::Truncated,
+            _ => FormatError::Io(error),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ArtError> for FormatError {
+    fn from(error: ArtError) -> Self {
+        match error {
+            // only an ArenaArt runs out of indices, the deserializer builds an Art
+            ArtError::OutOfMemory | ArtError::CapacityExceeded => FormatError::OutOfMemory,
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Typo tolerant search of the keys within a Levenshtein distance of a
+//! query.
+//!
+//! The search runs a [`Levenshtein`] automaton, whose state is the row of
+//! the dynamic programming matrix of the edit distance for the path
+//! followed: a row holds the distance between the path and each prefix of
+//! the query. The keys of a subtree all start with its path, so once every
+//! cell of a row exceeds the bound the subtree can be skipped.
+
+use alloc::vec::Vec;
+
+use crate::{
+    automaton::{Levenshtein, Search},
+    Allocator, Art,
+};
+
+/// An iterator over the entries within an edit distance of a query, sorted
+/// by key, see [`Art::fuzzy_search`].
+pub struct FuzzySearch<'a, 'q>(Search<'a, Levenshtein<'q>>);
+
+impl Iterator for FuzzySearch<'_, '_> {
+    type Item = (Vec<u8>, usize, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.0.next()?;
+        // the last cell of the row is the distance to the whole query
+        let distance = *self.0.state().last().unwrap();
+        Some((key, distance, value))
+    }
+}
+
+impl<A: Allocator> Art<A> {
+    /// Iterate over the entries whose key is within `max_distance` edits,
+    /// insertions, deletions or substitutions of a byte, of `query`. The
+    /// entries are sorted by key and yielded with their distance.
+    pub fn fuzzy_search<'a, 'q>(
+        &'a self,
+        query: &'q [u8],
+        max_distance: usize,
+    ) -> FuzzySearch<'a, 'q> {
+        FuzzySearch(self.search(Levenshtein::new(query, max_distance)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{string::String, vec, vec::Vec};
+
+    use super::*;
+    use crate::test::test_rng;
+
+    fn words() -> Art {
+        let mut art = Art::new();
+        for (i, word) in [
+            "hello",
+            "hella",
+            "hell",
+            "help",
+            "yellow",
+            "world",
+            "word",
+            "a long shared prefix: hello",
+            "a long shared prefix: help",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            art.insert(word.as_bytes(), i as u64);
+        }
+        art.insert(b"helm", u64::MAX);
+        art
+    }
+
+    fn search(art: &Art, query: &str, max_distance: usize) -> Vec<(String, usize, u64)> {
+        art.fuzzy_search(query.as_bytes(), max_distance)
+            .map(|(key, distance, value)| (String::from_utf8(key).unwrap(), distance, value))
+            .collect()
+    }
+
+    #[test]
+    fn fuzzy_search() {
+        let art = words();
+        insta::assert_debug_snapshot!(search(&art, "hello", 0), @r###"
+        [
+            (
+                "hello",
+                0,
+                0,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, "helo", 1), @r###"
+        [
+            (
+                "hell",
+                1,
+                2,
+            ),
+            (
+                "hello",
+                1,
+                0,
+            ),
+            (
+                "helm",
+                1,
+                18446744073709551615,
+            ),
+            (
+                "help",
+                1,
+                3,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, "wrld", 2), @r###"
+        [
+            (
+                "word",
+                2,
+                6,
+            ),
+            (
+                "world",
+                1,
+                5,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, "a long shared prefix: hell", 1), @r###"
+        [
+            (
+                "a long shared prefix: hello",
+                1,
+                7,
+            ),
+            (
+                "a long shared prefix: help",
+                1,
+                8,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(search(&art, "", 4).len(), @"4");
+        insta::assert_debug_snapshot!(search(&Art::new(), "hello", 3), @"[]");
+    }
+
+    /// The edit distance, computed with the whole matrix.
+    fn levenshtein(a: &[u8], b: &[u8]) -> usize {
+        let mut matrix = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[0] = i;
+        }
+        matrix[0] = (0..=b.len()).collect();
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                let substitution = matrix[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+                matrix[i][j] = substitution
+                    .min(matrix[i - 1][j] + 1)
+                    .min(matrix[i][j - 1] + 1);
+            }
+        }
+        matrix[a.len()][b.len()]
+    }
+
+    #[test]
+    fn fuzzy_search_random_keys() {
+        let mut art = Art::new();
+        let mut map = std::collections::BTreeMap::new();
+        for (i, rng) in (0..2_000).zip(test_rng()) {
+            // few distinct bytes so many keys are close to each other, and
+            // compressed paths longer than what fits in the header
+            let len = (rng >> 60) as usize;
+            let bytes = (0..len).map(|b| b'a' + (rng >> (b * 2) & 3) as u8);
+            let key: Vec<u8> = match i % 3 {
+                0 => b"a long shared prefix"
+                    .iter()
+                    .copied()
+                    .chain(bytes)
+                    .collect(),
+                _ => bytes.collect(),
+            };
+            art.insert(&key, i);
+            map.insert(key, i);
+        }
+        for query in [
+            &b""[..],
+            b"abc",
+            b"dddd",
+            b"abcdabcdab",
+            b"a long shared prefix",
+            b"a long shared prefixabca",
+        ] {
+            for max_distance in 0..4 {
+                let expected: Vec<_> = map
+                    .iter()
+                    .map(|(key, value)| (key.clone(), levenshtein(key, query), *value))
+                    .filter(|(_, distance, _)| *distance <= max_distance)
+                    .collect();
+                let found: Vec<_> = art.fuzzy_search(query, max_distance).collect();
+                assert_eq!(found, expected, "{query:?} {max_distance}");
+            }
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Hashes of the entries of an [`Art`](crate::Art) under each of its nodes,
+//! for the trees created with [`Art::with_hashes`](crate::Art::with_hashes).
+//!
+//! The hashes are kept in a side table rather than in the nodes, so the
+//! trees that don't maintain them keep their 64-byte nodes. An inner node
+//! is keyed by the path leading to its children: splitting the compressed
+//! path of a node or merging it with its parent moves the node but never
+//! changes this path.
+//!
+//! The hash of a node is the wrapping sum of the hashes of the entries
+//! below it. It only depends on these entries, so two trees holding the
+//! same ones under a prefix agree on its hash whatever their shape, and a
+//! modification updates it by adding the hash of the new entry and
+//! subtracting the one of the old entry. A write thus only updates the
+//! nodes on the path of its key, and computes from scratch the hash of the
+//! node it creates, if any.
+//!
+//! Unlike the nodes, the side table is allocated by the global allocator.
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+
+use crate::{child::ChildRef, Allocator, Art, Global, InnerNode, Node};
+
+/// FNV-1a followed by a mix of the bits, the integers are hashed in little
+/// endian so the replicas agree across platforms. It detects the differences
+/// between replicas but doesn't resist an adversary.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ContentHasher(u64);
+
+impl ContentHasher {
+    pub const LEAF: u8 = 0;
+    pub const NODE: u8 = 1;
+    pub const CHILD: u8 = 2;
+
+    pub fn new(kind: u8) -> Self {
+        let mut hasher = ContentHasher(0xcbf2_9ce4_8422_2325);
+        hasher.write(&[kind]);
+        hasher
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub fn finish(self) -> u64 {
+        let mut hash = self.0;
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^ (hash >> 31)
+    }
+}
+
+/// The hash of an entry.
+pub(crate) fn entry_hash(key: &[u8], value: u64) -> u64 {
+    let mut hasher = ContentHasher::new(ContentHasher::LEAF);
+    hasher.write_u64(key.len() as u64);
+    hasher.write(key);
+    hasher.write_u64(value);
+    hasher.finish()
+}
+
+/// The inner nodes on the path of `key`, with the length of the path
+/// leading to their children.
+fn branches<'a>(root: &'a Node, key: &'a [u8]) -> impl Iterator<Item = (usize, &'a Node)> {
+    let mut next = Some((root, 0));
+    core::iter::from_fn(move || {
+        let (node, depth) = next.take()?;
+        let prefix_len = node.prefix_len as usize;
+        if node.inner.is_leaf() || node.prefix_mismatch(key, depth) < prefix_len {
+            return None;
+        }
+        let depth = depth + prefix_len;
+        let child = key.get(depth).and_then(|byte| node.inner.find_child(*byte));
+        if let Some(ChildRef::Node(child)) = child.map(|child| child.get()) {
+            next = Some((child, depth + 1));
+        }
+        Some((depth, node))
+    })
+}
+
+/// The wrapping sums of the hashes of the entries below the inner nodes of
+/// a tree, keyed by the path leading to their children.
+#[derive(Debug, Default)]
+pub(crate) struct Hashes(BTreeMap<Box<[u8]>, u64>);
+
+impl Hashes {
+    /// Update the hashes once `value` was inserted under `key`, replacing
+    /// `old`.
+    pub fn insert(&mut self, root: &Node, key: &[u8], old: Option<u64>, value: u64) {
+        let old = old.map_or(0, |old| entry_hash(key, old));
+        let delta = entry_hash(key, value).wrapping_sub(old);
+        for (depth, node) in branches(root, key) {
+            match self.0.get_mut(&key[..depth]) {
+                Some(sum) => *sum = sum.wrapping_add(delta),
+                // the node was created by the insertion, its sum already
+                // covers the new entry
+                None => {
+                    let sum = self.sum(node, &key[..depth]);
+                    self.0.insert(key[..depth].into(), sum);
+                }
+            }
+        }
+    }
+
+    /// The lengths of the paths of the inner nodes on the path of `key`, to
+    /// pass to [`Hashes::remove`] once it is removed.
+    pub fn branches(root: &Node, key: &[u8]) -> Vec<usize> {
+        branches(root, key).map(|(depth, _)| depth).collect()
+    }
+
+    /// Update the hashes once `key` and its `value` were removed, `before`
+    /// are the [`Hashes::branches`] of the key before the removal.
+    pub fn remove(&mut self, root: &Node, key: &[u8], value: u64, before: &[usize]) {
+        let after = Hashes::branches(root, key);
+        // the nodes left on the path were already there, the others were
+        // freed or merged with their single child
+        for depth in before.iter().filter(|depth| !after.contains(depth)) {
+            self.0.remove(&key[..*depth]);
+        }
+        let delta = entry_hash(key, value);
+        for depth in after {
+            let sum = self.0.get_mut(&key[..depth]).expect("The node is hashed");
+            *sum = sum.wrapping_sub(delta);
+        }
+    }
+
+    /// The hash of the entries whose key starts with `prefix`.
+    pub fn subtree(&self, root: &Node, prefix: &[u8]) -> u64 {
+        match root.find_prefix(prefix) {
+            None => 0,
+            Some((ChildRef::Value(value), _)) => entry_hash(prefix, value),
+            Some((ChildRef::Node(node), depth)) => match &node.inner {
+                InnerNode::Empty => 0,
+                InnerNode::SingleValueLeaf { key, value } => entry_hash(key, *value),
+                _ => self.0[&*[&prefix[..depth], node.path(depth)].concat()],
+            },
+        }
+    }
+
+    /// The sum of the hashes of the entries below `node`, whose children
+    /// are reached through `path`. Its inner children must be hashed.
+    fn sum(&self, node: &Node, path: &[u8]) -> u64 {
+        let mut sum = 0u64;
+        let mut key = Vec::from(path);
+        for (byte, child) in node.slots() {
+            key.truncate(path.len());
+            key.extend(byte);
+            let hash = match child.get() {
+                ChildRef::Value(value) => entry_hash(&key, value),
+                ChildRef::Node(child) => match &child.inner {
+                    InnerNode::SingleValueLeaf { key, value } => entry_hash(key, *value),
+                    _ => {
+                        let depth = key.len();
+                        key.extend_from_slice(child.path(depth));
+                        self.0[&*key]
+                    }
+                },
+            };
+            sum = sum.wrapping_add(hash);
+        }
+        sum
+    }
+}
+
+impl Art {
+    /// An empty tree maintaining the hash of the entries under each of its
+    /// nodes, see [`Art::root_hash`].
+    pub fn with_hashes() -> Art {
+        Art::with_hashes_in(Global)
+    }
+}
+
+impl<A: Allocator> Art<A> {
+    /// An empty tree maintaining the hashes of its entries, whose nodes and
+    /// keys are allocated by `alloc`, see [`Art::with_hashes`].
+    pub fn with_hashes_in(alloc: A) -> Self {
+        let mut art = Art::new_in(alloc);
+        art.hashes = Some(Box::default());
+        art
+    }
+
+    /// The hash of all the entries of the tree, `None` if it doesn't
+    /// maintain the hashes, see [`Art::with_hashes`].
+    pub fn root_hash(&self) -> Option<u64> {
+        self.subtree_hash(&[])
+    }
+
+    /// The hash of the entries whose key starts with `prefix`, `None` if
+    /// the tree doesn't maintain the hashes.
+    ///
+    /// It only depends on these entries, so two trees holding the same ones
+    /// under `prefix` have the same hash for it. Replicas can thus find
+    /// their differences by comparing the hashes of longer and longer
+    /// prefixes where they disagree. The hash of a prefix without any entry
+    /// is zero.
+    pub fn subtree_hash(&self, prefix: &[u8]) -> Option<u64> {
+        let hashes = self.hashes.as_ref()?;
+        Some(hashes.subtree(&self.root, prefix))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, vec::Vec};
+
+    use crate::{
+        test::{seeded_rng, test_rng},
+        Art,
+    };
+
+    use super::*;
+
+    /// The hash of the entries of `map` whose key starts with `prefix`.
+    fn expected(map: &BTreeMap<Vec<u8>, u64>, prefix: &[u8]) -> u64 {
+        map.range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .fold(0, |sum, (key, value)| {
+                sum.wrapping_add(entry_hash(key, *value))
+            })
+    }
+
+    #[test]
+    fn hashes() {
+        let build = |keys: &[&str]| {
+            let mut art = Art::with_hashes();
+            for key in keys {
+                art.insert(key.as_bytes(), key.len() as u64);
+            }
+            art
+        };
+        let a = build(&["abcx", "abcy"]);
+        let mut b = build(&["z", "abcy", "abcx"]);
+        assert_ne!(a.root_hash(), b.root_hash());
+        // the subtree under `ab` is the root of `a` but not of `b`
+        assert_eq!(a.subtree_hash(b"ab"), b.subtree_hash(b"ab"));
+        assert_eq!(a.subtree_hash(b"abcx"), b.subtree_hash(b"abcx"));
+        assert_ne!(a.subtree_hash(b"abcx"), a.subtree_hash(b"abcy"));
+        assert_eq!(a.subtree_hash(b"q"), Some(0));
+        assert_eq!(b.remove(b"z"), Some(1));
+        assert_eq!(b.root_hash(), a.root_hash());
+        b.insert(b"abcx", 0);
+        assert_ne!(b.root_hash(), a.root_hash());
+        assert_eq!(Art::with_hashes().root_hash(), Some(0));
+
+        let mut unhashed = Art::new();
+        unhashed.insert(b"abcx", 4);
+        assert_eq!(unhashed.root_hash(), None);
+        assert_eq!(unhashed.subtree_hash(b"ab"), None);
+    }
+
+    #[test]
+    fn hashes_random() {
+        let mut rng = seeded_rng(0x1234_5678_9abc_def1);
+        let mut art = Art::with_hashes();
+        let mut map = BTreeMap::new();
+        for (i, random) in (0..5_000).zip(test_rng()) {
+            // short keys inline their values in the slots, and the keys
+            // sharing a long head truncate the compressed paths
+            let head: &[u8] = if random & 1 == 1 {
+                b"a long shared head"
+            } else {
+                b""
+            };
+            let key: Vec<u8> = (0..(random >> 60) % 6)
+                .map(|i| b"abc"[(random >> (i * 2 + 1)) as usize % 3])
+                .collect();
+            let key = [head, &key].concat();
+            if random % 3 == 0 {
+                assert_eq!(art.remove(&key), map.remove(&key));
+            } else {
+                assert_eq!(art.insert(&key, i % 7), map.insert(key, i % 7));
+            }
+            if i % 50 == 0 {
+                assert_eq!(art.root_hash(), Some(expected(&map, &[])));
+                for _ in 0..20 {
+                    let random = rng.next().unwrap();
+                    let head: &[u8] = match random % 3 {
+                        0 => b"",
+                        1 => b"a long",
+                        _ => b"a long shared head",
+                    };
+                    let prefix: Vec<u8> = (0..random >> 61)
+                        .map(|i| b"abc"[(random >> (i * 2 + 3)) as usize % 3])
+                        .collect();
+                    let prefix = [head, &prefix].concat();
+                    assert_eq!(art.subtree_hash(&prefix), Some(expected(&map, &prefix)));
+                }
+            }
+        }
+        for key in map.keys() {
+            art.remove(key);
+        }
+        // the freed nodes don't leave their hashes behind
+        assert!(art.hashes.as_ref().unwrap().0.is_empty());
+        assert_eq!(art.root_hash(), Some(0));
+    }
+}
@@ -0,0 +1,231 @@
+//! Iteration over the entries of the tree in key order.
+//!
+//! The leaves stored in their own node store their full key which is read
+//! back directly. The values inlined in the slot of their parent don't, and
+//! their key is rebuilt from the path followed to reach them.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{
+    child::{Child, ChildRef},
+    Allocator, Art, InnerNode,
+};
+
+type Children<'a> = Box<dyn Iterator<Item = (u8, &'a Child)> + 'a>;
+
+/// An iterator over the entries of an [`Art`], sorted by key.
+pub struct Iter<'a> {
+    start: Option<ChildRef<'a>>,
+    /// The bytes of the path followed to reach the current node.
+    path: Vec<u8>,
+    /// The children left to visit, with the length of the path leading to them.
+    stack: Vec<(Children<'a>, usize)>,
+}
+
+impl<'a> Iter<'a> {
+    /// Iterate over the subtree `start` reached after the `depth` first bytes of `prefix`.
+    fn new(start: Option<(ChildRef<'a>, usize)>, prefix: &[u8]) -> Self {
+        let (start, depth) = match start {
+            Some((start, depth)) => (Some(start), depth),
+            None => (None, 0),
+        };
+        Iter {
+            start,
+            path: prefix[..depth].to_vec(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Return the entry stored in `child` or start iterating over its children.
+    fn visit(&mut self, child: ChildRef<'a>) -> Option<(Vec<u8>, u64)> {
+        match child {
+            ChildRef::Value(value) => Some((self.path.clone(), value)),
+            ChildRef::Node(node) => match &node.inner {
+                InnerNode::SingleValueLeaf { key, value } => Some((key.to_vec(), *value)),
+                inner => {
+                    let depth = self.path.len();
+                    self.path.extend_from_slice(node.path(depth));
+                    self.stack.push((inner.children(), self.path.len()));
+                    // the key ending at this node is the smallest of its subtree
+                    let end = node.end.as_ref()?;
+                    self.visit(end.get())
+                }
+            },
+        }
+    }
+}
+
+impl Iterator for Iter<'_> {
+    type Item = (Vec<u8>, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take() {
+            if let Some(entry) = self.visit(start) {
+                return Some(entry);
+            }
+        }
+        loop {
+            let (children, len) = self.stack.last_mut()?;
+            let len = *len;
+            match children.next() {
+                Some((key, child)) => {
+                    self.path.truncate(len);
+                    self.path.push(key);
+                    if let Some(entry) = self.visit(child.get()) {
+                        return Some(entry);
+                    }
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl<A: Allocator> Art<A> {
+    /// Iterate over all the entries of the tree, sorted by key.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter::new(Some((ChildRef::Node(&self.root), 0)), &[])
+    }
+
+    /// Iterate over the entries whose key starts with `prefix`, sorted by key.
+    pub fn prefix_iter(&self, prefix: &[u8]) -> Iter<'_> {
+        Iter::new(self.root.find_prefix(prefix), prefix)
+    }
+
+    /// Return the entry with the smallest key.
+    pub fn first_key_value(&self) -> Option<(Vec<u8>, u64)> {
+        self.iter().next()
+    }
+}
+
+impl<'a, A: Allocator> IntoIterator for &'a Art<A> {
+    type Item = (Vec<u8>, u64);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use super::*;
+    use crate::test::test_rng;
+
+    fn art() -> Art {
+        let mut art = Art::new();
+        for (i, key) in [
+            "hello",
+            "hella",
+            "hell",
+            "hey",
+            "a long shared prefix: hello",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            art.insert(key.as_bytes(), i as u64);
+        }
+        art.insert(b"a long shared prefix: world", 5);
+        art
+    }
+
+    fn collect(iter: Iter) -> Vec<(String, u64)> {
+        iter.map(|(key, value)| (String::from_utf8(key).unwrap(), value))
+            .collect()
+    }
+
+    #[test]
+    fn iter() {
+        insta::assert_debug_snapshot!(collect(Art::new().iter()), @"[]");
+        insta::assert_debug_snapshot!(collect(art().iter()), @r###"
+        [
+            (
+                "a long shared prefix: hello",
+                4,
+            ),
+            (
+                "a long shared prefix: world",
+                5,
+            ),
+            (
+                "hell",
+                2,
+            ),
+            (
+                "hella",
+                1,
+            ),
+            (
+                "hello",
+                0,
+            ),
+            (
+                "hey",
+                3,
+            ),
+        ]
+        "###);
+        let first = art().first_key_value();
+        let first = first.map(|(key, value)| (String::from_utf8(key).unwrap(), value));
+        insta::assert_debug_snapshot!(first, @r###"
+        Some(
+            (
+                "a long shared prefix: hello",
+                4,
+            ),
+        )
+        "###);
+    }
+
+    #[test]
+    fn prefix_iter() {
+        let art = art();
+        insta::assert_debug_snapshot!(collect(art.prefix_iter(b"hell")), @r###"
+        [
+            (
+                "hell",
+                2,
+            ),
+            (
+                "hella",
+                1,
+            ),
+            (
+                "hello",
+                0,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(collect(art.prefix_iter(b"a long")), @r###"
+        [
+            (
+                "a long shared prefix: hello",
+                4,
+            ),
+            (
+                "a long shared prefix: world",
+                5,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(collect(art.prefix_iter(b"hellos")), @"[]");
+        insta::assert_debug_snapshot!(collect(art.prefix_iter(b"")).len(), @"6");
+    }
+
+    #[test]
+    fn iter_is_sorted() {
+        let mut art = Art::new();
+        let mut map = std::collections::BTreeMap::new();
+        for (i, rng) in (0..10_000).zip(test_rng()) {
+            let key = rng.to_be_bytes()[..(rng >> 60) as usize % 9].to_vec();
+            art.insert(&key, i);
+            map.insert(key, i);
+        }
+        assert!(art.iter().eq(map.into_iter()));
+    }
+}
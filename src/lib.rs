@@ -1,17 +1,86 @@
-use std::{
+#![no_std]
+
+extern crate alloc;
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+};
+use core::{
     fmt::{self},
-    mem::take,
+    mem::{replace, take},
 };
 
+use child::{Child, ChildRef};
 use node16::Node16;
 use node256::Node256;
 use node4::Node4;
 use node48::Node48;
+use ptr::Ptr;
 
+mod arena;
+mod automaton;
+mod child;
+mod cidr;
+mod concurrent;
+mod diff;
+mod display;
+mod dot;
+mod epoch;
+mod error;
+mod fuzzy;
+mod hashes;
+mod iter;
 mod node16;
 mod node256;
 mod node4;
 mod node48;
+mod persistent;
+mod prefixes;
+mod ptr;
+mod rowex;
+#[cfg(feature = "std")]
+mod serialize;
+mod topic;
+mod tuning;
+
+pub use allocator_api2::alloc::{AllocError, Allocator, Global};
+pub use arena::ArenaArt;
+pub use automaton::{
+    Automaton, Complement, Glob, Intersection, Levenshtein, Prefix, Search, Str, Subsequence, Union,
+};
+pub use cidr::{CidrTable, Matches, Route, Routes};
+pub use concurrent::ConcurrentArt;
+pub use diff::Diff;
+pub use display::DisplayTree;
+pub use dot::DotOptions;
+pub use epoch::Guard;
+pub use error::ArtError;
+#[cfg(feature = "std")]
+pub use error::FormatError;
+pub use fuzzy::FuzzySearch;
+pub use iter::Iter;
+pub use persistent::{PersistentArt, Txn};
+pub use prefixes::Prefixes;
+pub use rowex::RowexArt;
+pub use topic::{Subscriptions, TopicSyntax};
+
+/// Internals exposed for the benchmarks, they are not part of the API.
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::node16::{find_key, find_key_linear, insert_position, insert_position_linear};
+}
+
+/// Number of bytes of the compressed path stored in the header of a node.
+///
+/// Longer paths are only partially stored. Their remaining bytes are skipped
+/// during lookups and verified optimistically once a leaf, which stores its
+/// full key, is reached.
+pub(crate) const PREFIX_LEN: usize = 8;
 
 /*
 Additionally, at the front of each inner node, a header of
@@ -20,206 +89,792 @@ of children, and the compressed path (cf. Section III-E)
 */
 #[derive(Default)]
 pub struct Node {
-    nb_childrens: u64,
-    path: Vec<u8>,
+    nb_childrens: u16,
+    prefix_len: u32,
+    prefix: [u8; PREFIX_LEN],
+    /// The value of the key ending right after the compressed path of an
+    /// inner node. It is kept out of the children so it doesn't use one of
+    /// their slots, and is stored like them, see [`Child`].
+    end: Option<Child>,
     inner: InnerNode,
+    /// The index plus one of the node among the candidates for a multi-level
+    /// node, or zero, see [`tuning`]. It fits in the padding of the header
+    /// and moves with the content of the node.
+    tuning: u16,
+}
+
+/// The slots of a node, see [`Node::slots`].
+pub(crate) type Slots<'a> = Box<dyn Iterator<Item = (Option<u8>, &'a Child)> + 'a>;
+
+/// Display a path as UTF-8 when it's valid, and as hexadecimal otherwise.
+pub(crate) fn path_to_string(path: &[u8]) -> String {
+    match core::str::from_utf8(path) {
+        Ok(s) => s.to_string(),
+        Err(_) => path.iter().fold(String::from("0x"), |mut s, b| {
+            s.push_str(&format!("{b:02x}"));
+            s
+        }),
+    }
+}
+
+fn debug_path(path: &[u8]) -> String {
+    match core::str::from_utf8(path) {
+        Ok(s) => format!("`{}` ({:?})", s, path),
+        Err(_) => format!("{:?}", path),
+    }
 }
 
 impl fmt::Debug for Node {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let path = match std::str::from_utf8(&self.path) {
-            Ok(s) => format!("`{}` ({:?})", s, self.path),
-            Err(_) => format!("{:?}", self.path),
-        };
+        let mut prefix = debug_path(self.stored_prefix());
+        if self.is_truncated() {
+            prefix.push_str(&format!(
+                " + {} bytes",
+                self.prefix_len as usize - PREFIX_LEN
+            ));
+        }
         f.debug_struct("Node")
             .field("nb_childrens", &self.nb_childrens)
-            .field("path", &path)
+            .field("prefix", &prefix)
+            .field("end", &self.end)
             .field("inner", &self.inner)
             .finish()
     }
 }
 
 impl Node {
-    fn insert(mut self, input: &[u8], value: u64) -> (Self, Option<u64>) {
-        let old_value = match self.inner {
+    fn leaf_in<A: Allocator>(key: &[u8], value: u64, alloc: &A) -> Result<Self, ArtError> {
+        Ok(Node {
+            inner: InnerNode::SingleValueLeaf {
+                key: Ptr::from_slice_in(key, alloc)?,
+                value,
+            },
+            ..Default::default()
+        })
+    }
+
+    /// Turn the leaf into the child slot reached after `depth` bytes. A leaf
+    /// whose key ends there is stored directly in the slot when possible,
+    /// `optimistic` tells if some bytes of the path were skipped to reach it.
+    ///
+    /// The leaf is given back if it can't be moved in its own allocation.
+    fn into_child_in<A: Allocator>(
+        self,
+        depth: usize,
+        optimistic: bool,
+        alloc: &A,
+    ) -> Result<Child, Node> {
+        if let InnerNode::SingleValueLeaf { ref key, value } = self.inner {
+            if let Some(child) = inline_value(key.len(), depth, optimistic, value) {
+                // Safety: the nodes of the tree are allocated by `alloc`
+                unsafe { self.free_in(alloc) };
+                return Ok(child);
+            }
+        }
+        Ptr::try_new_in(self, alloc).map(Child::node)
+    }
+
+    /// The part of the compressed path stored in the header.
+    fn stored_prefix(&self) -> &[u8] {
+        &self.prefix[..(self.prefix_len as usize).min(PREFIX_LEN)]
+    }
+
+    /// Whether the compressed path is too long to be fully stored in the header.
+    fn is_truncated(&self) -> bool {
+        self.prefix_len as usize > PREFIX_LEN
+    }
+
+    fn set_prefix(&mut self, prefix: &[u8]) {
+        self.prefix_len = prefix.len() as u32;
+        let stored = prefix.len().min(PREFIX_LEN);
+        self.prefix[..stored].copy_from_slice(&prefix[..stored]);
+    }
+
+    /// Return the key of any leaf living under this node.
+    ///
+    /// Only the leaves stored in their own node can be returned, which
+    /// are the only ones allowed below a truncated compressed path.
+    fn any_leaf_key(&self) -> &[u8] {
+        let mut node = self;
+        loop {
+            match &node.inner {
+                InnerNode::SingleValueLeaf { key, .. } => return key,
+                inner => {
+                    node = node
+                        .end
+                        .iter()
+                        .chain(inner.children().map(|(_, child)| child))
+                        .find_map(Child::as_node)
+                        .expect("No leaf stored in a node below a truncated path")
+                }
+            }
+        }
+    }
+
+    /// Return the bytes of the path between the parent node and the first
+    /// child of this node, `depth` is the number of key bytes consumed before
+    /// reaching this node. For a leaf, this is the remaining part of its key.
+    ///
+    /// The bytes that don't fit in the header are read back from a leaf.
+    fn path(&self, depth: usize) -> &[u8] {
+        match &self.inner {
+            InnerNode::Empty => &[],
+            InnerNode::SingleValueLeaf { key, .. } => &key[depth.min(key.len())..],
+            _ if self.is_truncated() => {
+                &self.any_leaf_key()[depth..depth + self.prefix_len as usize]
+            }
+            _ => self.stored_prefix(),
+        }
+    }
+
+    /// Iterate over the slots of the node in key order: the value of the key
+    /// ending at the node first, under `None`, and then the children.
+    pub(crate) fn slots(&self) -> Slots<'_> {
+        let end = self.end.iter().map(|end| (None, end));
+        let children = self
+            .inner
+            .children()
+            .map(|(byte, child)| (Some(byte), child));
+        Box::new(end.chain(children))
+    }
+
+    /// Return the number of bytes of the compressed path shared with `key[depth..]`.
+    fn prefix_mismatch(&self, key: &[u8], depth: usize) -> usize {
+        let key = &key[depth..];
+        let prefix = if self.is_truncated() {
+            self.path(depth)
+        } else {
+            self.stored_prefix()
+        };
+        common_len(prefix, key)
+    }
+
+    /// Return the child under which all the keys starting with `prefix` live,
+    /// and the number of bytes consumed before reaching it.
+    fn find_prefix(&self, prefix: &[u8]) -> Option<(ChildRef<'_>, usize)> {
+        let mut node = self;
+        let mut depth = 0;
+        loop {
+            let path = node.path(depth);
+            let remaining = &prefix[depth..];
+            if remaining.len() <= path.len() {
+                return path
+                    .starts_with(remaining)
+                    .then_some((ChildRef::Node(node), depth));
+            }
+            if !remaining.starts_with(path) {
+                return None;
+            }
+            depth += path.len();
+            let child = node.inner.find_child(prefix[depth])?;
+            depth += 1;
+            match child.get() {
+                ChildRef::Node(child) => node = child,
+                value => return (depth == prefix.len()).then_some((value, depth)),
+            }
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<u64> {
+        let mut node = self;
+        let mut depth = 0;
+        loop {
+            match &node.inner {
+                InnerNode::Empty => return None,
+                InnerNode::SingleValueLeaf {
+                    key: leaf_key,
+                    value,
+                } => {
+                    // the skipped bytes of the truncated prefixes are verified here
+                    return (**leaf_key == *key).then_some(*value);
+                }
+                inner => {
+                    let stored = node.stored_prefix();
+                    if key.get(depth..depth + stored.len())? != stored {
+                        return None;
+                    }
+                    depth += node.prefix_len as usize;
+                    let child = match key.get(depth) {
+                        Some(byte) => {
+                            depth += 1;
+                            inner.find_child(*byte)?
+                        }
+                        None if key.len() == depth => node.end.as_ref()?,
+                        None => return None,
+                    };
+                    match child.get() {
+                        ChildRef::Node(child) => node = child,
+                        // no bytes were skipped on the way to an inlined value
+                        ChildRef::Value(value) => return (key.len() == depth).then_some(value),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Insert `value` under `key`, `depth` is the number of bytes of `key`
+    /// consumed before reaching this node and `optimistic` tells if some of
+    /// them were skipped because they didn't fit in a header.
+    ///
+    /// The nodes are updated in place, a new node is only allocated when a
+    /// node has to be split or to grow. Everything an update needs is
+    /// allocated before the tree is modified, so the tree is left untouched
+    /// when `alloc` runs out of memory.
+    fn insert<A: Allocator>(
+        &mut self,
+        key: &[u8],
+        depth: usize,
+        value: u64,
+        optimistic: bool,
+        alloc: &A,
+    ) -> Result<Option<u64>, ArtError> {
+        match &mut self.inner {
             InnerNode::Empty => {
-                self.path = input.to_vec();
-                self.inner = InnerNode::SingleValueLeaf(value);
-                None
+                *self = Node::leaf_in(key, value, alloc)?;
+                Ok(None)
             }
 
-            InnerNode::SingleValueLeaf(v) => {
+            InnerNode::SingleValueLeaf {
+                key: leaf_key,
+                value: leaf_value,
+            } => {
                 // is it the same value?
-                if input == self.path {
-                    self.inner = InnerNode::SingleValueLeaf(value);
-                    Some(v)
-                } else {
-                    let common_path: Vec<u8> = input
-                        .iter()
-                        .zip(&self.path)
-                        .take_while(|(a, b)| a == b)
-                        .map(|(a, _b)| *a)
-                        .collect();
-
-                    let original_node_path = &self.path[common_path.len()..];
-                    let new_path = &input[common_path.len()..];
-
-                    let mut node4 = Node4::default();
-                    let is_original_before_new = original_node_path > new_path;
-                    let original_node_pos = is_original_before_new as usize;
-                    let new_node_pos = !is_original_before_new as usize;
-                    if !original_node_path.is_empty() {
-                        node4.keys[original_node_pos] = Cell::Some(original_node_path[0]);
-                    } else {
-                        node4.keys[original_node_pos] = Cell::End;
+                if **leaf_key == *key {
+                    return Ok(Some(replace(leaf_value, value)));
+                }
+                // the leaf stores its whole key, we only need to create an
+                // inner node where the two keys diverge
+                let common = common_len(&key[depth..], &leaf_key[depth..]);
+                let split = depth + common;
+                let optimistic = optimistic || common > PREFIX_LEN;
+
+                let (byte, new) = leaf_child(key, value, split, optimistic, alloc)?;
+                let leaf = take(self);
+                let leaf_byte = leaf.path(split).first().copied();
+                let leaf_depth = split + usize::from(leaf_byte.is_some());
+                let leaf = match leaf.into_child_in(leaf_depth, optimistic, alloc) {
+                    Ok(leaf) => leaf,
+                    Err(leaf) => {
+                        *self = leaf;
+                        // Safety: the nodes of the tree are allocated by `alloc`
+                        unsafe { new.free_in(alloc) };
+                        return Err(ArtError::OutOfMemory);
                     }
-                    node4.values[original_node_pos] =
-                        Some(Box::new(Node::default().insert(original_node_path, v).0));
+                };
+                self.set_prefix(&key[depth..split]);
+                self.insert_child(leaf_byte, leaf);
+                self.insert_child(byte, new);
+                Ok(None)
+            }
 
-                    if !new_path.is_empty() {
-                        node4.keys[new_node_pos] = Cell::Some(new_path[0]);
-                    } else {
-                        node4.keys[new_node_pos] = Cell::End;
+            _ => {
+                let prefix_len = self.prefix_len as usize;
+                let common = self.prefix_mismatch(key, depth);
+                if common < prefix_len {
+                    // split the compressed path where it diverges from the key
+                    let prefix = self.path(depth).to_vec();
+                    let optimistic = optimistic || common > PREFIX_LEN;
+                    let (byte, new) = leaf_child(key, value, depth + common, optimistic, alloc)?;
+                    let mut child = match Ptr::try_new_in(take(self), alloc) {
+                        Ok(child) => child,
+                        Err(node) => {
+                            *self = node;
+                            // Safety: the nodes of the tree are allocated by `alloc`
+                            unsafe { new.free_in(alloc) };
+                            return Err(ArtError::OutOfMemory);
+                        }
+                    };
+                    child.set_prefix(&prefix[common + 1..]);
+                    self.set_prefix(&prefix[..common]);
+                    self.insert_child(Some(prefix[common]), Child::node(child));
+                    self.insert_child(byte, new);
+                    return Ok(None);
+                }
+
+                let optimistic = optimistic || self.is_truncated();
+                let depth = depth + prefix_len;
+                let (slot, child_depth) = match key.get(depth) {
+                    Some(byte) => (self.inner.child_mut(*byte), depth + 1),
+                    None => (self.end.is_some().then_some(&mut self.end), depth),
+                };
+                let Some(slot) = slot else {
+                    self.add_leaf(key, value, depth, optimistic, alloc)?;
+                    return Ok(None);
+                };
+                let depth = child_depth;
+                match slot.as_mut().unwrap().as_node_mut() {
+                    Some(node) => node.insert(key, depth, value, optimistic, alloc),
+                    None => {
+                        let ChildRef::Value(old_value) = slot.as_ref().unwrap().get() else {
+                            unreachable!("The slot stores a node")
+                        };
+                        if key.len() == depth {
+                            let (_, child) = leaf_child(key, value, depth, optimistic, alloc)?;
+                            *slot = Some(child);
+                            return Ok(Some(old_value));
+                        }
+                        // the key of the inlined value is a prefix of the new key
+                        let mut node = Node {
+                            end: Child::value(old_value),
+                            ..Default::default()
+                        };
+                        node.add_leaf(key, value, depth, optimistic, alloc)?;
+                        match Ptr::try_new_in(node, alloc) {
+                            Ok(node) => {
+                                *slot = Some(Child::node(node));
+                                Ok(None)
+                            }
+                            Err(node) => {
+                                // Safety: the nodes of the tree are allocated by `alloc`
+                                unsafe { node.free_in(alloc) };
+                                Err(ArtError::OutOfMemory)
+                            }
+                        }
                     }
-                    node4.values[new_node_pos] =
-                        Some(Box::new(Node::default().insert(new_path, value).0));
-                    // patch ourselves
-                    self.path = common_path;
-                    self.inner = InnerNode::Node4(node4);
-                    None
                 }
-                // (InnerNode::Node4(value), None)
             }
+        }
+    }
+
+    /// Add a leaf to an inner node, either as a child or as the value of the
+    /// node if its key ends here. `depth` is the number of bytes of its key
+    /// consumed before reaching the children of this node.
+    fn add_leaf<A: Allocator>(
+        &mut self,
+        key: &[u8],
+        value: u64,
+        depth: usize,
+        optimistic: bool,
+        alloc: &A,
+    ) -> Result<(), ArtError> {
+        if key.len() > depth {
+            self.grow(alloc)?;
+        }
+        let (byte, child) = leaf_child(key, value, depth, optimistic, alloc)?;
+        self.insert_child(byte, child);
+        Ok(())
+    }
+
+    /// Grow a full inner node to the next node type, so it has room for a
+    /// new child.
+    fn grow<A: Allocator>(&mut self, alloc: &A) -> Result<(), ArtError> {
+        let grown = match &mut self.inner {
+            InnerNode::Node4(node) if node.is_full() => {
+                InnerNode::Node16(Ptr::new_with(alloc, || Node16::from(take(node)))?)
+            }
+            InnerNode::Node16(node) if node.is_full() => {
+                InnerNode::Node48(Ptr::new_with(alloc, || Node48::from(take(&mut **node)))?)
+            }
+            InnerNode::Node48(node) if node.is_full() => {
+                InnerNode::Node256(Ptr::new_with(alloc, || Node256::from(take(&mut **node)))?)
+            }
+            _ => return Ok(()),
+        };
+        // Safety: the nodes of the tree are allocated by `alloc`, and the
+        // children were moved out of the old node
+        unsafe { replace(&mut self.inner, grown).free_in(alloc) };
+        Ok(())
+    }
+
+    /// Add a new child to an inner node that has room for it, `None` stores
+    /// the child as the value of the node.
+    fn insert_child(&mut self, key: Option<u8>, child: Child) {
+        let Some(key) = key else {
+            self.end = Some(child);
+            return;
+        };
+        match &mut self.inner {
+            InnerNode::Empty => {
+                let mut node = Node4::default();
+                node.insert(key, child);
+                self.inner = InnerNode::Node4(node);
+            }
+            InnerNode::Node4(node) => node.insert(key, child),
+            InnerNode::Node16(node) => node.insert(key, child),
+            InnerNode::Node48(node) => node.insert(key, child),
+            InnerNode::Node256(node) => node.insert(key, child),
+            InnerNode::SingleValueLeaf { .. } => unreachable!("Added a child to a leaf"),
+        }
+        self.nb_childrens += 1;
+    }
 
-            InnerNode::Node4(ref mut node) => match input.strip_prefix(self.path.as_slice()) {
-                Some([]) => {
-                    if node.keys[0] == Cell::End {
-                        let mut value_node = node.values[0].take().unwrap();
-                        let value_node_v = value_node.inner.unwrap_leaf();
-                        let old_value = *value_node_v;
-                        *value_node_v = value;
-                        node.values[0] = Some(value_node);
-                        Some(old_value)
-                    } else {
-                        if self.nb_childrens == 4 {
-                            let new_node = take(node).promote(0, &[], Cell::End, value);
-                            self.inner = InnerNode::Node16(new_node);
-                        } else {
-                            node.insert(0, &[], Cell::End, value);
+    /// Remove the value stored under `key`, `depth` is the number of bytes
+    /// of `key` consumed before reaching this node and `optimistic` tells if
+    /// some of them were skipped because they didn't fit in a header.
+    ///
+    /// The emptied nodes are freed and the nodes left with a single entry
+    /// are merged with it, see [`Node::compact`].
+    fn remove<A: Allocator>(
+        &mut self,
+        key: &[u8],
+        depth: usize,
+        optimistic: bool,
+        alloc: &A,
+    ) -> Option<u64> {
+        let node_depth = depth;
+        match &self.inner {
+            InnerNode::Empty => return None,
+            InnerNode::SingleValueLeaf {
+                key: leaf_key,
+                value,
+            } => {
+                // the skipped bytes of the truncated prefixes are verified here
+                if **leaf_key != *key {
+                    return None;
+                }
+                let value = *value;
+                // Safety: the nodes of the tree are allocated by `alloc`
+                unsafe { take(self).free_in(alloc) };
+                return Some(value);
+            }
+            _ => (),
+        }
+
+        let stored = self.stored_prefix();
+        if key.get(depth..depth + stored.len())? != stored {
+            return None;
+        }
+        let optimistic = optimistic || self.is_truncated();
+        let depth = depth + self.prefix_len as usize;
+        let byte = key.get(depth).copied();
+        if byte.is_none() && key.len() != depth {
+            return None;
+        }
+        let child_depth = depth + usize::from(byte.is_some());
+        let slot = match byte {
+            Some(byte) => self.inner.child_mut(byte)?,
+            None => &mut self.end,
+        };
+        let value = match slot.as_mut()?.as_node_mut() {
+            Some(node) => {
+                let value = node.remove(key, child_depth, optimistic, alloc)?;
+                match node.inner {
+                    InnerNode::Empty => value,
+                    // the node was merged with the leaf of its last entry,
+                    // which may now fit in the slot
+                    InnerNode::SingleValueLeaf {
+                        key: ref leaf_key,
+                        value: leaf_value,
+                    } => {
+                        let len = leaf_key.len();
+                        if let Some(child) = inline_value(len, child_depth, optimistic, leaf_value)
+                        {
+                            let leaf = slot.replace(child).unwrap();
+                            // Safety: the nodes of the tree are allocated by `alloc`
+                            unsafe { leaf.free_in(alloc) };
                         }
-                        None
+                        return Some(value);
                     }
+                    _ => return Some(value),
                 }
-                Some(s) => {
-                    if let Some(pos) = node
-                        .keys
-                        .iter()
-                        .position(|k| matches!(k, Cell::Some(b) if *b == s[0]))
-                    {
-                        let (new_node, old_value) =
-                            node.values[pos].take().unwrap().insert(s, value);
-                        node.values[pos] = Some(Box::new(new_node));
-                        old_value
-                    } else {
-                        if self.nb_childrens == 4 {
-                            let pos = node
-                                .keys
-                                .iter()
-                                .position(|k| matches!(k, Cell::Some(b) if *b > s[0]))
-                                .unwrap_or(4);
-
-                            let new_node = take(node).promote(pos, s, Cell::Some(s[0]), value);
-                            self.inner = InnerNode::Node16(new_node);
-                        } else {
-                            let pos = node
-                                .keys
-                                .iter()
-                                .position(|k| {
-                                    *k == Cell::None || matches!(k, Cell::Some(b) if *b > s[0])
-                                })
-                                .unwrap();
-                            node.insert(pos, s, Cell::Some(s[0]), value);
+            }
+            // no bytes were skipped on the way to an inlined value
+            None if key.len() == child_depth => match slot.as_ref()?.get() {
+                ChildRef::Value(value) => value,
+                ChildRef::Node(_) => unreachable!("The slot stores a value"),
+            },
+            None => return None,
+        };
+
+        let child = match byte {
+            Some(byte) => {
+                self.nb_childrens -= 1;
+                self.inner.remove(byte)
+            }
+            None => self.end.take(),
+        };
+        // Safety: the nodes of the tree are allocated by `alloc`
+        unsafe { child.unwrap().free_in(alloc) };
+        self.compact(key, node_depth, optimistic, alloc);
+        Some(value)
+    }
+
+    /// Restore the shape an inner node would have if the key just removed
+    /// below it had never been inserted. `depth` is the number of bytes
+    /// consumed before reaching the node and `key` the removed key, which
+    /// gives the bytes of the path when an inlined value has to become a
+    /// leaf again.
+    ///
+    /// A node left with a single entry is replaced by it, and a node with
+    /// few children shrinks to a smaller node type, with some slack so
+    /// alternating insertions and removals don't resize it every time.
+    /// When this needs memory that `alloc` can't provide, the node is
+    /// simply kept as is.
+    fn compact<A: Allocator>(&mut self, key: &[u8], depth: usize, optimistic: bool, alloc: &A) {
+        let end = depth + self.prefix_len as usize;
+        let replacement = match (self.nb_childrens, &self.end) {
+            (0, None) => Node::default(),
+            (0, Some(child)) => match child.get() {
+                ChildRef::Value(value) => match Node::leaf_in(&key[..end], value, alloc) {
+                    Ok(leaf) => leaf,
+                    Err(_) => return,
+                },
+                ChildRef::Node(_) => {
+                    let leaf = self.end.take().unwrap().into_node().unwrap();
+                    // Safety: the nodes of the tree are allocated by `alloc`
+                    unsafe { leaf.into_inner(alloc) }
+                }
+            },
+            (1, None) => {
+                let (byte, child) = self.inner.children().next().unwrap();
+                match child.get() {
+                    ChildRef::Value(value) => {
+                        let leaf_key = [&key[..end], &[byte]].concat();
+                        match Node::leaf_in(&leaf_key, value, alloc) {
+                            Ok(leaf) => leaf,
+                            Err(_) => return,
                         }
-                        None
                     }
-                }
-                None => {
-                    let common_path: Vec<u8> = input
-                        .iter()
-                        .zip(&self.path)
-                        .take_while(|(a, b)| a == b)
-                        .map(|(a, _b)| *a)
-                        .collect();
-
-                    let original_node_path = &self.path[common_path.len()..];
-                    let new_path = &input[common_path.len()..];
-
-                    let mut node4 = Node4::default();
-                    let is_original_before_new = original_node_path > new_path;
-                    let original_node_pos = is_original_before_new as usize;
-                    let new_node_pos = !is_original_before_new as usize;
-                    if !original_node_path.is_empty() {
-                        node4.keys[original_node_pos] = Cell::Some(original_node_path[0]);
-                    } else {
-                        node4.keys[original_node_pos] = Cell::End;
+                    ChildRef::Node(child) if child.inner.is_leaf() => {
+                        let leaf = self.inner.remove(byte).unwrap().into_node().unwrap();
+                        // Safety: the nodes of the tree are allocated by `alloc`
+                        unsafe { leaf.into_inner(alloc) }
                     }
-                    self.path = original_node_path.to_vec();
-                    node4.values[original_node_pos] = Some(Box::new(take(&mut self)));
-
-                    if !new_path.is_empty() {
-                        node4.keys[new_node_pos] = Cell::Some(new_path[0]);
-                    } else {
-                        node4.keys[new_node_pos] = Cell::End;
+                    ChildRef::Node(child) => {
+                        // the merged path may not fit in the header, which is
+                        // only allowed if no value is inlined below it
+                        let len = end - depth + 1 + child.prefix_len as usize;
+                        if len > PREFIX_LEN && !optimistic && !child.is_truncated() {
+                            return;
+                        }
+                        let path = [self.path(depth), &[byte], child.path(end + 1)].concat();
+                        let child = self.inner.remove(byte).unwrap().into_node().unwrap();
+                        // Safety: the nodes of the tree are allocated by `alloc`
+                        let mut child = unsafe { child.into_inner(alloc) };
+                        child.set_prefix(&path);
+                        child
                     }
-                    node4.values[new_node_pos] =
-                        Some(Box::new(Node::default().insert(new_path, value).0));
-                    // patch ourselves
-                    self = Default::default();
-                    self.path = common_path;
-                    self.inner = InnerNode::Node4(node4);
-
-                    None
                 }
-            },
-            // InnerNode::Node16(node) => node.insert(input),
-            // InnerNode::Node48(node) => node.insert(input),
-            // InnerNode::Node256(node) => node.insert(input),
-            _ => todo!(),
+            }
+            _ => return self.shrink(alloc),
+        };
+        // Safety: the nodes of the tree are allocated by `alloc`, and the
+        // entries left were moved out of the node
+        unsafe { replace(self, replacement).free_in(alloc) };
+    }
+
+    /// Shrink an inner node to a smaller node type if it has few children.
+    fn shrink<A: Allocator>(&mut self, alloc: &A) {
+        let len = self.nb_childrens;
+        let shrunk = match &mut self.inner {
+            InnerNode::Node16(node) if len <= 3 => InnerNode::Node4(Node4::from(take(&mut **node))),
+            InnerNode::Node48(node) if len <= 12 => {
+                match Ptr::new_with(alloc, || Node16::from(take(&mut **node))) {
+                    Ok(node) => InnerNode::Node16(node),
+                    Err(_) => return,
+                }
+            }
+            InnerNode::Node256(node) if len <= 37 => {
+                match Ptr::new_with(alloc, || Node48::from(take(&mut **node))) {
+                    Ok(node) => InnerNode::Node48(node),
+                    Err(_) => return,
+                }
+            }
+            _ => return,
         };
-        self.nb_childrens = self
-            .nb_childrens
-            .saturating_add(u64::from(old_value.is_none()));
-        (self, old_value)
+        // Safety: the nodes of the tree are allocated by `alloc`, and the
+        // children were moved out of the old node
+        unsafe { replace(&mut self.inner, shrunk).free_in(alloc) };
+    }
+
+    /// Free the node and everything below it.
+    ///
+    /// The nodes are freed from an explicit stack rather than recursively,
+    /// a loaded tree can be deeper than the call stack allows.
+    ///
+    /// # Safety
+    ///
+    /// The nodes must have been allocated by `alloc`.
+    unsafe fn free_in<A: Allocator>(self, alloc: &A) {
+        let mut nodes = vec![self];
+        while let Some(node) = nodes.pop() {
+            let children = node.inner.into_children_in(alloc).map(|(_, child)| child);
+            for child in node.end.into_iter().chain(children) {
+                if let Ok(child) = child.into_node() {
+                    nodes.push(child.into_inner(alloc));
+                }
+            }
+        }
     }
 }
 
-#[derive(Default, Debug)]
+/// Return the length of the longest common prefix of `a` and `b`.
+pub(crate) fn common_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// Return the slot storing `value` directly, if a key of `key_len` bytes
+/// ends at a slot reached after `depth` bytes.
+///
+/// That's the only case where the path and the slot imply the whole key:
+/// otherwise the bytes after the slot have to be kept in a leaf.
+fn inline_value(key_len: usize, depth: usize, optimistic: bool, value: u64) -> Option<Child> {
+    if !optimistic && key_len == depth {
+        Child::value(value)
+    } else {
+        None
+    }
+}
+
+/// Build the child storing `value` under `key` in an inner node whose
+/// children are reached after `depth` bytes, and the byte it is stored
+/// under, or `None` if the key ends at the node.
+///
+/// Nothing is allocated when the value can be stored in the slot.
+fn leaf_child<A: Allocator>(
+    key: &[u8],
+    value: u64,
+    depth: usize,
+    optimistic: bool,
+    alloc: &A,
+) -> Result<(Option<u8>, Child), ArtError> {
+    let byte = key.get(depth).copied();
+    let depth = depth + usize::from(byte.is_some());
+    if let Some(child) = inline_value(key.len(), depth, optimistic, value) {
+        return Ok((byte, child));
+    }
+    let leaf = Node::leaf_in(key, value, alloc)?;
+    match Ptr::try_new_in(leaf, alloc) {
+        Ok(leaf) => Ok((byte, Child::node(leaf))),
+        Err(leaf) => {
+            // Safety: the key was allocated by `alloc`
+            unsafe { leaf.free_in(alloc) };
+            Err(ArtError::OutOfMemory)
+        }
+    }
+}
+
+#[derive(Default)]
 pub(crate) enum InnerNode {
     #[default]
     Empty,
 
-    SingleValueLeaf(u64),
+    /// A leaf stores its full key, this lets us create inner nodes only
+    /// where keys diverge and verify the truncated prefixes of its parents.
+    /// When possible, the value is directly stored in the slot of its parent
+    /// instead, see [`Child`].
+    SingleValueLeaf {
+        key: Ptr<[u8]>,
+        value: u64,
+    },
 
     Node4(Node4),
-    Node16(Node16),
-    Node48(Node48),
-    Node256(Node256),
+    Node16(Ptr<Node16>),
+    Node48(Ptr<Node48>),
+    Node256(Ptr<Node256>),
+}
+
+impl fmt::Debug for InnerNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InnerNode::Empty => write!(f, "Empty"),
+            InnerNode::SingleValueLeaf { key, value } => f
+                .debug_struct("SingleValueLeaf")
+                .field("key", &debug_path(key))
+                .field("value", value)
+                .finish(),
+            InnerNode::Node4(node) => f.debug_tuple("Node4").field(node).finish(),
+            InnerNode::Node16(node) => f.debug_tuple("Node16").field(node).finish(),
+            InnerNode::Node48(node) => f.debug_tuple("Node48").field(node).finish(),
+            InnerNode::Node256(node) => f.debug_tuple("Node256").field(node).finish(),
+        }
+    }
 }
 
 impl InnerNode {
-    #[track_caller]
-    pub fn unwrap_leaf(&mut self) -> &mut u64 {
-        if let Self::SingleValueLeaf(ref mut value) = self {
-            value
-        } else {
-            panic!("Unwrapped a non-leaf in `unwrap_leaf`")
+    /// Name of the node type, as used in the exported graphs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InnerNode::Empty => "Empty",
+            InnerNode::SingleValueLeaf { .. } => "Leaf",
+            InnerNode::Node4(_) => "Node4",
+            InnerNode::Node16(_) => "Node16",
+            InnerNode::Node48(_) => "Node48",
+            InnerNode::Node256(_) => "Node256",
+        }
+    }
+
+    /// Whether the node is empty or a leaf, that is it has no children.
+    pub fn is_leaf(&self) -> bool {
+        matches!(self, InnerNode::Empty | InnerNode::SingleValueLeaf { .. })
+    }
+
+    /// Iterate over the children of the node in key order.
+    pub fn children(&self) -> Box<dyn Iterator<Item = (u8, &Child)> + '_> {
+        match self {
+            InnerNode::Empty | InnerNode::SingleValueLeaf { .. } => Box::new(core::iter::empty()),
+            InnerNode::Node4(node) => Box::new(node.children()),
+            InnerNode::Node16(node) => Box::new(node.children()),
+            InnerNode::Node48(node) => Box::new(node.children()),
+            InnerNode::Node256(node) => Box::new(node.children()),
+        }
+    }
+
+    pub fn find_child(&self, key: u8) -> Option<&Child> {
+        match self {
+            InnerNode::Empty | InnerNode::SingleValueLeaf { .. } => None,
+            InnerNode::Node4(node) => node.find_child(key),
+            InnerNode::Node16(node) => node.find_child(key),
+            InnerNode::Node48(node) => node.find_child(key),
+            InnerNode::Node256(node) => node.find_child(key),
+        }
+    }
+
+    pub fn child_mut(&mut self, key: u8) -> Option<&mut Option<Child>> {
+        match self {
+            InnerNode::Empty | InnerNode::SingleValueLeaf { .. } => None,
+            InnerNode::Node4(node) => node.child_mut(key),
+            InnerNode::Node16(node) => node.child_mut(key),
+            InnerNode::Node48(node) => node.child_mut(key),
+            InnerNode::Node256(node) => node.child_mut(key),
+        }
+    }
+
+    pub fn remove(&mut self, key: u8) -> Option<Child> {
+        match self {
+            InnerNode::Empty | InnerNode::SingleValueLeaf { .. } => None,
+            InnerNode::Node4(node) => node.remove(key),
+            InnerNode::Node16(node) => node.remove(key),
+            InnerNode::Node48(node) => node.remove(key),
+            InnerNode::Node256(node) => node.remove(key),
+        }
+    }
+
+    /// Free the node and everything below it.
+    ///
+    /// # Safety
+    ///
+    /// The nodes must have been allocated by `alloc`.
+    unsafe fn free_in<A: Allocator>(self, alloc: &A) {
+        self.into_children_in(alloc)
+            .for_each(|(_, child)| child.free_in(alloc));
+    }
+
+    /// Free the memory of the node itself and return its children.
+    ///
+    /// # Safety
+    ///
+    /// The node must have been allocated by `alloc`.
+    unsafe fn into_children_in<A: Allocator>(
+        self,
+        alloc: &A,
+    ) -> Box<dyn Iterator<Item = (u8, Child)>> {
+        match self {
+            InnerNode::Empty => Box::new(core::iter::empty()),
+            InnerNode::SingleValueLeaf { key, .. } => {
+                key.free_in(alloc);
+                Box::new(core::iter::empty())
+            }
+            InnerNode::Node4(node) => Box::new(node.into_children()),
+            InnerNode::Node16(node) => Box::new(node.into_inner(alloc).into_children()),
+            InnerNode::Node48(node) => Box::new(node.into_inner(alloc).into_children()),
+            InnerNode::Node256(node) => Box::new(node.into_inner(alloc).into_children()),
         }
     }
 }
 
-#[derive(Default, PartialEq, Debug)]
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
 pub enum Cell {
-    End,
     #[default]
     None,
     Some(u8),
@@ -229,38 +884,132 @@ impl Cell {
     pub fn is_none(&self) -> bool {
         matches!(self, Cell::None)
     }
+
+    /// Return where `self` must be inserted in sorted `keys`, the empty
+    /// cells are kept at the end.
+    pub(crate) fn insert_position(&self, keys: &[Cell]) -> usize {
+        keys.iter()
+            .position(|key| match (key, self) {
+                (Cell::None, _) => true,
+                (Cell::Some(a), Cell::Some(b)) => a > b,
+                _ => false,
+            })
+            .unwrap_or(keys.len())
+    }
 }
 
-impl std::fmt::Display for Cell {
+impl fmt::Display for Cell {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Cell::Some(k) => write!(f, "`{}`", *k as char),
+            Cell::Some(k) => write!(f, "`{}`", (*k as char).escape_debug()),
             Cell::None => write!(f, "___"),
-            Cell::End => write!(f, "END"),
         }
     }
 }
 
-#[derive(Default, Debug)]
-pub struct Art {
+/// An Adaptive Radix Tree mapping byte strings to `u64`.
+///
+/// All the nodes and keys of the tree are allocated by `A`, see
+/// [`Art::new_in`].
+pub struct Art<A: Allocator = Global> {
     root: Node,
+    alloc: A,
+    /// The multi-level nodes and access statistics, see [`Art::optimize`].
+    tuning: tuning::Tuning,
+    /// The hashes of the entries under each node, see [`Art::with_hashes`].
+    hashes: Option<Box<hashes::Hashes>>,
 }
 
 impl Art {
     pub fn new() -> Art {
-        Default::default()
+        Art::new_in(Global)
     }
+}
+
+impl Default for Art {
+    fn default() -> Self {
+        Art::new()
+    }
+}
 
+impl<A: Allocator> Art<A> {
+    /// Create a tree whose nodes and keys are allocated by `alloc`, e.g. a
+    /// pool with a budget, the insertions then fail with
+    /// [`ArtError::OutOfMemory`] instead of aborting once it is exhausted.
+    pub fn new_in(alloc: A) -> Self {
+        Art {
+            root: Node::default(),
+            alloc,
+            tuning: tuning::Tuning::default(),
+            hashes: None,
+        }
+    }
+
+    /// Insert `value` under `input` and return the previous value, or
+    /// leave the tree untouched if the allocator fails.
+    pub fn try_insert(&mut self, input: &[u8], value: u64) -> Result<Option<u64>, ArtError> {
+        self.untune(input);
+        let old = self.root.insert(input, 0, value, false, &self.alloc)?;
+        if let Some(hashes) = &mut self.hashes {
+            hashes.insert(&self.root, input, old, value);
+        }
+        Ok(old)
+    }
+
+    /// Insert `value` under `input` and return the previous value.
+    ///
+    /// # Panics
+    ///
+    /// If the allocator fails, see [`Art::try_insert`].
     pub fn insert(&mut self, input: &[u8], value: u64) -> Option<u64> {
-        let this = std::mem::take(&mut self.root);
-        let old_value;
-        (self.root, old_value) = this.insert(input, value);
-        old_value
+        self.try_insert(input, value).expect("Out of memory")
+    }
+
+    /// Remove the value stored under `key` and return it.
+    ///
+    /// The tree keeps the shape it would have without this key, its nodes
+    /// are merged or shrunk when possible.
+    pub fn remove(&mut self, key: &[u8]) -> Option<u64> {
+        self.untune(key);
+        let Some(hashes) = &mut self.hashes else {
+            return self.root.remove(key, 0, false, &self.alloc);
+        };
+        let before = hashes::Hashes::branches(&self.root, key);
+        let value = self.root.remove(key, 0, false, &self.alloc)?;
+        hashes.remove(&self.root, key, value, &before);
+        Some(value)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<u64> {
+        self.tuned_get(key)
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+}
+
+impl<A: Allocator> fmt::Debug for Art<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Art").field("root", &self.root).finish()
+    }
+}
+
+impl<A: Allocator> Drop for Art<A> {
+    fn drop(&mut self) {
+        // Safety: all the nodes of the tree and its tuning state are
+        // allocated by `self.alloc`
+        unsafe {
+            take(&mut self.root).free_in(&self.alloc);
+            self.tuning.free_in(&self.alloc);
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::vec::Vec;
+
     use super::*;
 
     #[test]
@@ -270,7 +1019,8 @@ mod test {
         Art {
             root: Node {
                 nb_childrens: 0,
-                path: "`` ([])",
+                prefix: "`` ([])",
+                end: None,
                 inner: Empty,
             },
         }
@@ -285,11 +1035,13 @@ mod test {
         insta::assert_debug_snapshot!(art, @r###"
         Art {
             root: Node {
-                nb_childrens: 1,
-                path: "`hello` ([104, 101, 108, 108, 111])",
-                inner: SingleValueLeaf(
-                    42,
-                ),
+                nb_childrens: 0,
+                prefix: "`` ([])",
+                end: None,
+                inner: SingleValueLeaf {
+                    key: "`hello` ([104, 101, 108, 108, 111])",
+                    value: 42,
+                },
             },
         }
         "###);
@@ -309,11 +1061,13 @@ mod test {
         insta::assert_debug_snapshot!(art, @r###"
         Art {
             root: Node {
-                nb_childrens: 1,
-                path: "`hello` ([104, 101, 108, 108, 111])",
-                inner: SingleValueLeaf(
-                    43,
-                ),
+                nb_childrens: 0,
+                prefix: "`` ([])",
+                end: None,
+                inner: SingleValueLeaf {
+                    key: "`hello` ([104, 101, 108, 108, 111])",
+                    value: 43,
+                },
             },
         }
         "###);
@@ -330,11 +1084,12 @@ mod test {
         Art {
             root: Node {
                 nb_childrens: 2,
-                path: "`` ([])",
+                prefix: "`` ([])",
+                end: None,
                 inner: Node4(
                     Node {
                         keys: "[\"`h`\", \"`w`\", \"___\", \"___\"]",
-                        values: "[Some(Node { nb_childrens: 1, path: \"`hello` ([104, 101, 108, 108, 111])\", inner: SingleValueLeaf(42) }), Some(Node { nb_childrens: 1, path: \"`world` ([119, 111, 114, 108, 100])\", inner: SingleValueLeaf(43) }), None, None]",
+                        values: "[Some(Node { nb_childrens: 0, prefix: \"`` ([])\", end: None, inner: SingleValueLeaf { key: \"`hello` ([104, 101, 108, 108, 111])\", value: 42 } }), Some(Node { nb_childrens: 0, prefix: \"`` ([])\", end: None, inner: SingleValueLeaf { key: \"`world` ([119, 111, 114, 108, 100])\", value: 43 } }), None, None]",
                     },
                 ),
             },
@@ -353,11 +1108,12 @@ mod test {
         Art {
             root: Node {
                 nb_childrens: 2,
-                path: "`hell` ([104, 101, 108, 108])",
+                prefix: "`hell` ([104, 101, 108, 108])",
+                end: None,
                 inner: Node4(
                     Node {
                         keys: "[\"`a`\", \"`o`\", \"___\", \"___\"]",
-                        values: "[Some(Node { nb_childrens: 1, path: \"`a` ([97])\", inner: SingleValueLeaf(43) }), Some(Node { nb_childrens: 1, path: \"`o` ([111])\", inner: SingleValueLeaf(42) }), None, None]",
+                        values: "[Some(Value(43)), Some(Value(42)), None, None]",
                     },
                 ),
             },
@@ -375,12 +1131,17 @@ mod test {
         insta::assert_debug_snapshot!(art, @r###"
         Art {
             root: Node {
-                nb_childrens: 2,
-                path: "`hell` ([104, 101, 108, 108])",
+                nb_childrens: 1,
+                prefix: "`hell` ([104, 101, 108, 108])",
+                end: Some(
+                    Value(
+                        43,
+                    ),
+                ),
                 inner: Node4(
                     Node {
-                        keys: "[\"END\", \"`o`\", \"___\", \"___\"]",
-                        values: "[Some(Node { nb_childrens: 1, path: \"`` ([])\", inner: SingleValueLeaf(43) }), Some(Node { nb_childrens: 1, path: \"`o` ([111])\", inner: SingleValueLeaf(42) }), None, None]",
+                        keys: "[\"`o`\", \"___\", \"___\", \"___\"]",
+                        values: "[Some(Value(42)), None, None, None]",
                     },
                 ),
             },
@@ -403,12 +1164,17 @@ mod test {
         insta::assert_debug_snapshot!(art, @r###"
         Art {
             root: Node {
-                nb_childrens: 2,
-                path: "`hell` ([104, 101, 108, 108])",
+                nb_childrens: 1,
+                prefix: "`hell` ([104, 101, 108, 108])",
+                end: Some(
+                    Value(
+                        44,
+                    ),
+                ),
                 inner: Node4(
                     Node {
-                        keys: "[\"END\", \"`o`\", \"___\", \"___\"]",
-                        values: "[Some(Node { nb_childrens: 1, path: \"`` ([])\", inner: SingleValueLeaf(44) }), Some(Node { nb_childrens: 1, path: \"`o` ([111])\", inner: SingleValueLeaf(42) }), None, None]",
+                        keys: "[\"`o`\", \"___\", \"___\", \"___\"]",
+                        values: "[Some(Value(42)), None, None, None]",
                     },
                 ),
             },
@@ -427,12 +1193,17 @@ mod test {
         insta::assert_debug_snapshot!(art, @r###"
         Art {
             root: Node {
-                nb_childrens: 3,
-                path: "`hell` ([104, 101, 108, 108])",
+                nb_childrens: 2,
+                prefix: "`hell` ([104, 101, 108, 108])",
+                end: Some(
+                    Value(
+                        44,
+                    ),
+                ),
                 inner: Node4(
                     Node {
-                        keys: "[\"END\", \"`a`\", \"`o`\", \"___\"]",
-                        values: "[Some(Node { nb_childrens: 1, path: \"`` ([])\", inner: SingleValueLeaf(44) }), Some(Node { nb_childrens: 1, path: \"`a` ([97])\", inner: SingleValueLeaf(43) }), Some(Node { nb_childrens: 1, path: \"`o` ([111])\", inner: SingleValueLeaf(42) }), None]",
+                        keys: "[\"`a`\", \"`o`\", \"___\", \"___\"]",
+                        values: "[Some(Value(43)), Some(Value(42)), None, None]",
                     },
                 ),
             },
@@ -453,94 +1224,17 @@ mod test {
         insta::assert_debug_snapshot!(art, @r###"
         Art {
             root: Node {
-                nb_childrens: 5,
-                path: "`hell` ([104, 101, 108, 108])",
-                inner: Node16(
-                    Node16 {
-                        keys: [
-                            End,
-                            Some(
-                                97,
-                            ),
-                            Some(
-                                105,
-                            ),
-                            Some(
-                                111,
-                            ),
-                            Some(
-                                117,
-                            ),
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                        ],
-                        values: [
-                            Some(
-                                Node {
-                                    nb_childrens: 1,
-                                    path: "`` ([])",
-                                    inner: SingleValueLeaf(
-                                        46,
-                                    ),
-                                },
-                            ),
-                            Some(
-                                Node {
-                                    nb_childrens: 1,
-                                    path: "`a` ([97])",
-                                    inner: SingleValueLeaf(
-                                        43,
-                                    ),
-                                },
-                            ),
-                            Some(
-                                Node {
-                                    nb_childrens: 1,
-                                    path: "`i` ([105])",
-                                    inner: SingleValueLeaf(
-                                        44,
-                                    ),
-                                },
-                            ),
-                            Some(
-                                Node {
-                                    nb_childrens: 1,
-                                    path: "`o` ([111])",
-                                    inner: SingleValueLeaf(
-                                        42,
-                                    ),
-                                },
-                            ),
-                            Some(
-                                Node {
-                                    nb_childrens: 1,
-                                    path: "`u` ([117])",
-                                    inner: SingleValueLeaf(
-                                        45,
-                                    ),
-                                },
-                            ),
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                        ],
+                nb_childrens: 4,
+                prefix: "`hell` ([104, 101, 108, 108])",
+                end: Some(
+                    Value(
+                        46,
+                    ),
+                ),
+                inner: Node4(
+                    Node {
+                        keys: "[\"`a`\", \"`i`\", \"`o`\", \"`u`\"]",
+                        values: "[Some(Value(43)), Some(Value(44)), Some(Value(42)), Some(Value(45))]",
                     },
                 ),
             },
@@ -555,7 +1249,8 @@ mod test {
         Art {
             root: Node {
                 nb_childrens: 0,
-                path: "`` ([])",
+                prefix: "`` ([])",
+                end: None,
                 inner: Empty,
             },
         }
@@ -564,11 +1259,13 @@ mod test {
         insta::assert_debug_snapshot!(art, @r###"
         Art {
             root: Node {
-                nb_childrens: 1,
-                path: "`hello` ([104, 101, 108, 108, 111])",
-                inner: SingleValueLeaf(
-                    42,
-                ),
+                nb_childrens: 0,
+                prefix: "`` ([])",
+                end: None,
+                inner: SingleValueLeaf {
+                    key: "`hello` ([104, 101, 108, 108, 111])",
+                    value: 42,
+                },
             },
         }
         "###);
@@ -577,11 +1274,12 @@ mod test {
         Art {
             root: Node {
                 nb_childrens: 2,
-                path: "`hell` ([104, 101, 108, 108])",
+                prefix: "`hell` ([104, 101, 108, 108])",
+                end: None,
                 inner: Node4(
                     Node {
                         keys: "[\"`a`\", \"`o`\", \"___\", \"___\"]",
-                        values: "[Some(Node { nb_childrens: 1, path: \"`a` ([97])\", inner: SingleValueLeaf(43) }), Some(Node { nb_childrens: 1, path: \"`o` ([111])\", inner: SingleValueLeaf(42) }), None, None]",
+                        values: "[Some(Value(43)), Some(Value(42)), None, None]",
                     },
                 ),
             },
@@ -592,11 +1290,12 @@ mod test {
         Art {
             root: Node {
                 nb_childrens: 3,
-                path: "`hell` ([104, 101, 108, 108])",
+                prefix: "`hell` ([104, 101, 108, 108])",
+                end: None,
                 inner: Node4(
                     Node {
                         keys: "[\"`a`\", \"`i`\", \"`o`\", \"___\"]",
-                        values: "[Some(Node { nb_childrens: 1, path: \"`a` ([97])\", inner: SingleValueLeaf(43) }), Some(Node { nb_childrens: 1, path: \"`i` ([105])\", inner: SingleValueLeaf(44) }), Some(Node { nb_childrens: 1, path: \"`o` ([111])\", inner: SingleValueLeaf(42) }), None]",
+                        values: "[Some(Value(43)), Some(Value(44)), Some(Value(42)), None]",
                     },
                 ),
             },
@@ -607,11 +1306,12 @@ mod test {
         Art {
             root: Node {
                 nb_childrens: 4,
-                path: "`hell` ([104, 101, 108, 108])",
+                prefix: "`hell` ([104, 101, 108, 108])",
+                end: None,
                 inner: Node4(
                     Node {
                         keys: "[\"`a`\", \"`i`\", \"`o`\", \"`u`\"]",
-                        values: "[Some(Node { nb_childrens: 1, path: \"`a` ([97])\", inner: SingleValueLeaf(43) }), Some(Node { nb_childrens: 1, path: \"`i` ([105])\", inner: SingleValueLeaf(44) }), Some(Node { nb_childrens: 1, path: \"`o` ([111])\", inner: SingleValueLeaf(42) }), Some(Node { nb_childrens: 1, path: \"`u` ([117])\", inner: SingleValueLeaf(45) })]",
+                        values: "[Some(Value(43)), Some(Value(44)), Some(Value(42)), Some(Value(45))]",
                     },
                 ),
             },
@@ -633,81 +1333,59 @@ mod test {
         Art {
             root: Node {
                 nb_childrens: 5,
-                path: "`hell` ([104, 101, 108, 108])",
+                prefix: "`hell` ([104, 101, 108, 108])",
+                end: None,
                 inner: Node16(
                     Node16 {
+                        len: 5,
                         keys: [
-                            Some(
-                                97,
-                            ),
-                            Some(
-                                105,
-                            ),
-                            Some(
-                                111,
-                            ),
-                            Some(
-                                117,
-                            ),
-                            Some(
-                                121,
-                            ),
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
-                            None,
+                            97,
+                            105,
+                            111,
+                            117,
+                            121,
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
                         ],
                         values: [
                             Some(
-                                Node {
-                                    nb_childrens: 1,
-                                    path: "`a` ([97])",
-                                    inner: SingleValueLeaf(
-                                        43,
-                                    ),
-                                },
+                                Value(
+                                    43,
+                                ),
                             ),
                             Some(
-                                Node {
-                                    nb_childrens: 1,
-                                    path: "`i` ([105])",
-                                    inner: SingleValueLeaf(
-                                        44,
-                                    ),
-                                },
+                                Value(
+                                    44,
+                                ),
                             ),
                             Some(
-                                Node {
-                                    nb_childrens: 1,
-                                    path: "`o` ([111])",
-                                    inner: SingleValueLeaf(
-                                        42,
-                                    ),
-                                },
+                                Value(
+                                    42,
+                                ),
                             ),
                             Some(
-                                Node {
-                                    nb_childrens: 1,
-                                    path: "`u` ([117])",
-                                    inner: SingleValueLeaf(
-                                        45,
-                                    ),
-                                },
+                                Value(
+                                    45,
+                                ),
                             ),
                             Some(
                                 Node {
-                                    nb_childrens: 1,
-                                    path: "`yolo` ([121, 111, 108, 111])",
-                                    inner: SingleValueLeaf(
-                                        46,
-                                    ),
+                                    nb_childrens: 0,
+                                    prefix: "`` ([])",
+                                    end: None,
+                                    inner: SingleValueLeaf {
+                                        key: "`hellyolo` ([104, 101, 108, 108, 121, 111, 108, 111])",
+                                        value: 46,
+                                    },
                                 },
                             ),
                             None,
@@ -741,11 +1419,12 @@ mod test {
         Art {
             root: Node {
                 nb_childrens: 3,
-                path: "`hell` ([104, 101, 108, 108])",
+                prefix: "`hell` ([104, 101, 108, 108])",
+                end: None,
                 inner: Node4(
                     Node {
                         keys: "[\"`a`\", \"`i`\", \"`o`\", \"___\"]",
-                        values: "[Some(Node { nb_childrens: 1, path: \"`a` ([97])\", inner: SingleValueLeaf(43) }), Some(Node { nb_childrens: 1, path: \"`i` ([105])\", inner: SingleValueLeaf(44) }), Some(Node { nb_childrens: 1, path: \"`o` ([111])\", inner: SingleValueLeaf(42) }), None]",
+                        values: "[Some(Value(43)), Some(Value(44)), Some(Value(42)), None]",
                     },
                 ),
             },
@@ -764,16 +1443,527 @@ mod test {
         insta::assert_debug_snapshot!(art, @r###"
         Art {
             root: Node {
-                nb_childrens: 1,
-                path: "`he` ([104, 101])",
+                nb_childrens: 2,
+                prefix: "`he` ([104, 101])",
+                end: None,
                 inner: Node4(
                     Node {
                         keys: "[\"`l`\", \"`y`\", \"___\", \"___\"]",
-                        values: "[Some(Node { nb_childrens: 2, path: \"`ll` ([108, 108])\", inner: Node4(Node { keys: \"[\\\"`a`\\\", \\\"`o`\\\", \\\"___\\\", \\\"___\\\"]\", values: \"[Some(Node { nb_childrens: 1, path: \\\"`a` ([97])\\\", inner: SingleValueLeaf(43) }), Some(Node { nb_childrens: 1, path: \\\"`o` ([111])\\\", inner: SingleValueLeaf(42) }), None, None]\" }) }), Some(Node { nb_childrens: 1, path: \"`y` ([121])\", inner: SingleValueLeaf(44) }), None, None]",
+                        values: "[Some(Node { nb_childrens: 2, prefix: \"`l` ([108])\", end: None, inner: Node4(Node { keys: \"[\\\"`a`\\\", \\\"`o`\\\", \\\"___\\\", \\\"___\\\"]\", values: \"[Some(Value(43)), Some(Value(42)), None, None]\" }) }), Some(Value(44)), None, None]",
                     },
                 ),
             },
         }
         "###);
     }
+
+    #[test]
+    fn get() {
+        let mut art = Art::new();
+        insta::assert_debug_snapshot!(art.get(b"hello"), @"None");
+        art.insert(b"hello", 42);
+        art.insert(b"hella", 43);
+        art.insert(b"hell", 44);
+        art.insert(b"hey", 45);
+
+        insta::assert_debug_snapshot!(art.get(b"hello"), @r###"
+        Some(
+            42,
+        )
+        "###);
+        insta::assert_debug_snapshot!(art.get(b"hell"), @r###"
+        Some(
+            44,
+        )
+        "###);
+        insta::assert_debug_snapshot!(art.get(b"hey"), @r###"
+        Some(
+            45,
+        )
+        "###);
+        insta::assert_debug_snapshot!(art.get(b"he"), @"None");
+        insta::assert_debug_snapshot!(art.get(b"hellos"), @"None");
+        insta::assert_debug_snapshot!(art.get(b"hellu"), @"None");
+        insta::assert_debug_snapshot!(art.get(b""), @"None");
+    }
+
+    #[test]
+    fn truncated_prefix() {
+        let mut art = Art::new();
+        art.insert(b"a long shared prefix: hello", 42);
+        art.insert(b"a long shared prefix: world", 43);
+        insta::assert_debug_snapshot!(art, @r###"
+        Art {
+            root: Node {
+                nb_childrens: 2,
+                prefix: "`a long s` ([97, 32, 108, 111, 110, 103, 32, 115]) + 14 bytes",
+                end: None,
+                inner: Node4(
+                    Node {
+                        keys: "[\"`h`\", \"`w`\", \"___\", \"___\"]",
+                        values: "[Some(Node { nb_childrens: 0, prefix: \"`` ([])\", end: None, inner: SingleValueLeaf { key: \"`a long shared prefix: hello` ([97, 32, 108, 111, 110, 103, 32, 115, 104, 97, 114, 101, 100, 32, 112, 114, 101, 102, 105, 120, 58, 32, 104, 101, 108, 108, 111])\", value: 42 } }), Some(Node { nb_childrens: 0, prefix: \"`` ([])\", end: None, inner: SingleValueLeaf { key: \"`a long shared prefix: world` ([97, 32, 108, 111, 110, 103, 32, 115, 104, 97, 114, 101, 100, 32, 112, 114, 101, 102, 105, 120, 58, 32, 119, 111, 114, 108, 100])\", value: 43 } }), None, None]",
+                    },
+                ),
+            },
+        }
+        "###);
+
+        insta::assert_debug_snapshot!(art.get(b"a long shared prefix: hello"), @r###"
+        Some(
+            42,
+        )
+        "###);
+        // only differs in the bytes that aren't stored in the header
+        insta::assert_debug_snapshot!(art.get(b"a long shaped prefix: hello"), @"None");
+
+        // split the compressed path after the stored bytes
+        art.insert(b"a long shaped prefix: hello", 44);
+        insta::assert_snapshot!(art.display_tree(), @r###"
+        "a long sha" [Node4]
+        ├── `p` "ed prefix: hello" → 44
+        └── `r` "ed prefix: " [Node4]
+            ├── `h` "ello" → 42
+            └── `w` "orld" → 43
+        "###);
+        insta::assert_debug_snapshot!(art.get(b"a long shared prefix: hello"), @r###"
+        Some(
+            42,
+        )
+        "###);
+        insta::assert_debug_snapshot!(art.get(b"a long shaped prefix: hello"), @r###"
+        Some(
+            44,
+        )
+        "###);
+        insta::assert_debug_snapshot!(art.get(b"a long shared prefix: world"), @r###"
+        Some(
+            43,
+        )
+        "###);
+    }
+
+    #[test]
+    fn prefix_keys() {
+        let mut art = Art::new();
+        let long = b"a long shared prefix".as_slice();
+        for (i, key) in [
+            b"".as_slice(),
+            &[0],
+            &[0, 0],
+            long,
+            b"a long shared prefix: x",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(art.insert(key, i as u64), None);
+        }
+        insta::assert_snapshot!(art.display_tree(), @r###"
+        [Node4]
+        ├── END → 0
+        ├── 0x00 [Node4]
+        │   ├── END → 1
+        │   └── 0x00 → 2
+        └── `a` " long shared prefix" [Node4]
+            ├── END → 3
+            └── `:` " x" → 4
+        "###);
+        assert_eq!(art.get(b""), Some(0));
+        assert_eq!(art.get(&[0]), Some(1));
+        assert_eq!(art.get(&[0, 0]), Some(2));
+        assert_eq!(art.get(long), Some(3));
+        assert_eq!(art.get(b"a long shared prefiX"), None);
+        assert_eq!(art.get(b"a long shared"), None);
+
+        assert_eq!(art.insert(b"", 10), Some(0));
+        assert_eq!(art.insert(&[0], 11), Some(1));
+        assert_eq!(art.insert(long, 13), Some(3));
+        assert_eq!(art.get(b""), Some(10));
+        assert_eq!(art.get(&[0]), Some(11));
+        assert_eq!(art.get(long), Some(13));
+    }
+
+    #[test]
+    fn grow_to_node256() {
+        let mut art = Art::new();
+        art.insert(b"", 0);
+        for i in 0..=255 {
+            art.insert(&[i, i], u64::from(i));
+            let kind = art.root.inner.kind();
+            match i {
+                0..=3 => assert_eq!(kind, "Node4", "{i}"),
+                4..=15 => assert_eq!(kind, "Node16", "{i}"),
+                16..=47 => assert_eq!(kind, "Node48", "{i}"),
+                _ => assert_eq!(kind, "Node256", "{i}"),
+            }
+        }
+        assert_eq!(art.root.nb_childrens, 256);
+        assert_eq!(art.get(b""), Some(0));
+        for i in 0..=255 {
+            assert_eq!(art.get(&[i, i]), Some(u64::from(i)));
+            assert_eq!(art.get(&[i]), None);
+            assert_eq!(art.get(&[i, i.wrapping_add(1)]), None);
+        }
+    }
+
+    #[test]
+    fn insert_many_values() {
+        let mut art = Art::new();
+        let mut map = std::collections::BTreeMap::new();
+        for (i, rng) in (0..10_000).zip(test_rng()) {
+            // mix long keys, short keys, keys prefix of each other and
+            // compressed paths longer than what fits in the header
+            let prefix = [&b""[..], b"a long shared prefix", b"a long shared prefiX"];
+            let prefix = prefix[(rng >> 32) as usize % 3];
+            let len = (rng % 12) as usize;
+            let key = &rng.to_be_bytes()[..len.min(8)];
+            let key = [prefix, key, &b"-suffix"[..len.saturating_sub(8)]].concat();
+            assert_eq!(art.insert(&key, i), map.insert(key.clone(), i), "{key:?}");
+        }
+        for (key, value) in &map {
+            assert_eq!(art.get(key), Some(*value), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn inline_values() {
+        let mut art = Art::new();
+        art.insert(b"ab", 1);
+        // doesn't fit in 63 bits
+        art.insert(b"ac", u64::MAX);
+        // the key of an inlined value is a prefix of the new key
+        art.insert(b"abc", 3);
+        // the values can't be inlined below a truncated path
+        art.insert(b"0123456789a", 4);
+        art.insert(b"0123456789b", 5);
+        insta::assert_debug_snapshot!(art, @r###"
+        Art {
+            root: Node {
+                nb_childrens: 2,
+                prefix: "`` ([])",
+                end: None,
+                inner: Node4(
+                    Node {
+                        keys: "[\"`0`\", \"`a`\", \"___\", \"___\"]",
+                        values: "[Some(Node { nb_childrens: 2, prefix: \"`12345678` ([49, 50, 51, 52, 53, 54, 55, 56]) + 1 bytes\", end: None, inner: Node4(Node { keys: \"[\\\"`a`\\\", \\\"`b`\\\", \\\"___\\\", \\\"___\\\"]\", values: \"[Some(Node { nb_childrens: 0, prefix: \\\"`` ([])\\\", end: None, inner: SingleValueLeaf { key: \\\"`0123456789a` ([48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 97])\\\", value: 4 } }), Some(Node { nb_childrens: 0, prefix: \\\"`` ([])\\\", end: None, inner: SingleValueLeaf { key: \\\"`0123456789b` ([48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 98])\\\", value: 5 } }), None, None]\" }) }), Some(Node { nb_childrens: 2, prefix: \"`` ([])\", end: None, inner: Node4(Node { keys: \"[\\\"`b`\\\", \\\"`c`\\\", \\\"___\\\", \\\"___\\\"]\", values: \"[Some(Node { nb_childrens: 1, prefix: \\\"`` ([])\\\", end: Some(Value(1)), inner: Node4(Node { keys: \\\"[\\\\\\\"`c`\\\\\\\", \\\\\\\"___\\\\\\\", \\\\\\\"___\\\\\\\", \\\\\\\"___\\\\\\\"]\\\", values: \\\"[Some(Value(3)), None, None, None]\\\" }) }), Some(Node { nb_childrens: 0, prefix: \\\"`` ([])\\\", end: None, inner: SingleValueLeaf { key: \\\"`ac` ([97, 99])\\\", value: 18446744073709551615 } }), None, None]\" }) }), None, None]",
+                    },
+                ),
+            },
+        }
+        "###);
+
+        let entries: Vec<_> = art
+            .iter()
+            .map(|(key, value)| (String::from_utf8(key).unwrap(), value))
+            .collect();
+        insta::assert_debug_snapshot!(entries, @r###"
+        [
+            (
+                "0123456789a",
+                4,
+            ),
+            (
+                "0123456789b",
+                5,
+            ),
+            (
+                "ab",
+                1,
+            ),
+            (
+                "abc",
+                3,
+            ),
+            (
+                "ac",
+                18446744073709551615,
+            ),
+        ]
+        "###);
+        for (key, value) in [
+            (&b"ab"[..], 1),
+            (b"ac", u64::MAX),
+            (b"abc", 3),
+            (b"0123456789b", 5),
+        ] {
+            assert_eq!(art.get(key), Some(value));
+        }
+        assert_eq!(art.get(b"a"), None);
+        assert_eq!(art.get(b"abcd"), None);
+        assert_eq!(art.get(b"0123456780b"), None);
+
+        // replace an inlined value by a value that doesn't fit in the slot and back
+        assert_eq!(art.insert(b"ab", u64::MAX - 1), Some(1));
+        assert_eq!(art.insert(b"ac", 2), Some(u64::MAX));
+        insta::assert_snapshot!(art.display_tree(), @r###"
+        [Node4]
+        ├── `0` "123456789" [Node4]
+        │   ├── `a` → 4
+        │   └── `b` → 5
+        └── `a` [Node4]
+            ├── `b` [Node4]
+            │   ├── END → 18446744073709551614
+            │   └── `c` → 3
+            └── `c` → 2
+        "###);
+        assert_eq!(art.get(b"ab"), Some(u64::MAX - 1));
+        assert_eq!(art.get(b"ac"), Some(2));
+    }
+
+    #[test]
+    fn remove() {
+        let mut art = Art::new();
+        for (i, key) in [
+            "hello",
+            "hella",
+            "hell",
+            "hey",
+            "a long shared prefix: hello",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            art.insert(key.as_bytes(), i as u64);
+        }
+        art.insert(b"a long shared prefix: world", u64::MAX);
+        insta::assert_snapshot!(art.display_tree(), @r###"
+        [Node4]
+        ├── `a` " long shared prefix: " [Node4]
+        │   ├── `h` "ello" → 4
+        │   └── `w` "orld" → 18446744073709551615
+        └── `h` "e" [Node4]
+            ├── `l` "l" [Node4]
+            │   ├── END → 2
+            │   ├── `a` → 1
+            │   └── `o` → 0
+            └── `y` → 3
+        "###);
+
+        assert_eq!(art.remove(b"hel"), None);
+        assert_eq!(art.remove(b"hellos"), None);
+        assert_eq!(art.remove(b"a long shared prefiX: hello"), None);
+        assert_eq!(art.remove(b"hell"), Some(2));
+        assert_eq!(art.remove(b"hell"), None);
+        insta::assert_snapshot!(art.display_tree(), @r###"
+        [Node4]
+        ├── `a` " long shared prefix: " [Node4]
+        │   ├── `h` "ello" → 4
+        │   └── `w` "orld" → 18446744073709551615
+        └── `h` "e" [Node4]
+            ├── `l` "l" [Node4]
+            │   ├── `a` → 1
+            │   └── `o` → 0
+            └── `y` → 3
+        "###);
+
+        // the inner node left with a single entry is merged with it
+        assert_eq!(art.remove(b"hello"), Some(0));
+        assert_eq!(art.remove(b"a long shared prefix: world"), Some(u64::MAX));
+        insta::assert_snapshot!(art.display_tree(), @r###"
+        [Node4]
+        ├── `a` " long shared prefix: hello" → 4
+        └── `h` "e" [Node4]
+            ├── `l` "la" → 1
+            └── `y` → 3
+        "###);
+
+        assert_eq!(art.remove(b"hey"), Some(3));
+        assert_eq!(art.remove(b"hella"), Some(1));
+        insta::assert_snapshot!(art.display_tree(), @r###"
+        "a long shared prefix: hello" → 4
+        "###);
+        assert_eq!(art.remove(b"a long shared prefix: hello"), Some(4));
+        insta::assert_snapshot!(art.display_tree(), @"[Empty]");
+        assert_eq!(art.remove(b"hey"), None);
+    }
+
+    #[test]
+    fn shrink_from_node256() {
+        let mut art = Art::new();
+        for i in 0..=255 {
+            art.insert(&[i, i], u64::from(i));
+        }
+        for i in (1..=255).rev() {
+            assert_eq!(art.remove(&[i, i]), Some(u64::from(i)));
+            let kind = art.root.inner.kind();
+            match i {
+                38.. => assert_eq!(kind, "Node256", "{i}"),
+                13..=37 => assert_eq!(kind, "Node48", "{i}"),
+                4..=12 => assert_eq!(kind, "Node16", "{i}"),
+                2..=3 => assert_eq!(kind, "Node4", "{i}"),
+                _ => assert_eq!(kind, "Leaf", "{i}"),
+            }
+            if i > 1 {
+                assert_eq!(art.root.nb_childrens, u16::from(i));
+            }
+        }
+        assert_eq!(art.get(&[0, 0]), Some(0));
+        assert_eq!(art.remove(&[0, 0]), Some(0));
+        assert_eq!(art.root.inner.kind(), "Empty");
+    }
+
+    #[test]
+    fn remove_many_values() {
+        let mut keys = Vec::new();
+        for (i, rng) in (0..3_000_u64).zip(test_rng()) {
+            // few distinct bytes so the keys are often prefix of each other,
+            // and compressed paths longer than what fits in the header
+            let len = (rng >> 59) as usize % 20;
+            let bits = (0..len).map(|b| (rng >> (b * 2) & 1) as u8);
+            let key: Vec<u8> = match i % 3 {
+                0 => b"a long shared prefix"
+                    .iter()
+                    .copied()
+                    .chain(bits)
+                    .collect(),
+                _ => bits.collect(),
+            };
+            let value = if i % 5 == 0 { u64::MAX - i } else { i };
+            keys.push((key, value));
+        }
+        let mut art = Art::new();
+        let mut map = std::collections::BTreeMap::new();
+        for (key, value) in &keys {
+            art.insert(key, *value);
+            map.insert(key.clone(), *value);
+        }
+        for (i, (key, _)) in keys.iter().enumerate() {
+            if i % 3 == 0 {
+                continue;
+            }
+            assert_eq!(art.remove(key), map.remove(key), "{key:?}");
+        }
+        assert!(art.iter().eq(map.clone()));
+        for (key, _) in &keys {
+            assert_eq!(art.get(key), map.get(key).copied(), "{key:?}");
+        }
+
+        // the tree has the shape of one where the removed keys were never inserted
+        let mut expected = Art::new();
+        for (key, value) in &map {
+            expected.insert(key, *value);
+        }
+        assert_eq!(
+            art.display_tree().to_string(),
+            expected.display_tree().to_string()
+        );
+
+        for (key, value) in map {
+            assert_eq!(art.remove(&key), Some(value));
+        }
+        assert_eq!(art.root.inner.kind(), "Empty");
+    }
+
+    /// One step of the xorshift generator used by the randomized tests.
+    pub(crate) fn xorshift(mut x: u64) -> u64 {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    }
+
+    /// Pseudo-random numbers for the randomized tests, always the same so a
+    /// failure can be replayed.
+    pub(crate) fn test_rng() -> impl Iterator<Item = u64> {
+        seeded_rng(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Same as [`test_rng`], starting from another (non-zero) `seed`.
+    pub(crate) fn seeded_rng(seed: u64) -> impl Iterator<Item = u64> {
+        core::iter::successors(Some(xorshift(seed)), |&x| Some(xorshift(x)))
+    }
+
+    /// A tree made of a chain of `depth` Node4s with a single child each, far
+    /// deeper than the call stack would allow to recurse through, along with
+    /// the key of its only leaf.
+    pub(crate) fn deep_chain(depth: usize) -> (Art, Vec<u8>) {
+        let key = std::vec![b'a'; depth];
+        let mut node = Node::leaf_in(&key, 1, &Global).unwrap();
+        for _ in 0..depth {
+            let child = Ptr::try_new_in(node, &Global).unwrap();
+            node = Node::default();
+            node.insert_child(Some(b'a'), Child::node(child));
+        }
+        let mut art = Art::new();
+        art.root = node;
+        (art, key)
+    }
+
+    /// Delegates to the global allocator, but refuses to hand out more
+    /// than `budget` bytes at once.
+    pub(crate) struct Budget {
+        pub used: std::rc::Rc<std::cell::Cell<usize>>,
+        pub budget: usize,
+    }
+
+    unsafe impl Allocator for Budget {
+        fn allocate(
+            &self,
+            layout: std::alloc::Layout,
+        ) -> Result<std::ptr::NonNull<[u8]>, AllocError> {
+            let used = self.used.get() + layout.size();
+            if used > self.budget {
+                return Err(AllocError);
+            }
+            let ptr = Global.allocate(layout)?;
+            self.used.set(used);
+            Ok(ptr)
+        }
+
+        unsafe fn deallocate(&self, ptr: std::ptr::NonNull<u8>, layout: std::alloc::Layout) {
+            self.used.set(self.used.get() - layout.size());
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn out_of_memory() {
+        for budget in (0..20_000).step_by(97) {
+            let used = std::rc::Rc::default();
+            let mut art = Art::new_in(Budget {
+                used: std::rc::Rc::clone(&used),
+                budget,
+            });
+            let mut map = std::collections::BTreeMap::new();
+            for i in 0..500_u64 {
+                // long shared prefixes and values too large to be inlined
+                // need the most allocations
+                let prefix = [&b"a long shared prefix"[..], b"a long shared prefiX", b"b"];
+                let key = [
+                    prefix[i as usize % 3],
+                    &i.to_be_bytes()[7 - i as usize % 3..],
+                ]
+                .concat();
+                let value = if i % 5 == 0 { u64::MAX - i } else { i };
+                match art.try_insert(&key, value) {
+                    Ok(old) => assert_eq!(old, map.insert(key.clone(), value)),
+                    Err(error) => {
+                        assert_eq!(error, ArtError::OutOfMemory);
+                        break;
+                    }
+                }
+                // overwrites don't allocate when the value stays in its leaf
+                if value > 1 << 62 {
+                    assert_eq!(art.try_insert(&key, value - 1), Ok(Some(value)));
+                    map.insert(key, value - 1);
+                }
+            }
+            let entries: Vec<_> = art.iter().collect();
+            assert_eq!(
+                entries,
+                map.clone().into_iter().collect::<Vec<_>>(),
+                "{budget}"
+            );
+            // the removals that can't compact the tree without memory keep it as is
+            let removed: Vec<_> = map.keys().step_by(2).cloned().collect();
+            for key in removed {
+                assert_eq!(art.remove(&key), map.remove(&key));
+            }
+            let entries: Vec<_> = art.iter().collect();
+            assert_eq!(entries, map.into_iter().collect::<Vec<_>>(), "{budget}");
+            drop(art);
+            // everything was given back to the allocator
+            assert_eq!(used.get(), 0, "{budget}");
+        }
+    }
 }
@@ -4,32 +4,261 @@
 //! both arrays have space for 16 entries. A key can be found
 //! efficiently with binary search or, on modern hardware, with
 //! parallel comparisons using SIMD instructions.
+//!
+//! The keys are packed as 16 bytes so they fit in a single SIMD
+//! register, SSE2 is used on x86 and NEON on aarch64, with a portable
+//! fallback elsewhere.
 
-use crate::{node4::Node4, Cell, Node};
+use crate::{child::Child, node4::Node4, node48::Node48, Cell};
 
-#[derive(Debug)]
-pub(crate) struct Node16 {
-    keys: [Cell; 16],
-    values: [Option<Box<Node>>; 16],
+#[derive(Debug, Clone)]
+pub(crate) struct Node16<C = Child> {
+    len: u8,
+    /// Sorted, only the `len` first keys are used.
+    keys: [u8; 16],
+    values: [Option<C>; 16],
 }
 
-impl From<Node4> for Node16 {
-    fn from(mut value: Node4) -> Self {
-        let mut keys: [Cell; 16] = Default::default();
-        keys[..4].swap_with_slice(&mut value.keys);
+impl<C> Default for Node16<C> {
+    fn default() -> Self {
+        Node16 {
+            len: 0,
+            keys: [0; 16],
+            values: Default::default(),
+        }
+    }
+}
+
+impl<C> From<Node4<C>> for Node16<C> {
+    fn from(value: Node4<C>) -> Self {
+        let mut node = Node16::default();
+        for (key, child) in value.keys.into_iter().zip(value.values) {
+            if let (Cell::Some(key), Some(child)) = (key, child) {
+                node.insert(key, child);
+            }
+        }
+        node
+    }
+}
+
+impl<C> From<Node48<C>> for Node16<C> {
+    fn from(value: Node48<C>) -> Self {
+        let mut node = Node16::default();
+        for (key, child) in value.into_children() {
+            node.insert(key, child);
+        }
+        node
+    }
+}
+
+impl<C> Node16<C> {
+    pub fn is_full(&self) -> bool {
+        self.len == 16
+    }
 
-        let mut values: [Option<Box<Node>>; 16] = Default::default();
-        values[..4].swap_with_slice(&mut value.values);
+    pub fn insert(&mut self, key: u8, child: C) {
+        let len = usize::from(self.len);
+        let start = insert_position(&self.keys, len, key);
+        self.keys[start..=len].rotate_right(1);
+        self.keys[start] = key;
+        self.values[start..=len].rotate_right(1);
+        self.values[start] = Some(child);
+        self.len += 1;
+    }
 
-        Self { keys, values }
+    pub fn remove(&mut self, key: u8) -> Option<C> {
+        let len = usize::from(self.len);
+        let pos = find_key(&self.keys, len, key)?;
+        self.keys[pos..len].rotate_left(1);
+        self.values[pos..len].rotate_left(1);
+        self.len -= 1;
+        self.values[len - 1].take()
     }
+
+    pub fn find_child(&self, key: u8) -> Option<&C> {
+        let pos = find_key(&self.keys, usize::from(self.len), key)?;
+        self.values[pos].as_ref()
+    }
+
+    pub fn child_mut(&mut self, key: u8) -> Option<&mut Option<C>> {
+        let pos = find_key(&self.keys, usize::from(self.len), key)?;
+        Some(&mut self.values[pos])
+    }
+
+    pub fn into_children(self) -> impl Iterator<Item = (u8, C)> {
+        let len = usize::from(self.len);
+        self.keys
+            .into_iter()
+            .zip(self.values)
+            .take(len)
+            .filter_map(|(key, value)| Some((key, value?)))
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = (u8, &C)> {
+        let len = usize::from(self.len);
+        self.keys[..len]
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(key, value)| Some((*key, value.as_ref()?)))
+    }
+}
+
+/// Return the position of `key` among the `len` first `keys`.
+#[inline]
+pub fn find_key(keys: &[u8; 16], len: usize, key: u8) -> Option<usize> {
+    let mask = eq_mask(keys, key) & used_mask(len);
+    (mask != 0).then(|| mask.trailing_zeros() as usize)
+}
+
+/// Return the position where `key` must be inserted to keep the `len`
+/// first `keys` sorted, that is the number of keys lower than `key`.
+#[inline]
+pub fn insert_position(keys: &[u8; 16], len: usize, key: u8) -> usize {
+    (lt_mask(keys, key) & used_mask(len)).count_ones() as usize
+}
+
+/// The searches a Node16 would do without SIMD, only used as a baseline
+/// in the benchmarks.
+pub fn find_key_linear(keys: &[u8; 16], len: usize, key: u8) -> Option<usize> {
+    keys[..len].iter().position(|k| *k == key)
+}
+
+/// See [`find_key_linear`].
+pub fn insert_position_linear(keys: &[u8; 16], len: usize, key: u8) -> usize {
+    keys[..len].iter().position(|k| *k >= key).unwrap_or(len)
 }
 
-impl Node16 {
-    pub fn insert(&mut self, start: usize, path: &[u8], cell: Cell, value: u64) {
-        self.keys[start..].rotate_right(1);
-        self.keys[start] = cell;
-        self.values[start..].rotate_right(1);
-        self.values[start] = Some(Box::new(Node::default().insert(path, value).0));
+/// Bit `i` is set for the `len` first keys.
+#[inline]
+fn used_mask(len: usize) -> u32 {
+    (1 << len) - 1
+}
+
+// Bit `i` of the masks below is set when `keys[i]` matches the comparison.
+
+#[cfg(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse2")
+))]
+mod mask {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    #[inline]
+    pub fn eq_mask(keys: &[u8; 16], key: u8) -> u32 {
+        // Safety: SSE2 is enabled and the load is unaligned
+        unsafe {
+            let keys = _mm_loadu_si128(keys.as_ptr().cast());
+            let cmp = _mm_cmpeq_epi8(keys, _mm_set1_epi8(key as i8));
+            _mm_movemask_epi8(cmp) as u32
+        }
+    }
+
+    #[inline]
+    pub fn lt_mask(keys: &[u8; 16], key: u8) -> u32 {
+        // Safety: SSE2 is enabled and the load is unaligned
+        unsafe {
+            let keys = _mm_loadu_si128(keys.as_ptr().cast());
+            // SSE2 can only compare signed bytes, but `keys >= key` exactly
+            // when `max(keys, key) == keys`
+            let ge = _mm_cmpeq_epi8(_mm_max_epu8(keys, _mm_set1_epi8(key as i8)), keys);
+            !_mm_movemask_epi8(ge) as u32 & 0xffff
+        }
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+mod mask {
+    use core::arch::aarch64::*;
+
+    /// NEON has no movemask, the lanes are reduced to a bit each by
+    /// keeping a different bit in every lane of each half and summing them.
+    #[inline]
+    unsafe fn movemask(cmp: uint8x16_t) -> u32 {
+        const BITS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+        let bits = vandq_u8(cmp, vld1q_u8(BITS.as_ptr()));
+        let low = u32::from(vaddv_u8(vget_low_u8(bits)));
+        let high = u32::from(vaddv_u8(vget_high_u8(bits)));
+        low | high << 8
+    }
+
+    #[inline]
+    pub fn eq_mask(keys: &[u8; 16], key: u8) -> u32 {
+        // Safety: NEON is enabled and the load reads exactly 16 bytes
+        unsafe { movemask(vceqq_u8(vld1q_u8(keys.as_ptr()), vdupq_n_u8(key))) }
+    }
+
+    #[inline]
+    pub fn lt_mask(keys: &[u8; 16], key: u8) -> u32 {
+        // Safety: NEON is enabled and the load reads exactly 16 bytes
+        unsafe { movemask(vcltq_u8(vld1q_u8(keys.as_ptr()), vdupq_n_u8(key))) }
+    }
+}
+
+#[cfg(any(
+    test,
+    not(any(
+        target_arch = "x86_64",
+        all(target_arch = "x86", target_feature = "sse2"),
+        all(target_arch = "aarch64", target_feature = "neon")
+    ))
+))]
+mod portable {
+    // Without branches the compiler is free to vectorize these loops
+    // with whatever the target offers.
+
+    #[inline]
+    pub fn eq_mask(keys: &[u8; 16], key: u8) -> u32 {
+        keys.iter()
+            .enumerate()
+            .fold(0, |mask, (i, k)| mask | u32::from(*k == key) << i)
+    }
+
+    #[inline]
+    pub fn lt_mask(keys: &[u8; 16], key: u8) -> u32 {
+        keys.iter()
+            .enumerate()
+            .fold(0, |mask, (i, k)| mask | u32::from(*k < key) << i)
+    }
+}
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse2"),
+    all(target_arch = "aarch64", target_feature = "neon")
+)))]
+use portable as mask;
+
+use mask::{eq_mask, lt_mask};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn search() {
+        let keys = [
+            0, 1, 2, 10, 20, 30, 127, 128, 129, 200, 254, 255, 0, 0, 0, 0,
+        ];
+        for len in 0..=12 {
+            for key in 0..=255 {
+                assert_eq!(
+                    find_key(&keys, len, key),
+                    find_key_linear(&keys, len, key),
+                    "{len} {key}"
+                );
+                assert_eq!(
+                    insert_position(&keys, len, key),
+                    insert_position_linear(&keys, len, key),
+                    "{len} {key}"
+                );
+            }
+        }
+        for key in 0..=255 {
+            assert_eq!(portable::eq_mask(&keys, key), eq_mask(&keys, key));
+            assert_eq!(portable::lt_mask(&keys, key), lt_mask(&keys, key));
+        }
     }
 }
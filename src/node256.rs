@@ -6,9 +6,60 @@
 //! null, this representation is also very space efficient because
 //! only pointers need to be stored.
 
-use crate::Node;
+use crate::{child::Child, node48::Node48};
 
-#[derive(Debug)]
-pub struct Node256 {
-    values: [Option<Box<Node>>; 256],
+#[derive(Debug, Clone)]
+pub struct Node256<C = Child> {
+    values: [Option<C>; 256],
+}
+
+impl<C> Default for Node256<C> {
+    fn default() -> Self {
+        Node256 {
+            values: core::array::from_fn(|_| None),
+        }
+    }
+}
+
+impl<C> From<Node48<C>> for Node256<C> {
+    fn from(value: Node48<C>) -> Self {
+        let mut node = Node256::default();
+        for (key, child) in value.into_children() {
+            node.insert(key, child);
+        }
+        node
+    }
+}
+
+impl<C> Node256<C> {
+    pub fn insert(&mut self, key: u8, child: C) {
+        self.values[usize::from(key)] = Some(child);
+    }
+
+    pub fn remove(&mut self, key: u8) -> Option<C> {
+        self.values[usize::from(key)].take()
+    }
+
+    pub fn find_child(&self, key: u8) -> Option<&C> {
+        self.values[usize::from(key)].as_ref()
+    }
+
+    pub fn child_mut(&mut self, key: u8) -> Option<&mut Option<C>> {
+        let slot = &mut self.values[usize::from(key)];
+        slot.is_some().then_some(slot)
+    }
+
+    pub fn into_children(self) -> impl Iterator<Item = (u8, C)> {
+        self.values
+            .into_iter()
+            .enumerate()
+            .filter_map(|(key, value)| Some((key as u8, value?)))
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = (u8, &C)> {
+        self.values
+            .iter()
+            .enumerate()
+            .filter_map(|(key, value)| Some((key as u8, value.as_ref()?)))
+    }
 }
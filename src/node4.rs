@@ -3,16 +3,41 @@
 //! array of the same length for pointers. The keys and pointers
 //! are stored at corresponding positions and the keys are sorted.
 
-use crate::{node16::Node16, Cell, Node};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 
-#[derive(Default)]
-pub(crate) struct Node4 {
+use crate::{child::Child, node16::Node16, Cell};
+
+#[derive(Clone)]
+pub(crate) struct Node4<C = Child> {
     pub keys: [Cell; 4],
-    pub values: [Option<Box<Node>>; 4],
+    pub values: [Option<C>; 4],
 }
 
-impl std::fmt::Debug for Node4 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<C> Default for Node4<C> {
+    fn default() -> Self {
+        Node4 {
+            keys: Default::default(),
+            values: Default::default(),
+        }
+    }
+}
+
+impl<C> From<Node16<C>> for Node4<C> {
+    fn from(value: Node16<C>) -> Self {
+        let mut node = Node4::default();
+        for (key, child) in value.into_children() {
+            node.insert(key, child);
+        }
+        node
+    }
+}
+
+impl<C: core::fmt::Debug> core::fmt::Debug for Node4<C> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let keys = self
             .keys
             .iter()
@@ -25,17 +50,55 @@ impl std::fmt::Debug for Node4 {
     }
 }
 
-impl Node4 {
-    pub fn insert(&mut self, start: usize, path: &[u8], cell: Cell, value: u64) {
+impl<C> Node4<C> {
+    pub fn is_full(&self) -> bool {
+        !self.keys[3].is_none()
+    }
+
+    pub fn insert(&mut self, key: u8, child: C) {
+        let cell = Cell::Some(key);
+        let start = cell.insert_position(&self.keys);
         self.keys[start..].rotate_right(1);
         self.keys[start] = cell;
         self.values[start..].rotate_right(1);
-        self.values[start] = Some(Box::new(Node::default().insert(path, value).0));
+        self.values[start] = Some(child);
     }
 
-    pub fn promote(self, start: usize, path: &[u8], cell: Cell, value: u64) -> Node16 {
-        let mut new_node = Node16::from(self);
-        new_node.insert(start, path, cell, value);
-        new_node
+    pub fn remove(&mut self, key: u8) -> Option<C> {
+        let pos = self.keys.iter().position(|k| *k == Cell::Some(key))?;
+        self.keys[pos..].rotate_left(1);
+        self.keys[3] = Cell::None;
+        self.values[pos..].rotate_left(1);
+        self.values[3].take()
+    }
+
+    pub fn find_child(&self, key: u8) -> Option<&C> {
+        let pos = self.keys.iter().position(|k| *k == Cell::Some(key))?;
+        self.values[pos].as_ref()
+    }
+
+    pub fn child_mut(&mut self, key: u8) -> Option<&mut Option<C>> {
+        let pos = self.keys.iter().position(|k| *k == Cell::Some(key))?;
+        Some(&mut self.values[pos])
+    }
+
+    pub fn into_children(self) -> impl Iterator<Item = (u8, C)> {
+        self.keys
+            .into_iter()
+            .zip(self.values)
+            .filter_map(|(key, value)| match (key, value) {
+                (Cell::Some(key), Some(value)) => Some((key, value)),
+                _ => None,
+            })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = (u8, &C)> {
+        self.keys
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(key, value)| match (key, value) {
+                (Cell::Some(key), Some(value)) => Some((*key, value)),
+                _ => None,
+            })
     }
 }
@@ -8,10 +8,88 @@
 //! comparison to 256 pointers of 8 bytes, because the indexes
 //! only require 6 bits (we use 1 byte for simplicity).
 
-use crate::Node;
+use crate::{child::Child, node16::Node16, node256::Node256};
 
-#[derive(Debug)]
-pub struct Node48 {
+#[derive(Debug, Clone)]
+pub struct Node48<C = Child> {
+    len: u8,
     keys: [Option<u8>; 256],
-    values: [Option<Box<Node>>; 48],
+    values: [Option<C>; 48],
+}
+
+impl<C> Default for Node48<C> {
+    fn default() -> Self {
+        Node48 {
+            len: 0,
+            keys: [None; 256],
+            values: core::array::from_fn(|_| None),
+        }
+    }
+}
+
+impl<C> From<Node16<C>> for Node48<C> {
+    fn from(value: Node16<C>) -> Self {
+        let mut node = Node48::default();
+        for (key, child) in value.into_children() {
+            node.insert(key, child);
+        }
+        node
+    }
+}
+
+impl<C> From<Node256<C>> for Node48<C> {
+    fn from(value: Node256<C>) -> Self {
+        let mut node = Node48::default();
+        for (key, child) in value.into_children() {
+            node.insert(key, child);
+        }
+        node
+    }
+}
+
+impl<C> Node48<C> {
+    pub fn is_full(&self) -> bool {
+        self.len == 48
+    }
+
+    pub fn insert(&mut self, key: u8, child: C) {
+        let index = self.values.iter().position(Option::is_none).unwrap();
+        self.keys[usize::from(key)] = Some(index as u8);
+        self.values[index] = Some(child);
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, key: u8) -> Option<C> {
+        let index = self.keys[usize::from(key)].take()?;
+        self.len -= 1;
+        self.values[usize::from(index)].take()
+    }
+
+    pub fn find_child(&self, key: u8) -> Option<&C> {
+        let index = self.keys[usize::from(key)]?;
+        self.values[usize::from(index)].as_ref()
+    }
+
+    pub fn child_mut(&mut self, key: u8) -> Option<&mut Option<C>> {
+        let index = self.keys[usize::from(key)]?;
+        Some(&mut self.values[usize::from(index)])
+    }
+
+    pub fn into_children(self) -> impl Iterator<Item = (u8, C)> {
+        let mut values = self.values;
+        self.keys
+            .into_iter()
+            .enumerate()
+            .filter_map(move |(key, index)| {
+                let value = values[usize::from(index?)].take()?;
+                Some((key as u8, value))
+            })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = (u8, &C)> {
+        self.keys.iter().enumerate().filter_map(|(key, index)| {
+            let value = self.values[usize::from((*index)?)].as_ref()?;
+            Some((key as u8, value))
+        })
+    }
 }
@@ -0,0 +1,1271 @@
+//! A persistent variant of the [`Art`](crate::Art), following "Persistent
+//! Adaptive Radix Trees" (`assets/PART.pdf`).
+//!
+//! The nodes are shared between the versions of the tree through [`Arc`]s.
+//! Modifying a version copies the nodes on the path to the modified leaf,
+//! every other subtree stays shared with the previous versions. A node only
+//! referenced by the version being modified is updated in place instead of
+//! being copied.
+//!
+//! A tree can also maintain a hash of the content of each subtree, a Merkle
+//! tree letting two replicas find their differences by only comparing the
+//! hashes of the subtrees where they disagree. The hash of a node covers
+//! the path leading to it, so two replicas holding the same entries under a
+//! prefix agree on its hash whatever else they store.
+//!
+//! A modification updates the hashes of the nodes on its path without
+//! reading the rest of the tree: the state of the hasher fed with the path
+//! is passed down so each node only hashes its own prefix, and each node
+//! keeps the sum of the hashes of its children, which is updated by
+//! subtracting the old hash of the modified child and adding the new one.
+//! A [`Txn`] only marks the nodes it modifies as stale and rehashes them
+//! once when it is committed.
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::mem::take;
+
+use crate::{
+    common_len,
+    diff::{Diff, Merge},
+    hashes::{entry_hash, ContentHasher},
+    node16::Node16,
+    node256::Node256,
+    node4::Node4,
+    node48::Node48,
+};
+
+#[derive(Debug)]
+struct Leaf {
+    key: Box<[u8]>,
+    value: u64,
+    /// Zero when the tree doesn't maintain the hashes.
+    hash: u64,
+}
+
+impl Leaf {
+    fn new(key: &[u8], value: u64, hashed: bool) -> Arc<Leaf> {
+        Arc::new(Leaf {
+            key: key.into(),
+            value,
+            hash: if hashed { entry_hash(key, value) } else { 0 },
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Child {
+    Leaf(Arc<Leaf>),
+    Node(Arc<Node>),
+}
+
+impl Child {
+    fn hash(&self) -> u64 {
+        match self {
+            Child::Leaf(leaf) => leaf.hash,
+            Child::Node(node) => node.hash,
+        }
+    }
+
+    /// The hash of the child stored under `byte`, the term of the sum of
+    /// [`Node::children_hash`].
+    fn entry_hash(&self, byte: u8) -> u64 {
+        let mut hasher = ContentHasher::new(ContentHasher::CHILD);
+        hasher.write(&[byte]);
+        hasher.write_u64(self.hash());
+        hasher.finish()
+    }
+}
+
+/// How a modification maintains the hashes of the nodes it modifies.
+#[derive(Debug, Clone, Copy)]
+enum Hashing {
+    /// The tree doesn't maintain the hashes.
+    Off,
+    /// Update the hashes right away, the hasher was fed with the path
+    /// leading to the subtree being modified.
+    Now(ContentHasher),
+    /// Only mark the modified nodes as stale, see [`Txn::commit`].
+    Deferred,
+}
+
+impl Hashing {
+    fn new(hashed: bool, deferred: bool) -> Self {
+        match (hashed, deferred) {
+            (false, _) => Hashing::Off,
+            (true, false) => Hashing::Now(ContentHasher::new(ContentHasher::NODE)),
+            (true, true) => Hashing::Deferred,
+        }
+    }
+
+    /// Whether the new leaves must be hashed.
+    fn is_on(self) -> bool {
+        !matches!(self, Hashing::Off)
+    }
+
+    /// The hashing of a subtree whose path continues with `bytes`.
+    fn extend(self, bytes: &[u8]) -> Self {
+        match self {
+            Hashing::Now(mut hasher) => {
+                hasher.write(bytes);
+                Hashing::Now(hasher)
+            }
+            hashing => hashing,
+        }
+    }
+
+    /// The term of `child` in the sum of the hashes of the children of its
+    /// node, when it's maintained right away.
+    fn entry_hash(self, child: &Child, byte: u8) -> Option<u64> {
+        match self {
+            Hashing::Now(_) => Some(child.entry_hash(byte)),
+            _ => None,
+        }
+    }
+
+    /// Replace the term of the child `old` by the one of `new` in `sum`, the
+    /// sum of the hashes of the children of a node.
+    fn update_child(self, sum: &mut u64, byte: u8, old: Option<u64>, new: Option<&Child>) {
+        if let Hashing::Now(_) = self {
+            let new = new.map_or(0, |child| child.entry_hash(byte));
+            *sum = sum.wrapping_sub(old.unwrap_or(0)).wrapping_add(new);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Children {
+    Node4(Node4<Child>),
+    Node16(Box<Node16<Child>>),
+    Node48(Box<Node48<Child>>),
+    Node256(Box<Node256<Child>>),
+}
+
+impl Default for Children {
+    fn default() -> Self {
+        Children::Node4(Node4::default())
+    }
+}
+
+impl Children {
+    fn is_full(&self) -> bool {
+        match self {
+            Children::Node4(node) => node.is_full(),
+            Children::Node16(node) => node.is_full(),
+            Children::Node48(node) => node.is_full(),
+            Children::Node256(_) => false,
+        }
+    }
+
+    fn grow(self) -> Self {
+        match self {
+            Children::Node4(node) => Children::Node16(Box::new(Node16::from(node))),
+            Children::Node16(node) => Children::Node48(Box::new(Node48::from(*node))),
+            Children::Node48(node) => Children::Node256(Box::new(Node256::from(*node))),
+            Children::Node256(node) => Children::Node256(node),
+        }
+    }
+
+    /// Shrink to the smaller node type if `len` children fit in it, with the
+    /// same slack as the [`Art`](crate::Art).
+    fn shrink(self, len: u16) -> Self {
+        match self {
+            Children::Node16(node) if len <= 3 => Children::Node4(Node4::from(*node)),
+            Children::Node48(node) if len <= 12 => Children::Node16(Box::new(Node16::from(*node))),
+            Children::Node256(node) if len <= 37 => Children::Node48(Box::new(Node48::from(*node))),
+            children => children,
+        }
+    }
+
+    fn insert(&mut self, byte: u8, child: Child) {
+        match self {
+            Children::Node4(node) => node.insert(byte, child),
+            Children::Node16(node) => node.insert(byte, child),
+            Children::Node48(node) => node.insert(byte, child),
+            Children::Node256(node) => node.insert(byte, child),
+        }
+    }
+
+    fn remove(&mut self, byte: u8) -> Option<Child> {
+        match self {
+            Children::Node4(node) => node.remove(byte),
+            Children::Node16(node) => node.remove(byte),
+            Children::Node48(node) => node.remove(byte),
+            Children::Node256(node) => node.remove(byte),
+        }
+    }
+
+    fn find_child(&self, byte: u8) -> Option<&Child> {
+        match self {
+            Children::Node4(node) => node.find_child(byte),
+            Children::Node16(node) => node.find_child(byte),
+            Children::Node48(node) => node.find_child(byte),
+            Children::Node256(node) => node.find_child(byte),
+        }
+    }
+
+    fn child_mut(&mut self, byte: u8) -> Option<&mut Option<Child>> {
+        match self {
+            Children::Node4(node) => node.child_mut(byte),
+            Children::Node16(node) => node.child_mut(byte),
+            Children::Node48(node) => node.child_mut(byte),
+            Children::Node256(node) => node.child_mut(byte),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (u8, &Child)> + '_> {
+        match self {
+            Children::Node4(node) => Box::new(node.children()),
+            Children::Node16(node) => Box::new(node.children()),
+            Children::Node48(node) => Box::new(node.children()),
+            Children::Node256(node) => Box::new(node.children()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    /// The bytes between the key byte leading to the node and its children.
+    prefix: Box<[u8]>,
+    /// The leaf whose key ends in this node.
+    end: Option<Arc<Leaf>>,
+    len: u16,
+    children: Children,
+    /// Zero when the tree doesn't maintain the hashes.
+    hash: u64,
+    /// The wrapping sum of the [`Child::entry_hash`] of the children.
+    children_hash: u64,
+    /// Whether the hashes of the node are out of date, only while a
+    /// [`Txn`] modifying it isn't committed.
+    stale: bool,
+}
+
+impl Node {
+    fn new(prefix: &[u8]) -> Self {
+        Node {
+            prefix: prefix.into(),
+            ..Node::default()
+        }
+    }
+
+    /// Add `leaf` under `byte`, or as the end of the node if its key ends here.
+    fn add_leaf(&mut self, byte: Option<u8>, leaf: Arc<Leaf>) {
+        match byte {
+            Some(byte) => self.add_child(byte, Child::Leaf(leaf)),
+            None => self.end = Some(leaf),
+        }
+    }
+
+    fn add_child(&mut self, byte: u8, child: Child) {
+        if self.children.is_full() {
+            self.children = take(&mut self.children).grow();
+        }
+        self.children.insert(byte, child);
+        self.len += 1;
+    }
+
+    fn remove_child(&mut self, byte: u8) {
+        self.children.remove(byte);
+        self.len -= 1;
+        self.children = take(&mut self.children).shrink(self.len);
+    }
+
+    /// Update the hash of the node once its content changed, `depth` is the
+    /// length of the path leading to the node, before its prefix.
+    ///
+    /// The sum of the hashes of the children must be up to date, see
+    /// [`Node::sum_children`].
+    fn rehash(&mut self, hashing: Hashing, depth: usize) {
+        match hashing {
+            Hashing::Off => (),
+            Hashing::Deferred => self.stale = true,
+            Hashing::Now(mut hasher) => {
+                hasher.write(&self.prefix);
+                hasher.write_u64((depth + self.prefix.len()) as u64);
+                hasher.write_u64(self.end.as_ref().map_or(0, |leaf| leaf.hash));
+                hasher.write_u64(self.children_hash);
+                self.hash = hasher.finish();
+            }
+        }
+    }
+
+    /// Compute the sum of the hashes of the children from scratch.
+    fn sum_children(&mut self) {
+        self.children_hash = self.children.iter().fold(0, |sum, (byte, child)| {
+            sum.wrapping_add(child.entry_hash(byte))
+        });
+    }
+
+    /// Rehash the stale nodes of the subtree `child`, reached after `depth`
+    /// bytes of path fed to `hasher`.
+    fn refresh(child: &mut Child, hasher: ContentHasher, depth: usize) {
+        let node = match child {
+            Child::Node(node) if node.stale => Arc::make_mut(node),
+            _ => return,
+        };
+        let mut path = hasher;
+        path.write(&node.prefix);
+        let depth_children = depth + node.prefix.len() + 1;
+        let bytes: Vec<u8> = node.children.iter().map(|(byte, _)| byte).collect();
+        for byte in bytes {
+            let mut path = path;
+            path.write(&[byte]);
+            let child = node.children.child_mut(byte).unwrap().as_mut().unwrap();
+            Node::refresh(child, path, depth_children);
+        }
+        node.sum_children();
+        node.stale = false;
+        node.rehash(Hashing::Now(hasher), depth);
+    }
+}
+
+/// Insert `value` under `key` in the subtree `child`, reached after `depth`
+/// bytes of the key. Return the subtree replacing `child` and the previous
+/// value.
+fn insert_at(
+    child: Child,
+    key: &[u8],
+    depth: usize,
+    value: u64,
+    hashing: Hashing,
+) -> (Child, Option<u64>) {
+    let hashed = hashing.is_on();
+    let mut node = match child {
+        Child::Leaf(leaf) if *leaf.key == *key => {
+            return (Child::Leaf(Leaf::new(key, value, hashed)), Some(leaf.value))
+        }
+        Child::Leaf(leaf) => {
+            let split = depth + common_len(&leaf.key[depth..], &key[depth..]);
+            let mut node = Node::new(&key[depth..split]);
+            node.add_leaf(leaf.key.get(split).copied(), leaf);
+            node.add_leaf(key.get(split).copied(), Leaf::new(key, value, hashed));
+            node.sum_children();
+            node.rehash(hashing, depth);
+            return (Child::Node(Arc::new(node)), None);
+        }
+        Child::Node(node) => node,
+    };
+    let common = common_len(&node.prefix, &key[depth..]);
+    if common < node.prefix.len() {
+        // the key diverges in the prefix, the node moves under a new one and
+        // keeps its hash since its path doesn't change
+        let mut split = Node::new(&node.prefix[..common]);
+        let byte = node.prefix[common];
+        let moved = Arc::make_mut(&mut node);
+        moved.prefix = moved.prefix[common + 1..].into();
+        split.add_child(byte, Child::Node(node));
+        split.add_leaf(
+            key.get(depth + common).copied(),
+            Leaf::new(key, value, hashed),
+        );
+        split.sum_children();
+        split.rehash(hashing, depth);
+        return (Child::Node(Arc::new(split)), None);
+    }
+
+    let node_depth = depth;
+    let depth = depth + common;
+    let inner = Arc::make_mut(&mut node);
+    let old_value = match key.get(depth) {
+        None => inner
+            .end
+            .replace(Leaf::new(key, value, hashed))
+            .map(|leaf| leaf.value),
+        Some(&byte) => match inner.children.child_mut(byte) {
+            Some(slot) => {
+                let child = slot.take().unwrap();
+                let old = hashing.entry_hash(&child, byte);
+                let path = hashing.extend(&key[node_depth..depth + 1]);
+                let (child, old_value) = insert_at(child, key, depth + 1, value, path);
+                hashing.update_child(&mut inner.children_hash, byte, old, Some(&child));
+                *slot = Some(child);
+                old_value
+            }
+            None => {
+                let leaf = Child::Leaf(Leaf::new(key, value, hashed));
+                hashing.update_child(&mut inner.children_hash, byte, None, Some(&leaf));
+                inner.add_child(byte, leaf);
+                None
+            }
+        },
+    };
+    inner.rehash(hashing, node_depth);
+    (Child::Node(node), old_value)
+}
+
+/// Remove `key`, which must be in the subtree `child` reached after `depth`
+/// bytes of the key. Return the subtree replacing `child` and the removed
+/// value.
+fn remove_at(child: Child, key: &[u8], depth: usize, hashing: Hashing) -> (Option<Child>, u64) {
+    let mut node = match child {
+        Child::Leaf(leaf) => return (None, leaf.value),
+        Child::Node(node) => node,
+    };
+    let node_depth = depth;
+    let inner = Arc::make_mut(&mut node);
+    let depth = depth + inner.prefix.len();
+    let value = match key.get(depth) {
+        None => inner.end.take().expect("The key is in the tree").value,
+        Some(&byte) => {
+            let slot = inner
+                .children
+                .child_mut(byte)
+                .expect("The key is in the tree");
+            let child = slot.take().unwrap();
+            let old = hashing.entry_hash(&child, byte);
+            let path = hashing.extend(&key[node_depth..depth + 1]);
+            let (child, value) = remove_at(child, key, depth + 1, path);
+            hashing.update_child(&mut inner.children_hash, byte, old, child.as_ref());
+            match child {
+                Some(child) => *slot = Some(child),
+                None => inner.remove_child(byte),
+            }
+            value
+        }
+    };
+
+    // a node without children is replaced by its end, and a node with a
+    // single child by the child, whose prefix then includes the node's and
+    // whose hash doesn't change since its path stays the same
+    let child = match (inner.len, inner.end.is_some()) {
+        (0, _) => inner.end.take().map(Child::Leaf),
+        (1, false) => {
+            let (byte, _) = inner.children.iter().next().unwrap();
+            match inner.children.remove(byte).unwrap() {
+                Child::Leaf(leaf) => Some(Child::Leaf(leaf)),
+                Child::Node(mut child) => {
+                    let merged = Arc::make_mut(&mut child);
+                    merged.prefix = [&inner.prefix[..], &[byte], &merged.prefix].concat().into();
+                    Some(Child::Node(child))
+                }
+            }
+        }
+        _ => {
+            inner.rehash(hashing, node_depth);
+            Some(Child::Node(node))
+        }
+    };
+    (child, value)
+}
+
+type Entries<'a> = Box<dyn Iterator<Item = (&'a [u8], u64)> + 'a>;
+
+/// The entries of the subtrees `children`, sorted by key.
+fn entries<'a>(children: Box<dyn Iterator<Item = &'a Child> + 'a>) -> Entries<'a> {
+    let mut stack = Vec::from([children]);
+    Box::new(core::iter::from_fn(move || loop {
+        let Some(child) = stack.last_mut()?.next() else {
+            stack.pop();
+            continue;
+        };
+        match child {
+            Child::Leaf(leaf) => return Some((&*leaf.key, leaf.value)),
+            Child::Node(node) => {
+                stack.push(Box::new(node.children.iter().map(|(_, child)| child)));
+                // the end of a node comes before its children
+                if let Some(leaf) = &node.end {
+                    return Some((&*leaf.key, leaf.value));
+                }
+            }
+        }
+    }))
+}
+
+/// A subtree compared by [`PersistentArt::diff`].
+#[derive(Clone, Copy)]
+enum Tree<'a> {
+    Leaf(&'a Arc<Leaf>),
+    /// The node whose `skip` first prefix bytes were already compared.
+    Node(&'a Arc<Node>, usize),
+}
+
+impl<'a> From<&'a Child> for Tree<'a> {
+    fn from(child: &'a Child) -> Self {
+        match child {
+            Child::Leaf(leaf) => Tree::Leaf(leaf),
+            Child::Node(node) => Tree::Node(node, 0),
+        }
+    }
+}
+
+impl<'a> Tree<'a> {
+    fn entries(tree: Option<Self>) -> Entries<'a> {
+        match tree {
+            None => Box::new(core::iter::empty()),
+            Some(Tree::Leaf(leaf)) => Box::new(core::iter::once((&*leaf.key, leaf.value))),
+            Some(Tree::Node(node, _)) => Box::new(
+                node.end
+                    .iter()
+                    .map(|leaf| (&*leaf.key, leaf.value))
+                    .chain(entries(Box::new(
+                        node.children.iter().map(|(_, child)| child),
+                    ))),
+            ),
+        }
+    }
+}
+
+enum Task<'a> {
+    /// Two subtrees reached through the same key bytes.
+    Pair(Option<Tree<'a>>, Option<Tree<'a>>),
+    /// Subtrees whose entries are compared one by one.
+    Merge(Merge<Entries<'a>, Entries<'a>>),
+}
+
+/// Push the tasks comparing `old` and `new` on `tasks`, the first ones to
+/// run last.
+fn compare<'a>(tasks: &mut Vec<Task<'a>>, old: Option<Tree<'a>>, new: Option<Tree<'a>>) {
+    let (old_node, old_skip, new_node, new_skip) = match (old, new) {
+        (None, None) => return,
+        (Some(Tree::Leaf(old)), Some(Tree::Leaf(new))) if Arc::ptr_eq(old, new) => return,
+        (Some(Tree::Node(old, old_skip)), Some(Tree::Node(new, new_skip))) => {
+            (old, old_skip, new, new_skip)
+        }
+        (old, new) => {
+            tasks.push(Task::Merge(Merge::new(
+                Tree::entries(old),
+                Tree::entries(new),
+            )));
+            return;
+        }
+    };
+    if Arc::ptr_eq(old_node, new_node) && old_skip == new_skip {
+        return;
+    }
+    let old_prefix = &old_node.prefix[old_skip..];
+    let new_prefix = &new_node.prefix[new_skip..];
+    match (old_prefix.first(), new_prefix.first()) {
+        (Some(old_byte), Some(new_byte)) if old_byte == new_byte => {
+            let old = Tree::Node(old_node, old_skip + 1);
+            let new = Tree::Node(new_node, new_skip + 1);
+            tasks.push(Task::Pair(Some(old), Some(new)));
+        }
+        // the keys diverge in the prefixes, the subtrees have no common key
+        (Some(_), Some(_)) => tasks.push(Task::Merge(Merge::new(
+            Tree::entries(old),
+            Tree::entries(new),
+        ))),
+        _ => {
+            // at least one of the nodes branches here, the other one is seen
+            // as a node with the next byte of its prefix as only child
+            let branches = |node: &'a Arc<Node>, skip: usize| -> Vec<(u8, Tree<'a>)> {
+                match node.prefix.get(skip) {
+                    Some(&byte) => Vec::from([(byte, Tree::Node(node, skip + 1))]),
+                    None => node
+                        .children
+                        .iter()
+                        .map(|(byte, child)| (byte, child.into()))
+                        .collect(),
+                }
+            };
+            let mut old = branches(old_node, old_skip).into_iter().peekable();
+            let mut new = branches(new_node, new_skip).into_iter().peekable();
+            let mut pairs = Vec::new();
+            loop {
+                let (old, new) = match (old.peek(), new.peek()) {
+                    (None, None) => break,
+                    (Some((a, _)), Some((b, _))) if a == b => (old.next(), new.next()),
+                    (Some((a, _)), Some((b, _))) if a > b => (None, new.next()),
+                    (Some(_), _) => (old.next(), None),
+                    (None, Some(_)) => (None, new.next()),
+                };
+                pairs.push(Task::Pair(
+                    old.map(|(_, tree)| tree),
+                    new.map(|(_, tree)| tree),
+                ));
+            }
+            tasks.extend(pairs.into_iter().rev());
+
+            let end = |node: &'a Arc<Node>, skip: usize| {
+                let end = if node.prefix.len() == skip {
+                    node.end.as_ref()
+                } else {
+                    None
+                };
+                end.map(Tree::Leaf)
+            };
+            tasks.push(Task::Pair(end(old_node, old_skip), end(new_node, new_skip)));
+        }
+    }
+}
+
+/// An adaptive radix tree whose versions are immutable.
+///
+/// [`insert`](Self::insert) and [`remove`](Self::remove) return a new version
+/// of the tree sharing all the nodes they didn't modify with the original
+/// one, which stays unchanged. Cloning a version is O(1), so a reader can
+/// take a point-in-time [`snapshot`](Self::snapshot) while a writer keeps
+/// creating new versions, and rolling back a modification is only a matter
+/// of dropping the new version.
+#[derive(Debug, Clone, Default)]
+pub struct PersistentArt {
+    root: Option<Child>,
+    len: usize,
+    /// Whether the nodes maintain the hash of their subtree.
+    hashed: bool,
+}
+
+impl PersistentArt {
+    pub fn new() -> PersistentArt {
+        Default::default()
+    }
+
+    /// An empty tree maintaining the hash of each of its subtrees, see
+    /// [`root_hash`](Self::root_hash). The versions derived from it maintain
+    /// them too.
+    pub fn with_hashes() -> PersistentArt {
+        PersistentArt {
+            hashed: true,
+            ..Default::default()
+        }
+    }
+
+    /// The number of entries in this version.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<u64> {
+        let mut child = self.root.as_ref()?;
+        let mut depth = 0;
+        loop {
+            let node = match child {
+                Child::Leaf(leaf) => return (*leaf.key == *key).then_some(leaf.value),
+                Child::Node(node) => node,
+            };
+            if !key[depth..].starts_with(&node.prefix) {
+                return None;
+            }
+            depth += node.prefix.len();
+            child = match key.get(depth) {
+                Some(&byte) => {
+                    depth += 1;
+                    node.children.find_child(byte)?
+                }
+                None => return node.end.as_ref().map(|leaf| leaf.value),
+            };
+        }
+    }
+
+    /// Return a new version of the tree with `value` under `key`.
+    ///
+    /// Every call copies the path to the leaf, use a
+    /// [`transaction`](Self::transaction) to apply many modifications at once.
+    pub fn insert(&self, key: &[u8], value: u64) -> PersistentArt {
+        let mut version = self.clone();
+        version.insert_mut(key, value, false);
+        version
+    }
+
+    /// Return a new version of the tree without `key`.
+    pub fn remove(&self, key: &[u8]) -> PersistentArt {
+        let mut version = self.clone();
+        version.remove_mut(key, false);
+        version
+    }
+
+    /// Take a snapshot of this version, it's a cheap clone.
+    pub fn snapshot(&self) -> PersistentArt {
+        self.clone()
+    }
+
+    /// Start a batch of modifications on top of this version.
+    pub fn transaction(&self) -> Txn {
+        Txn {
+            version: self.clone(),
+        }
+    }
+
+    /// Whether both versions share their root, in which case they hold the
+    /// same entries.
+    pub fn ptr_eq(&self, other: &PersistentArt) -> bool {
+        match (&self.root, &other.root) {
+            (None, None) => true,
+            (Some(Child::Leaf(a)), Some(Child::Leaf(b))) => Arc::ptr_eq(a, b),
+            (Some(Child::Node(a)), Some(Child::Node(b))) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Iterate over the entries of this version in the order of their keys.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], u64)> {
+        entries(Box::new(self.root.iter()))
+    }
+
+    /// The differences from `self` to `other`, sorted by key.
+    ///
+    /// Applying them to `self` gives `other`. The subtrees shared by both
+    /// versions are skipped without being visited, so comparing a version
+    /// with one derived from it only costs as much as the modified paths.
+    pub fn diff<'a>(&'a self, other: &'a PersistentArt) -> impl Iterator<Item = Diff> + 'a {
+        let mut tasks = Vec::new();
+        let tree = |root: &'a Option<Child>| root.as_ref().map(Tree::from);
+        tasks.push(Task::Pair(tree(&self.root), tree(&other.root)));
+        core::iter::from_fn(move || loop {
+            match tasks.pop()? {
+                Task::Pair(old, new) => compare(&mut tasks, old, new),
+                Task::Merge(mut merge) => {
+                    if let Some(diff) = merge.next() {
+                        tasks.push(Task::Merge(merge));
+                        return Some(diff);
+                    }
+                }
+            }
+        })
+    }
+
+    /// The hash of all the entries of this version, `None` if the tree
+    /// doesn't maintain the hashes, see [`with_hashes`](Self::with_hashes).
+    pub fn root_hash(&self) -> Option<u64> {
+        self.subtree_hash(&[])
+    }
+
+    /// The hash of the entries whose key starts with `prefix`, `None` if the
+    /// tree doesn't maintain the hashes.
+    ///
+    /// It only depends on these entries, so two trees holding the same ones
+    /// under `prefix` have the same hash for it. The hash of a prefix without
+    /// any entry is zero.
+    pub fn subtree_hash(&self, prefix: &[u8]) -> Option<u64> {
+        self.hashed
+            .then(|| self.subtree(prefix).map_or(0, |(child, _)| child.hash()))
+    }
+
+    /// The differences from `self` to `other` found by comparing the hashes
+    /// of their subtrees, sorted by key.
+    ///
+    /// It only relies on lookups of prefixes in `other`, like a replica
+    /// asking another one for the hashes of its subtrees: starting from the
+    /// root, the subtrees with the same hash are skipped and the others are
+    /// split by their next key byte until the differing entries are found.
+    /// Unlike [`diff`](Self::diff), it doesn't need the trees to share their
+    /// nodes. When one of the trees doesn't maintain the hashes, no subtree
+    /// is skipped.
+    pub fn reconcile(&self, other: &PersistentArt) -> Vec<Diff> {
+        let mut diffs = Vec::new();
+        self.reconcile_at(other, &[], &mut diffs);
+        diffs
+    }
+
+    fn reconcile_at(&self, other: &PersistentArt, prefix: &[u8], diffs: &mut Vec<Diff>) {
+        let (old, new) = (self.subtree(prefix), other.subtree(prefix));
+        if self.hashed && other.hashed && self.subtree_hash(prefix) == other.subtree_hash(prefix) {
+            return;
+        }
+        let (old_node, old_path, new_node, new_path) = match (old, new) {
+            (None, None) => return,
+            (Some((Child::Node(old), old_depth)), Some((Child::Node(new), new_depth))) => {
+                let old_path = [&prefix[..old_depth], &old.prefix].concat();
+                let new_path = [&prefix[..new_depth], &new.prefix].concat();
+                (old, old_path, new, new_path)
+            }
+            (old, new) => {
+                let old = entries(Box::new(old.map(|(child, _)| child).into_iter()));
+                let new = entries(Box::new(new.map(|(child, _)| child).into_iter()));
+                diffs.extend(Merge::new(old, new));
+                return;
+            }
+        };
+
+        let common = common_len(&old_path, &new_path);
+        if common < old_path.len() && common < new_path.len() {
+            // the keys diverge before the nodes branch, nothing is in common
+            let old = entries(Box::new(core::iter::once(old.unwrap().0)));
+            let new = entries(Box::new(core::iter::once(new.unwrap().0)));
+            diffs.extend(Merge::new(old, new));
+            return;
+        }
+        // at least one of the nodes branches here, the other one only has the
+        // next byte of its path
+        let branch = &old_path[..common];
+        let old_end = old_node.end.as_ref().filter(|_| old_path.len() == common);
+        let new_end = new_node.end.as_ref().filter(|_| new_path.len() == common);
+        diffs.extend(Merge::new(
+            old_end.map(|leaf| (&*leaf.key, leaf.value)).into_iter(),
+            new_end.map(|leaf| (&*leaf.key, leaf.value)).into_iter(),
+        ));
+        let bytes = |path: &[u8], node: &Node| -> Vec<u8> {
+            match path.get(common) {
+                Some(&byte) => Vec::from([byte]),
+                None => node.children.iter().map(|(byte, _)| byte).collect(),
+            }
+        };
+        let mut bytes = [bytes(&old_path, old_node), bytes(&new_path, new_node)].concat();
+        bytes.sort_unstable();
+        bytes.dedup();
+        for byte in bytes {
+            self.reconcile_at(other, &[branch, &[byte]].concat(), diffs);
+        }
+    }
+
+    /// The subtree holding exactly the entries whose key starts with
+    /// `prefix`, with the depth of the key byte leading to it.
+    fn subtree(&self, prefix: &[u8]) -> Option<(&Child, usize)> {
+        let mut child = self.root.as_ref()?;
+        let mut depth = 0;
+        loop {
+            let node = match child {
+                Child::Leaf(leaf) => return leaf.key.starts_with(prefix).then_some((child, depth)),
+                Child::Node(node) => node,
+            };
+            let rest = &prefix[depth..];
+            if rest.len() <= node.prefix.len() {
+                return node.prefix.starts_with(rest).then_some((child, depth));
+            }
+            if !rest.starts_with(&node.prefix) {
+                return None;
+            }
+            let next = depth + node.prefix.len();
+            child = node.children.find_child(prefix[next])?;
+            depth = next + 1;
+        }
+    }
+
+    /// Insert in place, only copying the nodes shared with other versions.
+    ///
+    /// A `deferred` insertion leaves the hashes of the nodes it modifies
+    /// stale, see [`Txn::commit`].
+    fn insert_mut(&mut self, key: &[u8], value: u64, deferred: bool) -> Option<u64> {
+        let hashing = Hashing::new(self.hashed, deferred);
+        let (root, old_value) = match self.root.take() {
+            Some(root) => insert_at(root, key, 0, value, hashing),
+            None => (Child::Leaf(Leaf::new(key, value, self.hashed)), None),
+        };
+        self.root = Some(root);
+        if old_value.is_none() {
+            self.len += 1;
+        }
+        old_value
+    }
+
+    /// Remove in place, only copying the nodes shared with other versions.
+    fn remove_mut(&mut self, key: &[u8], deferred: bool) -> Option<u64> {
+        // nothing is copied when the key is missing
+        self.get(key)?;
+        let hashing = Hashing::new(self.hashed, deferred);
+        let (root, value) = remove_at(self.root.take()?, key, 0, hashing);
+        self.root = root;
+        self.len -= 1;
+        Some(value)
+    }
+}
+
+/// A batch of modifications applied on top of a version of a
+/// [`PersistentArt`], returned by [`PersistentArt::transaction`].
+///
+/// The first modification reaching a node shared with other versions copies
+/// it, the following ones modify the copy in place since the transaction is
+/// its only owner. A large batch thus copies each shared node at most once
+/// instead of copying a path per modification. Nothing is visible to the
+/// other versions until [`commit`](Self::commit), and dropping the
+/// transaction discards it.
+#[derive(Debug)]
+pub struct Txn {
+    version: PersistentArt,
+}
+
+impl Txn {
+    pub fn insert(&mut self, key: &[u8], value: u64) -> Option<u64> {
+        self.version.insert_mut(key, value, true)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<u64> {
+        self.version.remove_mut(key, true)
+    }
+
+    /// Read `key` as modified by the transaction so far.
+    pub fn get(&self, key: &[u8]) -> Option<u64> {
+        self.version.get(key)
+    }
+
+    /// Return the version of the tree with all the modifications applied.
+    ///
+    /// The hashes of the nodes modified by the transaction are computed
+    /// here, once per node whatever the number of modifications below it.
+    pub fn commit(mut self) -> PersistentArt {
+        if let Some(root) = &mut self.version.root {
+            Node::refresh(root, ContentHasher::new(ContentHasher::NODE), 0);
+        }
+        self.version
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::test::{seeded_rng, test_rng};
+
+    /// Random keys made of the bytes of `alphabet` and shorter than `max_len`,
+    /// along with the random number they were drawn from, which picks the
+    /// operation to apply.
+    fn random_ops(
+        rng: impl Iterator<Item = u64>,
+        alphabet: &'static [u8],
+        max_len: usize,
+    ) -> impl Iterator<Item = (Vec<u8>, u64)> {
+        rng.map(move |rng| {
+            let len = (rng >> 60) as usize % max_len;
+            let key = (0..len)
+                .map(|i| alphabet[(rng >> (i * 2)) as usize % alphabet.len()])
+                .collect();
+            (key, rng)
+        })
+    }
+
+    fn entries(art: &PersistentArt) -> Vec<(Vec<u8>, u64)> {
+        art.iter()
+            .map(|(key, value)| (key.to_vec(), value))
+            .collect()
+    }
+
+    #[test]
+    fn versions() {
+        let v0 = PersistentArt::new();
+        let v1 = v0.insert(b"hello", 1);
+        let v2 = v1.insert(b"help", 2).insert(b"he", 3);
+        let v3 = v2.insert(b"hello", 4).remove(b"help");
+
+        assert!(v0.is_empty());
+        assert_eq!(entries(&v1), [(b"hello".to_vec(), 1)]);
+        assert_eq!(
+            entries(&v2),
+            [
+                (b"he".to_vec(), 3),
+                (b"hello".to_vec(), 1),
+                (b"help".to_vec(), 2)
+            ]
+        );
+        assert_eq!(entries(&v3), [(b"he".to_vec(), 3), (b"hello".to_vec(), 4)]);
+        assert_eq!(v3.get(b"hello"), Some(4));
+        assert_eq!(v2.get(b"hello"), Some(1));
+        assert_eq!(v3.get(b"help"), None);
+        assert_eq!(v2.get(b"help"), Some(2));
+        assert_eq!((v1.len(), v2.len(), v3.len()), (1, 3, 2));
+
+        // removing a missing key doesn't copy anything
+        assert!(v3.remove(b"nope").ptr_eq(&v3));
+        assert!(v3.snapshot().ptr_eq(&v3));
+        assert!(!v3.insert(b"hello", 4).ptr_eq(&v3));
+    }
+
+    #[test]
+    fn share_unchanged_nodes() {
+        let mut art = PersistentArt::new();
+        for prefix in [b'a', b'b', b'c'] {
+            for i in 0..100u8 {
+                art = art.insert(&[prefix, i, i], u64::from(i));
+            }
+        }
+        let old = art.snapshot();
+        let new = art.insert(&[b'b', 7, 0], 42);
+
+        let (Some(Child::Node(old)), Some(Child::Node(new))) = (&old.root, &new.root) else {
+            panic!("The root should be a node");
+        };
+        assert!(!Arc::ptr_eq(old, new));
+        for byte in [b'a', b'b', b'c'] {
+            let (Some(Child::Node(a)), Some(Child::Node(b))) =
+                (old.children.find_child(byte), new.children.find_child(byte))
+            else {
+                panic!("The children should be nodes");
+            };
+            // only the modified path was copied
+            assert_eq!(Arc::ptr_eq(a, b), byte != b'b', "{}", byte as char);
+        }
+        drop(art);
+        // a node is only referenced by the versions sharing it
+        let Some(Child::Node(a)) = old.children.find_child(b'a') else {
+            unreachable!()
+        };
+        assert_eq!(Arc::strong_count(a), 2);
+    }
+
+    #[test]
+    fn grow_and_shrink() {
+        let mut versions = Vec::new();
+        let mut art = PersistentArt::new();
+        for byte in 0..=255u8 {
+            art = art.insert(&[byte], u64::from(byte));
+            versions.push(art.snapshot());
+        }
+        for byte in 0..=255u8 {
+            art = art.remove(&[byte]);
+            versions.push(art.snapshot());
+        }
+        assert!(art.is_empty());
+        assert!(art.root.is_none());
+        for (i, version) in versions.iter().enumerate() {
+            let expected: Vec<u8> = if i < 256 {
+                (0..=i as u8).collect()
+            } else {
+                (i - 255..=255).map(|byte| byte as u8).collect()
+            };
+            let keys: Vec<u8> = version.iter().map(|(key, _)| key[0]).collect();
+            assert_eq!(keys, expected, "version {i}");
+        }
+    }
+
+    #[test]
+    fn random_against_btreemap() {
+        let mut art = PersistentArt::new();
+        let mut map = BTreeMap::new();
+        let mut versions = Vec::new();
+        for (i, (key, rng)) in (0..5_000).zip(random_ops(test_rng(), b"abcd", 5)) {
+            if rng % 3 == 0 {
+                art = art.remove(&key);
+                map.remove(&key);
+            } else {
+                art = art.insert(&key, i);
+                map.insert(key, i);
+            }
+            if i % 100 == 0 {
+                versions.push((art.snapshot(), map.clone()));
+            }
+        }
+        for (art, map) in versions {
+            assert_eq!(art.len(), map.len());
+            let expected: Vec<(Vec<u8>, u64)> = map.into_iter().collect();
+            assert_eq!(entries(&art), expected);
+            for (key, value) in expected {
+                assert_eq!(art.get(&key), Some(value));
+            }
+        }
+    }
+
+    #[test]
+    fn transaction() {
+        let mut base = PersistentArt::new();
+        for i in 0..100u8 {
+            base = base.insert(&[b'a', i], u64::from(i));
+            base = base.insert(&[b'b', i], u64::from(i));
+        }
+        let root = |art: &PersistentArt| match &art.root {
+            Some(Child::Node(node)) => Arc::as_ptr(node),
+            _ => panic!("The root should be a node"),
+        };
+
+        let mut txn = base.transaction();
+        assert_eq!(txn.insert(&[b'a', 0], 42), Some(0));
+        let copied = root(&txn.version);
+        assert_ne!(copied, root(&base));
+        for i in 0..100u8 {
+            txn.insert(&[b'a', i, i], 1000);
+            txn.remove(&[b'a', i]);
+        }
+        assert_eq!(txn.remove(b"missing"), None);
+        // the nodes copied by the first modification were reused
+        assert_eq!(root(&txn.version), copied);
+        assert_eq!(txn.get(&[b'a', 7, 7]), Some(1000));
+        assert_eq!(txn.get(&[b'a', 7]), None);
+
+        // the base version didn't see anything
+        assert_eq!(base.get(&[b'a', 0]), Some(0));
+        assert_eq!(base.get(&[b'a', 7, 7]), None);
+        assert_eq!(base.len(), 200);
+
+        let committed = txn.commit();
+        assert_eq!(committed.len(), 200);
+        let mut expected = PersistentArt::new();
+        for i in 0..100u8 {
+            expected = expected.insert(&[b'a', i, i], 1000);
+            expected = expected.insert(&[b'b', i], u64::from(i));
+        }
+        assert_eq!(entries(&committed), entries(&expected));
+        // the untouched subtree is still shared with the base
+        let (Some(Child::Node(a)), Some(Child::Node(b))) = (&base.root, &committed.root) else {
+            unreachable!()
+        };
+        let (Some(Child::Node(a)), Some(Child::Node(b))) =
+            (a.children.find_child(b'b'), b.children.find_child(b'b'))
+        else {
+            panic!("The children should be nodes");
+        };
+        assert!(Arc::ptr_eq(a, b));
+
+        // dropping a transaction rolls it back
+        let mut txn = committed.transaction();
+        txn.insert(b"rollback", 1);
+        drop(txn);
+        assert_eq!(committed.get(b"rollback"), None);
+    }
+
+    #[test]
+    fn diff() {
+        let mut base = PersistentArt::new();
+        for key in ["abcdefgh", "abcdefij", "abcdxy", "b", "bb", "bbb"] {
+            base = base.insert(key.as_bytes(), 1);
+        }
+        let mut txn = base.transaction();
+        // split the prefix of the node under `a`
+        txn.insert(b"abz", 2);
+        txn.insert(b"abcdefgh", 3);
+        txn.remove(b"bb");
+        txn.insert(b"bbbb", 4);
+        let new = txn.commit();
+
+        assert_eq!(
+            base.diff(&new).collect::<Vec<_>>(),
+            [
+                Diff::Changed(b"abcdefgh".to_vec(), 1, 3),
+                Diff::Added(b"abz".to_vec(), 2),
+                Diff::Removed(b"bb".to_vec(), 1),
+                Diff::Added(b"bbbb".to_vec(), 4),
+            ]
+        );
+        assert_eq!(base.diff(&base).count(), 0);
+        assert_eq!(new.diff(&PersistentArt::new()).count(), new.len());
+    }
+
+    #[test]
+    fn diff_random_versions() {
+        let mut rng = seeded_rng(0x9e37_79b9_7f4a_7c15);
+        let mut next = || rng.next().unwrap();
+        let mut ops = random_ops(test_rng(), b"abc", 6);
+
+        let mut versions = Vec::from([PersistentArt::new()]);
+        for i in 0..300 {
+            let mut txn = versions[next() as usize % versions.len()].transaction();
+            for _ in 0..next() % 8 {
+                let (key, rng) = ops.next().unwrap();
+                if rng % 4 == 0 {
+                    txn.remove(&key);
+                } else {
+                    txn.insert(&key, i % 3);
+                }
+            }
+            versions.push(txn.commit());
+        }
+        for _ in 0..1_000 {
+            let old = &versions[next() as usize % versions.len()];
+            let new = &versions[next() as usize % versions.len()];
+            let expected: Vec<Diff> = Merge::new(old.iter(), new.iter()).collect();
+            assert_eq!(old.diff(new).collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn hashes() {
+        let build = |keys: &[&str]| {
+            let mut txn = PersistentArt::with_hashes().transaction();
+            for key in keys {
+                txn.insert(key.as_bytes(), key.len() as u64);
+            }
+            txn.commit()
+        };
+        let a = build(&["abcx", "abcy"]);
+        let b = build(&["z", "abcy", "abcx"]);
+        assert_ne!(a.root_hash(), b.root_hash());
+        // the subtree under `ab` is the root of `a` but not of `b`
+        assert_eq!(a.subtree_hash(b"ab"), b.subtree_hash(b"ab"));
+        assert_eq!(a.subtree_hash(b"abcx"), b.subtree_hash(b"abcx"));
+        assert_ne!(a.subtree_hash(b"abcx"), a.subtree_hash(b"abcy"));
+        assert_eq!(a.subtree_hash(b"q"), Some(0));
+        assert_eq!(b.remove(b"z").root_hash(), a.root_hash());
+        assert_ne!(a.insert(b"abcx", 0).root_hash(), a.root_hash());
+        assert_eq!(PersistentArt::new().insert(b"abcx", 4).root_hash(), None);
+    }
+
+    #[test]
+    fn hashes_random() {
+        let mut art = PersistentArt::with_hashes();
+        let mut map = BTreeMap::new();
+        for (i, (key, rng)) in (0..3_000).zip(random_ops(test_rng(), b"abcd", 5)) {
+            if rng % 3 == 0 {
+                art = art.remove(&key);
+                map.remove(&key);
+            } else {
+                art = art.insert(&key, i % 7);
+                map.insert(key, i % 7);
+            }
+            if i % 100 == 0 {
+                // the hashes maintained incrementally match a tree built at once
+                let mut fresh = PersistentArt::with_hashes().transaction();
+                for (key, value) in map.iter().rev() {
+                    fresh.insert(key, *value);
+                }
+                assert_eq!(art.root_hash(), fresh.commit().root_hash());
+            }
+        }
+    }
+
+    #[test]
+    fn hashes_txn() {
+        let mut ops = random_ops(seeded_rng(0x1234_5678_9abc_def1), b"abcd", 6);
+
+        // transactions over a shared base rehash the nodes they modified at
+        // commit, and agree with the modifications applied one by one
+        let mut art = PersistentArt::with_hashes();
+        for round in 0..30 {
+            let base = art.clone();
+            let mut txn = art.transaction();
+            for (i, (key, rng)) in (0..50).zip(&mut ops) {
+                if rng % 3 == 0 {
+                    txn.remove(&key);
+                    art = art.remove(&key);
+                } else {
+                    txn.insert(&key, round * 50 + i);
+                    art = art.insert(&key, round * 50 + i);
+                }
+            }
+            let committed = txn.commit();
+            assert_eq!(committed.root_hash(), art.root_hash());
+            for prefix in [&b"a"[..], b"ab", b"bcd", b"dd"] {
+                assert_eq!(committed.subtree_hash(prefix), art.subtree_hash(prefix));
+            }
+            // the base version isn't touched by the transaction
+            assert_eq!(base.root_hash(), base.transaction().commit().root_hash());
+        }
+    }
+
+    #[test]
+    fn reconcile() {
+        let mut ops = random_ops(seeded_rng(0x9e37_79b9_7f4a_7c15), b"abc", 8);
+        let mut random_key = || ops.next().unwrap();
+
+        let mut base = BTreeMap::new();
+        for i in 0..500 {
+            base.insert(random_key().0, i);
+        }
+        for round in 0..50 {
+            // two replicas built separately, without any shared node
+            let mut replicas = [base.clone(), base.clone()];
+            for _ in 0..round % 10 {
+                let (key, rng) = random_key();
+                let replica = &mut replicas[rng as usize % 2];
+                if rng % 3 == 0 {
+                    replica.remove(&key);
+                } else {
+                    replica.insert(key, rng % 5);
+                }
+            }
+            let [old, new] = replicas.map(|map| {
+                let mut txn = PersistentArt::with_hashes().transaction();
+                for (key, value) in map {
+                    txn.insert(&key, value);
+                }
+                txn.commit()
+            });
+            let expected: Vec<Diff> = Merge::new(old.iter(), new.iter()).collect();
+            assert_eq!(old.reconcile(&new), expected);
+            assert_eq!(old.diff(&new).collect::<Vec<_>>(), expected);
+            // without the hashes nothing is skipped but the result is the same
+            let mut unhashed = PersistentArt::new().transaction();
+            for (key, value) in new.iter() {
+                unhashed.insert(key, value);
+            }
+            assert_eq!(old.reconcile(&unhashed.commit()), expected);
+        }
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<PersistentArt>();
+        assert_send_sync::<Txn>();
+    }
+}
@@ -0,0 +1,262 @@
+//! Lookups of the stored keys that are a prefix of a query, as done by a
+//! routing table or a URL router.
+//!
+//! All of them live on the path of the query: each inner node may store
+//! the key ending right after its compressed path in its end slot, and the
+//! path stops at the child where the last one ends. The bytes skipped in a
+//! truncated compressed path are verified against the full key of the
+//! leaves, like in a regular lookup.
+
+use crate::{child::ChildRef, Allocator, Art, InnerNode, Node};
+
+/// An iterator over the entries whose key is a prefix of a query, see
+/// [`Art::all_prefixes_of`]. It yields the length of the key and its value,
+/// from the shortest key to the longest.
+pub struct Prefixes<'a, 'k> {
+    key: &'k [u8],
+    walk: PrefixWalk<'a>,
+}
+
+impl Iterator for Prefixes<'_, '_> {
+    type Item = (usize, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walk.next(self.key)
+    }
+}
+
+/// The state of a walk down the path of a query, kept apart from the
+/// query so it can also be owned by the iterator.
+pub(crate) struct PrefixWalk<'a> {
+    /// The next child on the path of the key, with the number of bytes
+    /// consumed to reach it.
+    next: Option<(ChildRef<'a>, usize)>,
+}
+
+impl<'a> PrefixWalk<'a> {
+    pub fn new(root: &'a Node) -> Self {
+        PrefixWalk {
+            next: Some((ChildRef::Node(root), 0)),
+        }
+    }
+
+    /// Return the next entry whose key is a prefix of `key`, which must be
+    /// the same query on every call.
+    pub fn next(&mut self, key: &[u8]) -> Option<(usize, u64)> {
+        loop {
+            let (child, depth) = self.next.take()?;
+            let node = match child {
+                ChildRef::Node(node) if !node.inner.is_leaf() => node,
+                leaf => return entry(key, leaf, depth),
+            };
+            let stored = node.stored_prefix();
+            if key.get(depth..depth + stored.len())? != stored {
+                return None;
+            }
+            let depth = depth + node.prefix_len as usize;
+            self.next = key.get(depth).and_then(|byte| {
+                let child = node.inner.find_child(*byte)?;
+                Some((child.get(), depth + 1))
+            });
+            let end = node.end.as_ref().filter(|_| depth <= key.len());
+            if let Some(entry) = end.and_then(|end| entry(key, end.get(), depth)) {
+                return Some(entry);
+            }
+        }
+    }
+}
+
+/// Return the entry stored in a slot reached after `depth` bytes, if its
+/// key is a prefix of `key`.
+fn entry(key: &[u8], child: ChildRef, depth: usize) -> Option<(usize, u64)> {
+    match child {
+        // no bytes were skipped on the way to an inlined value
+        ChildRef::Value(value) => Some((depth, value)),
+        ChildRef::Node(node) => match &node.inner {
+            InnerNode::SingleValueLeaf {
+                key: leaf_key,
+                value,
+            } => key
+                .starts_with(leaf_key)
+                .then_some((leaf_key.len(), *value)),
+            _ => None,
+        },
+    }
+}
+
+impl<A: Allocator> Art<A> {
+    /// Iterate over the entries whose key is a prefix of `key`, including
+    /// `key` itself, from the shortest to the longest. Each entry is the
+    /// length of its key and its value.
+    pub fn all_prefixes_of<'a, 'k>(&'a self, key: &'k [u8]) -> Prefixes<'a, 'k> {
+        Prefixes {
+            key,
+            walk: PrefixWalk::new(&self.root),
+        }
+    }
+
+    /// Return the entry with the longest key that is a prefix of `key`, as
+    /// the length of its key and its value.
+    pub fn longest_prefix_match(&self, key: &[u8]) -> Option<(usize, u64)> {
+        self.all_prefixes_of(key).last()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::test::test_rng;
+
+    fn routes() -> Art {
+        let mut art = Art::new();
+        for (i, route) in [
+            "/",
+            "/api",
+            "/api/v1",
+            "/api/v1/users",
+            "/api/v2",
+            "/static/a/very/long/path/to/assets",
+            "/static/a/very/long/path/to/assets/img",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            art.insert(route.as_bytes(), i as u64);
+        }
+        // doesn't fit in a slot
+        art.insert(b"/api/v1/admin", u64::MAX);
+        art
+    }
+
+    #[test]
+    fn all_prefixes_of() {
+        let art = routes();
+        let prefixes = |key: &str| art.all_prefixes_of(key.as_bytes()).collect::<Vec<_>>();
+        insta::assert_debug_snapshot!(prefixes("/api/v1/users/42"), @r###"
+        [
+            (
+                1,
+                0,
+            ),
+            (
+                4,
+                1,
+            ),
+            (
+                7,
+                2,
+            ),
+            (
+                13,
+                3,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(prefixes("/api/v1/admin"), @r###"
+        [
+            (
+                1,
+                0,
+            ),
+            (
+                4,
+                1,
+            ),
+            (
+                7,
+                2,
+            ),
+            (
+                13,
+                18446744073709551615,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(prefixes("/static/a/very/long/path/to/assets/img/logo.png"), @r###"
+        [
+            (
+                1,
+                0,
+            ),
+            (
+                34,
+                5,
+            ),
+            (
+                38,
+                6,
+            ),
+        ]
+        "###);
+        // the skipped bytes of the truncated path are verified
+        insta::assert_debug_snapshot!(prefixes("/static/a/VERY/long/path/to/assets/img"), @r###"
+        [
+            (
+                1,
+                0,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(prefixes("/ap"), @r###"
+        [
+            (
+                1,
+                0,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(prefixes(""), @"[]");
+        insta::assert_debug_snapshot!(Art::new().all_prefixes_of(b"/").count(), @"0");
+    }
+
+    #[test]
+    fn longest_prefix_match() {
+        let art = routes();
+        assert_eq!(art.longest_prefix_match(b"/api/v2/users"), Some((7, 4)));
+        assert_eq!(art.longest_prefix_match(b"/api/v3"), Some((4, 1)));
+        assert_eq!(art.longest_prefix_match(b"/api"), Some((4, 1)));
+        assert_eq!(art.longest_prefix_match(b"/index.html"), Some((1, 0)));
+        assert_eq!(art.longest_prefix_match(b"index.html"), None);
+
+        let mut art = Art::new();
+        art.insert(b"leaf", 1);
+        assert_eq!(art.longest_prefix_match(b"leaf and more"), Some((4, 1)));
+        assert_eq!(art.longest_prefix_match(b"lea"), None);
+    }
+
+    #[test]
+    fn all_prefixes_of_random_keys() {
+        let mut art = Art::new();
+        let mut map = std::collections::BTreeMap::new();
+        for (i, rng) in (0..5_000).zip(test_rng()) {
+            // few distinct bytes so the keys are often prefix of each other,
+            // and compressed paths longer than what fits in the header
+            let len = (rng >> 59) as usize;
+            let bits = (0..len).map(|b| (rng >> (b * 2) & 1) as u8);
+            let key: Vec<u8> = match i % 3 {
+                0 => b"a long shared prefix"
+                    .iter()
+                    .copied()
+                    .chain(bits)
+                    .collect(),
+                _ => bits.collect(),
+            };
+            let value = if i % 7 == 0 { u64::MAX - i } else { i };
+            art.insert(&key, value);
+            map.insert(key, value);
+        }
+        for (key, _) in map.iter().step_by(7) {
+            for len in 0..=key.len() + 1 {
+                let mut query = key.clone();
+                query.resize(len, 1);
+                let expected: Vec<_> = (0..=query.len())
+                    .filter_map(|len| Some((len, *map.get(&query[..len])?)))
+                    .collect();
+                let prefixes: Vec<_> = art.all_prefixes_of(&query).collect();
+                assert_eq!(prefixes, expected, "{query:?}");
+            }
+        }
+    }
+}
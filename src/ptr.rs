@@ -0,0 +1,155 @@
+//! Owning pointers to the memory the tree obtained from its allocator.
+//!
+//! Unlike a `Box`, a [`Ptr`] doesn't store its allocator, which would cost
+//! a few bytes per node for most allocators. The tree keeps the allocator
+//! and frees its nodes explicitly, dropping a `Ptr` leaks its memory.
+
+use core::{alloc::Layout, fmt, marker::PhantomData, ops, ptr::NonNull};
+
+use allocator_api2::alloc::Allocator;
+
+use crate::ArtError;
+
+pub(crate) struct Ptr<T: ?Sized> {
+    ptr: NonNull<T>,
+    _marker: PhantomData<T>,
+}
+
+// Safety: a `Ptr` owns its value exactly like a `Box` would.
+unsafe impl<T: ?Sized + Send> Send for Ptr<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for Ptr<T> {}
+
+impl<T> Ptr<T> {
+    /// Move `value` in memory allocated by `alloc`, or give it back if the
+    /// allocation fails.
+    pub fn try_new_in<A: Allocator>(value: T, alloc: &A) -> Result<Self, T> {
+        match alloc.allocate(Layout::new::<T>()) {
+            Ok(ptr) => {
+                let ptr = ptr.cast::<T>();
+                // Safety: the memory was just allocated with the layout of a `T`
+                unsafe {
+                    ptr.as_ptr().write(value);
+                    Ok(Ptr::from_raw(ptr))
+                }
+            }
+            Err(_) => Err(value),
+        }
+    }
+
+    /// Allocate the memory of a `T` before building it with `f`, which
+    /// isn't called if the allocation fails.
+    pub fn new_with<A: Allocator>(alloc: &A, f: impl FnOnce() -> T) -> Result<Self, ArtError> {
+        let ptr = alloc
+            .allocate(Layout::new::<T>())
+            .map_err(|_| ArtError::OutOfMemory)?
+            .cast::<T>();
+        // Safety: the memory was just allocated with the layout of a `T`
+        unsafe {
+            ptr.as_ptr().write(f());
+            Ok(Ptr::from_raw(ptr))
+        }
+    }
+
+    /// Move the value out and give its memory back to `alloc`.
+    ///
+    /// # Safety
+    ///
+    /// The memory must have been allocated by `alloc`.
+    pub unsafe fn into_inner<A: Allocator>(self, alloc: &A) -> T {
+        let value = self.ptr.as_ptr().read();
+        alloc.deallocate(self.ptr.cast(), Layout::new::<T>());
+        value
+    }
+}
+
+impl Ptr<[u8]> {
+    pub fn from_slice_in<A: Allocator>(bytes: &[u8], alloc: &A) -> Result<Self, ArtError> {
+        let layout = Layout::for_value(bytes);
+        let ptr = alloc
+            .allocate(layout)
+            .map_err(|_| ArtError::OutOfMemory)?
+            .cast::<u8>();
+        // Safety: the memory was just allocated with the layout of `bytes`
+        unsafe {
+            ptr.as_ptr()
+                .copy_from_nonoverlapping(bytes.as_ptr(), bytes.len());
+            Ok(Ptr::from_raw(NonNull::slice_from_raw_parts(
+                ptr,
+                bytes.len(),
+            )))
+        }
+    }
+}
+
+impl<T> Ptr<[T]> {
+    /// Allocate `len` values built by `f` from their index, in memory
+    /// allocated by `alloc`.
+    pub fn from_fn_in<A: Allocator>(
+        len: usize,
+        mut f: impl FnMut(usize) -> T,
+        alloc: &A,
+    ) -> Result<Self, ArtError> {
+        let layout = Layout::array::<T>(len).map_err(|_| ArtError::OutOfMemory)?;
+        let ptr = alloc
+            .allocate(layout)
+            .map_err(|_| ArtError::OutOfMemory)?
+            .cast::<T>();
+        // Safety: the memory was just allocated with the layout of `len`
+        // values, which are all initialized before the slice is built
+        unsafe {
+            for i in 0..len {
+                ptr.as_ptr().add(i).write(f(i));
+            }
+            Ok(Ptr::from_raw(NonNull::slice_from_raw_parts(ptr, len)))
+        }
+    }
+
+    /// Drop the values and give their memory back to `alloc`.
+    ///
+    /// # Safety
+    ///
+    /// The memory must have been allocated by `alloc`.
+    pub unsafe fn free_in<A: Allocator>(self, alloc: &A) {
+        let layout = Layout::for_value(&*self);
+        self.ptr.as_ptr().drop_in_place();
+        alloc.deallocate(self.ptr.cast(), layout);
+    }
+}
+
+impl<T: ?Sized> Ptr<T> {
+    /// # Safety
+    ///
+    /// `ptr` must point to an initialized value owned by nothing else.
+    pub unsafe fn from_raw(ptr: NonNull<T>) -> Self {
+        Ptr {
+            ptr,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn into_raw(self) -> NonNull<T> {
+        self.ptr
+    }
+}
+
+impl<T: ?Sized> ops::Deref for Ptr<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: the pointer is valid until the tree frees it
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: ?Sized> ops::DerefMut for Ptr<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the pointer is valid until the tree frees it
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Ptr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
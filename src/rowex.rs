@@ -0,0 +1,613 @@
+//! A variant of the [`ConcurrentArt`](crate::ConcurrentArt) synchronized
+//! with ROWEX, the Read-Optimized Write-EXclusion protocol of
+//! [The ART of Practical Synchronization][artsync].
+//!
+//! Readers don't look at the locks and never restart: a lookup is a single
+//! descent along the key, which makes [`RowexArt::get_ref`] wait-free. Pinning
+//! its guard isn't, see [`RowexArt::pin`]. The writers exclude each other with the lock of
+//! the nodes they modify, taking the lock of a parent before the one of
+//! its child, and restart when a node they locked was replaced meanwhile.
+//!
+//! Every modification is made visible by a single atomic store, so a
+//! reader either sees the node before or after it:
+//! - the children are appended to a Node4, a Node16 or a Node48 and their
+//!   count is stored last. A removed child is cleared but its slot isn't
+//!   reused until the node is copied.
+//! - a node that is full, or whose compressed path is split, is replaced by
+//!   a copy built before being stored in its parent. A reader still in the
+//!   old node sees its content from before the replacement.
+//!
+//! Like in the OLC variant, the unlinked nodes and leaves are freed once no
+//! pinned [`Guard`] can reach them.
+//!
+//! [artsync]: https://db.in.tum.de/~leis/papers/artsync.pdf
+
+use alloc::boxed::Box;
+use core::sync::atomic::{
+    AtomicU8, AtomicUsize,
+    Ordering::{AcqRel, Acquire, Relaxed, Release},
+};
+
+use crate::{
+    common_len,
+    concurrent::{self, Children, Leaf, Restart, Root, Slot},
+    epoch::{Collector, Guard},
+};
+
+/// The children of a Node4 or a Node16, in insertion order.
+struct Append<const N: usize> {
+    /// The number of used slots, including the removed children.
+    count: AtomicU8,
+    keys: [AtomicU8; N],
+    children: [AtomicUsize; N],
+}
+
+impl<const N: usize> Default for Append<N> {
+    fn default() -> Self {
+        Append {
+            count: AtomicU8::new(0),
+            keys: core::array::from_fn(|_| AtomicU8::new(0)),
+            children: core::array::from_fn(|_| AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<const N: usize> Append<N> {
+    /// The slot of the child of `byte`, a removed child may have left a
+    /// cleared slot with the same byte before it.
+    fn position(&self, byte: u8) -> Option<usize> {
+        (0..self.count.load(Acquire) as usize)
+            .find(|i| self.keys[*i].load(Relaxed) == byte && self.children[*i].load(Acquire) != 0)
+    }
+
+    fn find_child(&self, byte: u8) -> usize {
+        self.position(byte)
+            .map_or(0, |i| self.children[i].load(Acquire))
+    }
+
+    fn insert(&self, byte: u8, child: usize) {
+        let slot = self.count.load(Relaxed);
+        self.keys[slot as usize].store(byte, Relaxed);
+        self.children[slot as usize].store(child, Relaxed);
+        self.count.store(slot + 1, Release);
+    }
+
+    fn change(&self, byte: u8, child: usize) {
+        if let Some(i) = self.position(byte) {
+            self.children[i].store(child, Release);
+        }
+    }
+
+    fn remove(&self, byte: u8) {
+        if let Some(i) = self.position(byte) {
+            self.children[i].store(0, Release);
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.count.load(Relaxed) as usize == N
+    }
+
+    fn for_each(&self, mut f: impl FnMut(u8, usize)) {
+        for i in 0..self.count.load(Acquire) as usize {
+            match self.children[i].load(Acquire) {
+                0 => (),
+                child => f(self.keys[i].load(Relaxed), child),
+            }
+        }
+    }
+}
+
+/// Up to 48 children, indexed by a table of 256 bytes.
+struct Indexed {
+    /// The number of used slots, including the removed children.
+    count: AtomicU8,
+    /// The slot of the child of each byte plus one, zero when there is none.
+    index: [AtomicU8; 256],
+    children: [AtomicUsize; 48],
+}
+
+impl Default for Indexed {
+    fn default() -> Self {
+        Indexed {
+            count: AtomicU8::new(0),
+            index: core::array::from_fn(|_| AtomicU8::new(0)),
+            children: core::array::from_fn(|_| AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl Indexed {
+    fn find_child(&self, byte: u8) -> usize {
+        match self.index[byte as usize].load(Acquire) {
+            0 => 0,
+            slot => self.children[slot as usize - 1].load(Acquire),
+        }
+    }
+
+    fn insert(&self, byte: u8, child: usize) {
+        let slot = self.count.load(Relaxed);
+        self.children[slot as usize].store(child, Relaxed);
+        self.index[byte as usize].store(slot + 1, Release);
+        self.count.store(slot + 1, Relaxed);
+    }
+
+    fn change(&self, byte: u8, child: usize) {
+        match self.index[byte as usize].load(Relaxed) {
+            0 => (),
+            slot => self.children[slot as usize - 1].store(child, Release),
+        }
+    }
+
+    fn remove(&self, byte: u8) {
+        match self.index[byte as usize].swap(0, Release) {
+            0 => (),
+            slot => self.children[slot as usize - 1].store(0, Release),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.count.load(Relaxed) == 48
+    }
+
+    fn for_each(&self, mut f: impl FnMut(u8, usize)) {
+        for byte in 0..=255 {
+            match self.find_child(byte) {
+                0 => (),
+                child => f(byte, child),
+            }
+        }
+    }
+}
+
+/// One child for each byte.
+struct Direct {
+    children: [AtomicUsize; 256],
+}
+
+impl Default for Direct {
+    fn default() -> Self {
+        Direct {
+            children: core::array::from_fn(|_| AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl Direct {
+    fn find_child(&self, byte: u8) -> usize {
+        self.children[byte as usize].load(Acquire)
+    }
+
+    fn change(&self, byte: u8, child: usize) {
+        self.children[byte as usize].store(child, Release);
+    }
+
+    fn for_each(&self, mut f: impl FnMut(u8, usize)) {
+        for byte in 0..=255 {
+            match self.find_child(byte) {
+                0 => (),
+                child => f(byte, child),
+            }
+        }
+    }
+}
+
+enum Inner {
+    Node4(Append<4>),
+    Node16(Append<16>),
+    Node48(Box<Indexed>),
+    Node256(Box<Direct>),
+}
+
+impl Inner {
+    /// The smallest node holding `len` children.
+    fn with_capacity(len: usize) -> Inner {
+        match len {
+            0..=4 => Inner::Node4(Append::default()),
+            5..=16 => Inner::Node16(Append::default()),
+            17..=48 => Inner::Node48(Box::default()),
+            _ => Inner::Node256(Box::default()),
+        }
+    }
+
+    fn find_child(&self, byte: u8) -> usize {
+        match self {
+            Inner::Node4(node) => node.find_child(byte),
+            Inner::Node16(node) => node.find_child(byte),
+            Inner::Node48(node) => node.find_child(byte),
+            Inner::Node256(node) => node.find_child(byte),
+        }
+    }
+
+    /// Whether a child can't be added without copying the node.
+    fn is_full(&self) -> bool {
+        match self {
+            Inner::Node4(node) => node.is_full(),
+            Inner::Node16(node) => node.is_full(),
+            Inner::Node48(node) => node.is_full(),
+            Inner::Node256(_) => false,
+        }
+    }
+
+    /// Replace the child of a byte.
+    fn change(&self, byte: u8, child: usize) {
+        match self {
+            Inner::Node4(node) => node.change(byte, child),
+            Inner::Node16(node) => node.change(byte, child),
+            Inner::Node48(node) => node.change(byte, child),
+            Inner::Node256(node) => node.change(byte, child),
+        }
+    }
+
+    fn remove(&self, byte: u8) {
+        match self {
+            Inner::Node4(node) => node.remove(byte),
+            Inner::Node16(node) => node.remove(byte),
+            Inner::Node48(node) => node.remove(byte),
+            Inner::Node256(node) => node.change(byte, 0),
+        }
+    }
+
+    fn len(&self) -> usize {
+        let mut len = 0;
+        self.for_each(|_, _| len += 1);
+        len
+    }
+}
+
+impl Children for Inner {
+    fn insert(&self, byte: u8, child: usize) {
+        match self {
+            Inner::Node4(node) => node.insert(byte, child),
+            Inner::Node16(node) => node.insert(byte, child),
+            Inner::Node48(node) => node.insert(byte, child),
+            Inner::Node256(node) => node.change(byte, child),
+        }
+    }
+
+    fn for_each(&self, f: impl FnMut(u8, usize)) {
+        match self {
+            Inner::Node4(node) => node.for_each(f),
+            Inner::Node16(node) => node.for_each(f),
+            Inner::Node48(node) => node.for_each(f),
+            Inner::Node256(node) => node.for_each(f),
+        }
+    }
+}
+
+type Node = concurrent::Node<Inner>;
+
+impl Node {
+    /// A copy of a locked node with another compressed path, in the
+    /// smallest type holding its children and `extra` more. The cleared
+    /// slots of the removed children are dropped.
+    fn copy(&self, prefix: &[u8], extra: usize) -> Node {
+        let node = Node::new(prefix, Inner::with_capacity(self.inner.len() + extra));
+        node.end.store(self.end.load(Relaxed), Relaxed);
+        self.inner
+            .for_each(|byte, child| node.inner.insert(byte, child));
+        node
+    }
+}
+
+/// Lock `parent` then `node`, and check that `node` is still the child
+/// of `byte` in `parent`.
+fn lock_both(parent: &Node, byte: u8, node: &Node) -> Result<(), Restart> {
+    parent.lock.lock()?;
+    if parent.inner.find_child(byte) != node as *const Node as usize {
+        parent.lock.unlock();
+        return Err(Restart);
+    }
+    if let Err(restart) = node.lock.lock() {
+        parent.lock.unlock();
+        return Err(restart);
+    }
+    Ok(())
+}
+
+/// An adaptive radix tree that can be read and modified from many threads
+/// through a shared reference, whose lookups never restart.
+///
+/// Compared to the [`ConcurrentArt`](crate::ConcurrentArt), the lookups
+/// never restart but the writers do more work: a node is copied when it is
+/// full even if some of its children were removed.
+pub struct RowexArt {
+    root: Root<Inner>,
+    collector: Collector,
+}
+
+impl Default for RowexArt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RowexArt {
+    pub fn new() -> Self {
+        RowexArt {
+            root: Root::new(Inner::Node256(Box::default())),
+            collector: Collector::default(),
+        }
+    }
+
+    /// Pin the current thread, the nodes and leaves can't be freed until
+    /// the guard is dropped.
+    ///
+    /// Pinning is lock-free but not wait-free: it claims a participant and
+    /// publishes its epoch with compare-and-swap loops, which retry when
+    /// other threads pin or advance the epoch at the same time. A thread
+    /// needing wait-free lookups pins a guard once and passes it to
+    /// [`RowexArt::get_ref`].
+    pub fn pin(&self) -> Guard<'_> {
+        self.collector.pin()
+    }
+
+    /// Replace the locked `node` by `replacement` in its locked parent.
+    fn replace(&self, parent: &Node, byte: u8, node: &Node, replacement: Node, guard: &Guard) {
+        parent.inner.change(byte, replacement.into_raw());
+        node.lock.unlock_obsolete();
+        parent.lock.unlock();
+        // SAFETY: the node was unlinked while its parent was locked
+        unsafe { node.retire(guard) };
+    }
+
+    /// Return the value of a key. This pins a guard for the lookup, so
+    /// unlike [`RowexArt::get_ref`] it's only lock-free.
+    pub fn get(&self, key: &[u8]) -> Option<u64> {
+        self.get_ref(key, &self.pin()).copied()
+    }
+
+    /// Return a reference to the value of a key, valid as long as the guard
+    /// even if the key is updated or removed meanwhile.
+    ///
+    /// The lookup is wait-free: it never waits for a writer nor restarts,
+    /// and finishes in a number of steps bounded by the length of the key.
+    pub fn get_ref<'g>(&'g self, key: &[u8], guard: &'g Guard) -> Option<&'g u64> {
+        assert!(
+            guard.belongs_to(&self.collector),
+            "The guard was pinned by another tree"
+        );
+        let mut node = &*self.root;
+        let mut depth = 0;
+        loop {
+            if !key[depth..].starts_with(&node.prefix) {
+                return None;
+            }
+            depth += node.prefix.len();
+            let child = match key.get(depth) {
+                Some(byte) => node.inner.find_child(*byte),
+                None => node.end.load(Acquire),
+            };
+            match self.root.child(child, guard) {
+                Slot::Empty => return None,
+                Slot::Leaf(leaf) => return (*leaf.key == *key).then_some(&leaf.value),
+                Slot::Node(child) => {
+                    node = child;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Insert a key and its value, return the previous value of the key.
+    pub fn insert(&self, key: &[u8], value: u64) -> Option<u64> {
+        let guard = self.pin();
+        loop {
+            if let Ok(old) = self.try_insert(key, value, &guard) {
+                return old;
+            }
+        }
+    }
+
+    fn try_insert<'g>(
+        &'g self,
+        key: &[u8],
+        value: u64,
+        guard: &'g Guard,
+    ) -> Result<Option<u64>, Restart> {
+        // the parent of the current node and the byte leading to it
+        let mut parent: Option<(&Node, u8)> = None;
+        let mut node = &*self.root;
+        let mut depth = 0;
+        loop {
+            let common = common_len(&node.prefix, &key[depth..]);
+            if common < node.prefix.len() {
+                // the key leaves the compressed path, the node is replaced by
+                // a new node holding the shared part of the path
+                let (parent, byte) = parent.expect("The root has no compressed path");
+                lock_both(parent, byte, node)?;
+                let split = Node::new(&node.prefix[..common], Inner::Node4(Append::default()));
+                let copy = node.copy(&node.prefix[common + 1..], 0);
+                split.inner.insert(node.prefix[common], copy.into_raw());
+                split.put(key, depth + common, Leaf::new_raw(key, value));
+                self.replace(parent, byte, node, split, guard);
+                return Ok(None);
+            }
+            depth += common;
+
+            let Some(&byte) = key.get(depth) else {
+                node.lock.lock()?;
+                let old = node.end.swap(Leaf::new_raw(key, value), AcqRel);
+                node.lock.unlock();
+                return Ok(match self.root.child(old, guard) {
+                    Slot::Leaf(leaf) => {
+                        // SAFETY: the leaf was replaced while its node was locked
+                        unsafe { guard.defer(old, Leaf::free) };
+                        Some(leaf.value)
+                    }
+                    _ => None,
+                });
+            };
+            let child = node.inner.find_child(byte);
+            match self.root.child(child, guard) {
+                Slot::Empty if node.inner.is_full() => {
+                    // the node is replaced by a copy with room for the child
+                    let (parent, parent_byte) = parent.expect("The root is never full");
+                    lock_both(parent, parent_byte, node)?;
+                    if node.inner.find_child(byte) != 0 {
+                        node.lock.unlock();
+                        parent.lock.unlock();
+                        return Err(Restart);
+                    }
+                    let copy = node.copy(&node.prefix, 1);
+                    copy.inner.insert(byte, Leaf::new_raw(key, value));
+                    self.replace(parent, parent_byte, node, copy, guard);
+                    return Ok(None);
+                }
+                Slot::Empty => {
+                    node.lock.lock()?;
+                    if node.inner.is_full() || node.inner.find_child(byte) != 0 {
+                        node.lock.unlock();
+                        return Err(Restart);
+                    }
+                    node.inner.insert(byte, Leaf::new_raw(key, value));
+                    node.lock.unlock();
+                    return Ok(None);
+                }
+                Slot::Leaf(leaf) => {
+                    node.lock.lock()?;
+                    if node.inner.find_child(byte) != child {
+                        node.lock.unlock();
+                        return Err(Restart);
+                    }
+                    if *leaf.key == *key {
+                        node.inner.change(byte, Leaf::new_raw(key, value));
+                        node.lock.unlock();
+                        // SAFETY: the leaf was replaced while its node was locked
+                        unsafe { guard.defer(child, Leaf::free) };
+                        return Ok(Some(leaf.value));
+                    }
+                    // both keys go in a new node holding their shared bytes
+                    let depth = depth + 1;
+                    let common = common_len(&leaf.key[depth..], &key[depth..]);
+                    let split =
+                        Node::new(&key[depth..depth + common], Inner::Node4(Append::default()));
+                    split.put(&leaf.key, depth + common, child);
+                    split.put(key, depth + common, Leaf::new_raw(key, value));
+                    node.inner.change(byte, split.into_raw());
+                    node.lock.unlock();
+                    return Ok(None);
+                }
+                Slot::Node(child) => {
+                    parent = Some((node, byte));
+                    node = child;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Remove a key, return its value if it was present.
+    pub fn remove(&self, key: &[u8]) -> Option<u64> {
+        let guard = self.pin();
+        loop {
+            if let Ok(old) = self.try_remove(key, &guard) {
+                return old;
+            }
+        }
+    }
+
+    fn try_remove<'g>(&'g self, key: &[u8], guard: &'g Guard) -> Result<Option<u64>, Restart> {
+        let mut parent: Option<(&Node, u8)> = None;
+        let mut node = &*self.root;
+        let mut depth = 0;
+        loop {
+            if !key[depth..].starts_with(&node.prefix) {
+                return Ok(None);
+            }
+            depth += node.prefix.len();
+            let byte = key.get(depth).copied();
+            let slot = |node: &Node| match byte {
+                Some(byte) => node.inner.find_child(byte),
+                None => node.end.load(Acquire),
+            };
+            let child = slot(node);
+            match self.root.child(child, guard) {
+                Slot::Empty => return Ok(None),
+                Slot::Leaf(leaf) if *leaf.key != *key => return Ok(None),
+                Slot::Leaf(leaf) => {
+                    let len =
+                        |node: &Node| node.inner.len() + usize::from(node.end.load(Relaxed) != 0);
+                    // the node would be empty, it is unlinked from its parent
+                    let unlink = parent.filter(|_| len(node) == 1);
+                    match unlink {
+                        Some((parent, parent_byte)) => lock_both(parent, parent_byte, node)?,
+                        None => node.lock.lock()?,
+                    }
+                    let unlock = || {
+                        node.lock.unlock();
+                        if let Some((parent, _)) = unlink {
+                            parent.lock.unlock();
+                        }
+                    };
+                    if slot(node) != child {
+                        unlock();
+                        return Err(Restart);
+                    }
+                    match unlink {
+                        Some((parent, parent_byte)) if len(node) == 1 => {
+                            parent.inner.remove(parent_byte);
+                            node.lock.unlock_obsolete();
+                            parent.lock.unlock();
+                            // SAFETY: the node was unlinked while its parent was locked
+                            unsafe { node.retire(guard) };
+                        }
+                        _ => {
+                            match byte {
+                                Some(byte) => node.inner.remove(byte),
+                                None => node.end.store(0, Release),
+                            }
+                            unlock();
+                        }
+                    }
+                    // SAFETY: the leaf was unlinked while its node was locked
+                    unsafe { guard.defer(child, Leaf::free) };
+                    return Ok(Some(leaf.value));
+                }
+                Slot::Node(child) => {
+                    // a node is only reached through a byte
+                    parent = byte.map(|byte| (node, byte));
+                    node = child;
+                    depth += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::concurrent::concurrent_tests;
+
+    concurrent_tests!(RowexArt);
+
+    #[test]
+    fn reuse_removed_slots() {
+        let art = RowexArt::new();
+        for round in 0..4 {
+            for byte in 0..=255 {
+                assert_eq!(art.insert(&[b'a', byte], byte as u64 + round), None);
+            }
+            for byte in (0..=255).step_by(2) {
+                assert_eq!(art.remove(&[b'a', byte]), Some(byte as u64 + round));
+            }
+            for byte in 0..=255 {
+                let expected = (byte % 2 == 1).then_some(byte as u64 + round);
+                assert_eq!(art.get(&[b'a', byte]), expected);
+            }
+            for byte in (1..=255).step_by(2) {
+                assert_eq!(art.remove(&[b'a', byte]), Some(byte as u64 + round));
+            }
+        }
+        // a Node4 whose slots are all used by removed children
+        assert_eq!(art.insert(&[b'b', 100], 0), None);
+        for byte in 0..10 {
+            assert_eq!(art.insert(&[b'b', byte], 1), None);
+            assert_eq!(art.remove(&[b'b', byte]), Some(1));
+            assert_eq!(art.get(&[b'b', 100]), Some(0));
+        }
+        assert_eq!(art.insert(&[b'b', 100], 2), Some(0));
+        assert_eq!(art.get(&[b'b', 100]), Some(2));
+    }
+}
@@ -0,0 +1,918 @@
+//! A compact binary format to store a tree and load it back without
+//! inserting its keys one by one.
+//!
+//! The format is versioned and describes itself. It is made of:
+//!
+//! - a header: the magic `ARTC`, the version of the format as a
+//!   little-endian `u16`, the type of the values and the CRC-32 of these
+//!   seven bytes,
+//! - the records of the nodes in post-order, a node is always written after
+//!   its children, followed by an end marker,
+//! - a trailer: the root, the number of records and the CRC-32 of
+//!   everything written after the header.
+//!
+//! A leaf record stores its full key and its value. An inner node record
+//! stores its type, its whole compressed path, the value ending after it and
+//! the key byte and reference of each of its children. A reference is either
+//! a value stored in the slot, or the distance back to the record of a node.
+//! Lengths, values and distances are LEB128 varints.
+//!
+//! Matching its checksums isn't enough for an input to be loaded, it must
+//! also describe a valid tree: every node is used once, the keys of the
+//! leaves follow the paths leading to them and no value is stored in a slot
+//! below a truncated path. The shape of the nodes isn't checked beyond
+//! their capacity: a node may have a single entry, or fewer children than
+//! its type is meant for, like the nodes a removal couldn't merge or shrink
+//! because the path would be too long or the allocator failed.
+//!
+//! The records are written and read back with explicit stacks rather than
+//! recursively, so a deep tree doesn't overflow the call stack.
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use std::io::{self, Read, Write};
+
+use crate::{
+    child::{Child, ChildRef},
+    error::FormatError,
+    node16::Node16,
+    node256::Node256,
+    node4::Node4,
+    node48::Node48,
+    ptr::Ptr,
+    Allocator, Art, Global, InnerNode, Node, Slots,
+};
+
+const MAGIC: [u8; 4] = *b"ARTC";
+const VERSION: u16 = 1;
+/// The only type of values, a `u64`.
+const VALUE_U64: u8 = 0;
+
+const END: u8 = 0;
+const LEAF: u8 = 1;
+const NODE4: u8 = 2;
+const NODE16: u8 = 3;
+const NODE48: u8 = 4;
+const NODE256: u8 = 5;
+
+const REF_NONE: u8 = 0;
+const REF_VALUE: u8 = 1;
+const REF_NODE: u8 = 2;
+
+/// The table of the reflected CRC-32 used by zlib and Ethernet.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+struct Crc32(u32);
+
+impl Crc32 {
+    fn new() -> Self {
+        Crc32(!0)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = CRC_TABLE[((self.0 ^ byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+fn push_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn header() -> [u8; 11] {
+    let mut header = [0; 11];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    header[6] = VALUE_U64;
+    let crc = crc32(&header[..7]);
+    header[7..].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+/// The content of a slot, a node being identified by the offset of its
+/// record from the end of the header.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Ref {
+    None,
+    Value(u64),
+    Node(u64),
+}
+
+struct Encoder<W> {
+    writer: W,
+    /// The number of bytes written after the header.
+    offset: u64,
+    crc: Crc32,
+    records: u64,
+    /// The record being written, to write it with a single call.
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    fn flush_record(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.buf)?;
+        self.crc.update(&self.buf);
+        self.offset += self.buf.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Push a reference made from the record starting at `start`.
+    fn push_ref(&mut self, reference: Ref, start: u64) {
+        match reference {
+            Ref::None => self.buf.push(REF_NONE),
+            Ref::Value(value) => {
+                self.buf.push(REF_VALUE);
+                push_varint(&mut self.buf, value);
+            }
+            Ref::Node(offset) => {
+                self.buf.push(REF_NODE);
+                push_varint(&mut self.buf, start - offset);
+            }
+        }
+    }
+
+    /// Write the records of `root` and everything below it, children
+    /// first.
+    fn tree(&mut self, root: &Node) -> io::Result<Ref> {
+        let mut stack = Vec::new();
+        let mut done = self.enter(root, 0, &mut stack)?;
+        loop {
+            let Some(top) = stack.last_mut() else {
+                return Ok(done.expect("The root was written"));
+            };
+            if let Some(reference) = done.take() {
+                match top.slot {
+                    None => top.end = reference,
+                    Some(byte) => top.children.push((byte, reference)),
+                }
+            }
+            let Some((byte, child)) = top.slots.next() else {
+                let pending = stack.pop().unwrap();
+                done = Some(self.inner(pending)?);
+                continue;
+            };
+            top.slot = byte;
+            let depth = top.depth + usize::from(byte.is_some());
+            done = match child.get() {
+                ChildRef::Value(value) => Some(Ref::Value(value)),
+                ChildRef::Node(node) => self.enter(node, depth, &mut stack)?,
+            };
+        }
+    }
+
+    /// Write the record of a leaf, or push an inner node on `stack` to
+    /// write its children first. `depth` is the number of key bytes
+    /// consumed before reaching the node.
+    fn enter<'a>(
+        &mut self,
+        node: &'a Node,
+        depth: usize,
+        stack: &mut Vec<Pending<'a>>,
+    ) -> io::Result<Option<Ref>> {
+        let kind = match &node.inner {
+            InnerNode::Empty => return Ok(Some(Ref::None)),
+            InnerNode::SingleValueLeaf { key, value } => {
+                let start = self.offset;
+                self.buf.push(LEAF);
+                push_varint(&mut self.buf, key.len() as u64);
+                self.buf.extend_from_slice(key);
+                push_varint(&mut self.buf, *value);
+                self.flush_record()?;
+                self.records += 1;
+                return Ok(Some(Ref::Node(start)));
+            }
+            InnerNode::Node4(_) => NODE4,
+            InnerNode::Node16(_) => NODE16,
+            InnerNode::Node48(_) => NODE48,
+            InnerNode::Node256(_) => NODE256,
+        };
+        let path = node.path(depth);
+        stack.push(Pending {
+            kind,
+            path,
+            depth: depth + path.len(),
+            slots: node.slots(),
+            slot: None,
+            end: Ref::None,
+            children: Vec::new(),
+        });
+        Ok(None)
+    }
+
+    /// Write the record of an inner node whose children were written.
+    fn inner(&mut self, pending: Pending<'_>) -> io::Result<Ref> {
+        if pending.end == Ref::None && pending.children.is_empty() {
+            return Ok(Ref::None);
+        }
+        let start = self.offset;
+        self.buf.push(pending.kind);
+        push_varint(&mut self.buf, pending.path.len() as u64);
+        self.buf.extend_from_slice(pending.path);
+        self.push_ref(pending.end, start);
+        push_varint(&mut self.buf, pending.children.len() as u64);
+        for (byte, child) in pending.children {
+            self.buf.push(byte);
+            self.push_ref(child, start);
+        }
+        self.flush_record()?;
+        self.records += 1;
+        Ok(Ref::Node(start))
+    }
+}
+
+/// An inner node whose children are being written, see [`Encoder::tree`].
+struct Pending<'a> {
+    kind: u8,
+    path: &'a [u8],
+    /// The number of key bytes consumed before reaching the children.
+    depth: usize,
+    /// The slots left to write, the end first and then the children.
+    slots: Slots<'a>,
+    /// The slot being written, `None` for the end.
+    slot: Option<u8>,
+    end: Ref,
+    children: Vec<(u8, Ref)>,
+}
+
+/// A node read from the input, before it is checked and allocated.
+enum Record {
+    Leaf {
+        key: Box<[u8]>,
+        value: u64,
+    },
+    Inner {
+        kind: u8,
+        path: Box<[u8]>,
+        end: Ref,
+        children: Vec<(u8, Ref)>,
+    },
+}
+
+struct Decoder<R> {
+    reader: R,
+    /// The number of bytes read after the header.
+    offset: u64,
+    crc: Crc32,
+}
+
+impl<R: Read> Decoder<R> {
+    fn byte(&mut self) -> Result<u8, FormatError> {
+        let mut byte = [0];
+        self.reader.read_exact(&mut byte)?;
+        self.crc.update(&byte);
+        self.offset += 1;
+        Ok(byte[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, FormatError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            if shift == 63 && byte > 1 {
+                break;
+            }
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(FormatError::Corrupted("varint overflows a u64"))
+    }
+
+    fn len(&mut self) -> Result<usize, FormatError> {
+        usize::try_from(self.varint()?).map_err(|_| FormatError::Corrupted("length too large"))
+    }
+
+    /// Read `len` bytes, without trusting `len` to allocate them upfront.
+    fn bytes(&mut self, len: usize) -> Result<Box<[u8]>, FormatError> {
+        let mut bytes = Vec::new();
+        (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(FormatError::Truncated);
+        }
+        self.crc.update(&bytes);
+        self.offset += len as u64;
+        Ok(bytes.into_boxed_slice())
+    }
+
+    /// Read a reference made from the record starting at `start`.
+    fn reference(&mut self, start: u64) -> Result<Ref, FormatError> {
+        match self.byte()? {
+            REF_NONE => Ok(Ref::None),
+            REF_VALUE => Ok(Ref::Value(self.varint()?)),
+            REF_NODE => match self.varint()? {
+                distance @ 1.. if distance <= start => Ok(Ref::Node(start - distance)),
+                _ => Err(FormatError::Corrupted(
+                    "reference to a node not written before",
+                )),
+            },
+            _ => Err(FormatError::Corrupted("unknown type of reference")),
+        }
+    }
+
+    fn record(&mut self, kind: u8, start: u64) -> Result<Record, FormatError> {
+        if kind == LEAF {
+            let len = self.len()?;
+            let key = self.bytes(len)?;
+            let value = self.varint()?;
+            return Ok(Record::Leaf { key, value });
+        }
+        let capacity = match kind {
+            NODE4 => 4,
+            NODE16 => 16,
+            NODE48 => 48,
+            NODE256 => 256,
+            _ => return Err(FormatError::Corrupted("unknown type of node")),
+        };
+        let len = self.len()?;
+        if u32::try_from(len).is_err() {
+            return Err(FormatError::Corrupted("compressed path too long"));
+        }
+        let path = self.bytes(len)?;
+        let end = self.reference(start)?;
+        let len = self.len()?;
+        if len > capacity {
+            return Err(FormatError::Corrupted(
+                "too many children for the type of node",
+            ));
+        }
+        let mut children: Vec<(u8, Ref)> = Vec::with_capacity(len);
+        for _ in 0..len {
+            let byte = self.byte()?;
+            if children.last().is_some_and(|(last, _)| *last >= byte) {
+                return Err(FormatError::Corrupted("children not sorted by key"));
+            }
+            match self.reference(start)? {
+                Ref::None => return Err(FormatError::Corrupted("empty child slot")),
+                child => children.push((byte, child)),
+            }
+        }
+        if end == Ref::None && children.is_empty() {
+            return Err(FormatError::Corrupted("inner node without children"));
+        }
+        Ok(Record::Inner {
+            kind,
+            path,
+            end,
+            children,
+        })
+    }
+}
+
+/// Allocate the nodes of the records from the root, checking they form a
+/// valid tree.
+struct Builder<'a, A: Allocator> {
+    starts: Vec<u64>,
+    records: Vec<Option<Record>>,
+    /// The key bytes consumed to reach the slot being built.
+    key: Vec<u8>,
+    /// The inner nodes whose children are being built, from the root.
+    stack: Vec<Building>,
+    alloc: &'a A,
+}
+
+/// An inner node whose children are being built, see [`Builder::tree`].
+struct Building {
+    node: Node,
+    /// The number of key bytes consumed before reaching the node.
+    depth: usize,
+    /// Whether a truncated path leads to the children.
+    optimistic: bool,
+    /// The slots left to build, the end first and then the children.
+    slots: vec::IntoIter<(Option<u8>, Ref)>,
+    /// The slot being built, `None` for the end.
+    slot: Option<u8>,
+}
+
+impl<A: Allocator> Builder<'_, A> {
+    fn take(&mut self, offset: u64) -> Result<Record, FormatError> {
+        let index = self
+            .starts
+            .binary_search(&offset)
+            .map_err(|_| FormatError::Corrupted("reference to the middle of a record"))?;
+        self.records[index]
+            .take()
+            .ok_or(FormatError::Corrupted("node referenced twice"))
+    }
+
+    /// Build the tree whose root is `record`, freeing what was built if the
+    /// input turns out to be invalid.
+    fn tree(&mut self, record: Record) -> Result<Node, FormatError> {
+        let built = self.build(record);
+        while let Some(building) = self.stack.pop() {
+            // Safety: the nodes are allocated by `self.alloc`
+            unsafe { building.node.free_in(self.alloc) };
+        }
+        built
+    }
+
+    fn build(&mut self, record: Record) -> Result<Node, FormatError> {
+        let mut done = self.enter(record, false, false)?;
+        loop {
+            if let Some(node) = done.take() {
+                let Some(top) = self.stack.last_mut() else {
+                    return Ok(node);
+                };
+                let child = Ptr::try_new_in(node, self.alloc).map_err(|node| {
+                    // Safety: the nodes are allocated by `self.alloc`
+                    unsafe { node.free_in(self.alloc) };
+                    FormatError::OutOfMemory
+                })?;
+                top.attach(Child::node(child));
+            }
+            let top = self.stack.last_mut().unwrap();
+            let Some((byte, reference)) = top.slots.next() else {
+                let building = self.stack.pop().unwrap();
+                self.key.truncate(building.depth);
+                done = Some(building.node);
+                continue;
+            };
+            top.slot = byte;
+            let optimistic = top.optimistic;
+            self.key.truncate(top.depth + top.node.prefix_len as usize);
+            self.key.extend(byte);
+            match reference {
+                Ref::None => return Err(FormatError::Corrupted("empty child slot")),
+                Ref::Value(_) if optimistic => {
+                    return Err(FormatError::Corrupted(
+                        "value stored below a truncated path",
+                    ))
+                }
+                Ref::Value(value) => {
+                    let child = Child::value(value)
+                        .ok_or(FormatError::Corrupted("value too large for a slot"))?;
+                    top.attach(child);
+                }
+                Ref::Node(offset) => {
+                    let record = self.take(offset)?;
+                    done = self.enter(record, optimistic, byte.is_none())?;
+                }
+            }
+        }
+    }
+
+    /// Allocate the node of a leaf, or push an inner node on the stack to
+    /// build its children first. `optimistic` tells if a truncated path
+    /// leads to it and `ends_here` if it is the end of an inner node.
+    fn enter(
+        &mut self,
+        record: Record,
+        optimistic: bool,
+        ends_here: bool,
+    ) -> Result<Option<Node>, FormatError> {
+        let (kind, path, end, children) = match record {
+            Record::Leaf { key, value } => {
+                if !key.starts_with(&self.key) || (ends_here && key.len() != self.key.len()) {
+                    return Err(FormatError::Corrupted(
+                        "key of a leaf not matching its path",
+                    ));
+                }
+                return Ok(Some(Node::leaf_in(&key, value, self.alloc)?));
+            }
+            Record::Inner { .. } if ends_here => {
+                return Err(FormatError::Corrupted(
+                    "inner node stored as the end of a path",
+                ))
+            }
+            Record::Inner {
+                kind,
+                path,
+                end,
+                children,
+            } => (kind, path, end, children),
+        };
+        let inner = match kind {
+            NODE4 => InnerNode::Node4(Node4::default()),
+            NODE16 => InnerNode::Node16(Ptr::new_with(self.alloc, Node16::default)?),
+            NODE48 => InnerNode::Node48(Ptr::new_with(self.alloc, Node48::default)?),
+            _ => InnerNode::Node256(Ptr::new_with(self.alloc, Node256::default)?),
+        };
+        let mut node = Node {
+            nb_childrens: children.len() as u16,
+            inner,
+            ..Default::default()
+        };
+        node.set_prefix(&path);
+
+        let depth = self.key.len();
+        self.key.extend_from_slice(&path);
+        let optimistic = optimistic || node.is_truncated();
+        let end = (end != Ref::None).then_some((None, end));
+        let children = children
+            .into_iter()
+            .map(|(byte, child)| (Some(byte), child));
+        self.stack.push(Building {
+            node,
+            depth,
+            optimistic,
+            slots: end
+                .into_iter()
+                .chain(children)
+                .collect::<Vec<_>>()
+                .into_iter(),
+            slot: None,
+        });
+        Ok(None)
+    }
+}
+
+impl Building {
+    /// Store the child of the slot being built.
+    fn attach(&mut self, child: Child) {
+        let Some(byte) = self.slot else {
+            self.node.end = Some(child);
+            return;
+        };
+        match &mut self.node.inner {
+            InnerNode::Node4(inner) => inner.insert(byte, child),
+            InnerNode::Node16(inner) => inner.insert(byte, child),
+            InnerNode::Node48(inner) => inner.insert(byte, child),
+            InnerNode::Node256(inner) => inner.insert(byte, child),
+            InnerNode::Empty | InnerNode::SingleValueLeaf { .. } => unreachable!(),
+        }
+    }
+}
+
+impl Art {
+    /// Load a tree written by [`Art::serialize_into`].
+    ///
+    /// The input is rejected if it is truncated, doesn't match its checksums
+    /// or doesn't describe a valid tree. It is read in small pieces, a
+    /// [`BufReader`](std::io::BufReader) should wrap unbuffered readers.
+    pub fn deserialize_from(reader: impl Read) -> Result<Art, FormatError> {
+        Art::deserialize_from_in(reader, Global)
+    }
+}
+
+impl<A: Allocator> Art<A> {
+    /// Write the tree to `writer` in a compact binary format, which can be
+    /// loaded back with [`Art::deserialize_from`] much faster than the keys
+    /// could be inserted again.
+    ///
+    /// The nodes are written in post-order with their type, their whole
+    /// compressed path and the key byte and offset of each child. The input
+    /// is protected by a version number and CRC-32 checksums. Every node is
+    /// written with a single call to `writer`.
+    pub fn serialize_into(&self, writer: impl Write) -> io::Result<()> {
+        let mut encoder = Encoder {
+            writer,
+            offset: 0,
+            crc: Crc32::new(),
+            records: 0,
+            buf: Vec::new(),
+        };
+        encoder.writer.write_all(&header())?;
+        let root = encoder.tree(&self.root)?;
+        encoder.buf.push(END);
+        encoder.flush_record()?;
+
+        let trailer = encoder.offset;
+        encoder.push_ref(root, trailer);
+        push_varint(&mut encoder.buf, encoder.records);
+        encoder.flush_record()?;
+        let crc = encoder.crc.finish();
+        encoder.writer.write_all(&crc.to_le_bytes())
+    }
+
+    /// Load a tree written by [`Art::serialize_into`], allocating its nodes
+    /// and keys with `alloc`, see [`Art::deserialize_from`].
+    pub fn deserialize_from_in(mut reader: impl Read, alloc: A) -> Result<Self, FormatError> {
+        let mut header = [0; 11];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(FormatError::BadMagic);
+        }
+        if crc32(&header[..7]).to_le_bytes() != header[7..] {
+            return Err(FormatError::ChecksumMismatch);
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        if header[6] != VALUE_U64 {
+            return Err(FormatError::UnsupportedValueType(header[6]));
+        }
+
+        let mut decoder = Decoder {
+            reader,
+            offset: 0,
+            crc: Crc32::new(),
+        };
+        let mut starts = Vec::new();
+        let mut records = Vec::new();
+        loop {
+            let start = decoder.offset;
+            match decoder.byte()? {
+                END => break,
+                kind => records.push(Some(decoder.record(kind, start)?)),
+            }
+            starts.push(start);
+        }
+        let trailer = decoder.offset;
+        let root = decoder.reference(trailer)?;
+        let count = decoder.varint()?;
+        let crc = decoder.crc.finish();
+        let mut expected = [0; 4];
+        decoder.reader.read_exact(&mut expected)?;
+        if crc.to_le_bytes() != expected {
+            return Err(FormatError::ChecksumMismatch);
+        }
+        if count != records.len() as u64 {
+            return Err(FormatError::Corrupted("wrong number of nodes"));
+        }
+
+        let mut art = Art::new_in(alloc);
+        let mut builder = Builder {
+            starts,
+            records,
+            key: Vec::new(),
+            stack: Vec::new(),
+            alloc: &art.alloc,
+        };
+        art.root = match root {
+            Ref::None => Node::default(),
+            Ref::Value(_) => return Err(FormatError::Corrupted("value stored at the root")),
+            Ref::Node(offset) => {
+                let record = builder.take(offset)?;
+                builder.tree(record)?
+            }
+        };
+        if builder.records.iter().any(Option::is_some) {
+            return Err(FormatError::Corrupted("node not reachable from the root"));
+        }
+        Ok(art)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, format};
+
+    use super::*;
+    use crate::test::test_rng;
+
+    fn round_trip(art: &Art) -> Art {
+        let mut bytes = Vec::new();
+        art.serialize_into(&mut bytes).unwrap();
+        let loaded = Art::deserialize_from(bytes.as_slice()).unwrap();
+        assert_eq!(
+            art.iter().collect::<Vec<_>>(),
+            loaded.iter().collect::<Vec<_>>()
+        );
+        // the nodes are the same, so is their serialization
+        let mut again = Vec::new();
+        loaded.serialize_into(&mut again).unwrap();
+        assert_eq!(bytes, again);
+        loaded
+    }
+
+    fn sample() -> Art {
+        let mut art = Art::new();
+        art.insert(b"", 0);
+        art.insert(b"ab", 1);
+        art.insert(b"ac", u64::MAX);
+        art.insert(b"abc", 3);
+        art.insert(b"0123456789a", 4);
+        art.insert(b"0123456789b", 5);
+        art.insert(b"0123456789", 6);
+        art
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn format() {
+        let mut art = Art::new();
+        art.insert(b"ab", 1);
+        art.insert(b"ac", 2);
+        let mut bytes = Vec::new();
+        art.serialize_into(&mut bytes).unwrap();
+        assert_eq!(&bytes[..7], b"ARTC\x01\x00\x00");
+        assert_eq!(
+            &bytes[11..bytes.len() - 4],
+            [
+                // the root, a Node4 with the path `a`, no end and 2 values
+                NODE4, 1, b'a', REF_NONE, 2, b'b', REF_VALUE, 1, b'c', REF_VALUE, 2, END,
+                // the root is 12 bytes back, there is 1 record
+                REF_NODE, 12, 1,
+            ]
+        );
+        assert_eq!(bytes[bytes.len() - 4..], [0xc3, 0x54, 0x64, 0xb4]);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(&Art::new());
+        let mut art = Art::new();
+        art.insert(b"single", 42);
+        round_trip(&art);
+
+        let art = round_trip(&sample());
+        assert_eq!(art.get(b"0123456789b"), Some(5));
+        assert_eq!(art.get(b"0123456789c"), None);
+
+        // every type of node, long keys and values not fitting in a slot
+        let mut art = Art::new();
+        let mut expected = BTreeMap::new();
+        for (i, seed) in (0..5_000u64).zip(test_rng()) {
+            let key = match i % 3 {
+                0 => seed.to_be_bytes().to_vec(),
+                1 => (seed % 300).to_be_bytes().to_vec(),
+                _ => format!("some/long/shared/path/{}", seed % 1000).into_bytes(),
+            };
+            let value = if i % 5 == 0 { seed } else { seed >> 40 };
+            art.insert(&key, value);
+            expected.insert(key, value);
+        }
+        let art = round_trip(&art);
+        for (key, value) in &expected {
+            assert_eq!(art.get(key), Some(*value));
+        }
+        assert_eq!(art.iter().count(), expected.len());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut bytes = Vec::new();
+        sample().serialize_into(&mut bytes).unwrap();
+        for len in 0..bytes.len() {
+            assert!(
+                matches!(
+                    Art::deserialize_from(&bytes[..len]),
+                    Err(FormatError::Truncated)
+                ),
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut bytes = Vec::new();
+        sample().serialize_into(&mut bytes).unwrap();
+        for i in 0..bytes.len() {
+            for flip in [0x01, 0x80, 0xff] {
+                let mut corrupted = bytes.clone();
+                corrupted[i] ^= flip;
+                assert!(Art::deserialize_from(corrupted.as_slice()).is_err(), "{i}");
+            }
+        }
+
+        bytes[0] = b'B';
+        assert!(matches!(
+            Art::deserialize_from(bytes.as_slice()),
+            Err(FormatError::BadMagic)
+        ));
+        let mut header = header();
+        header[4] = 2;
+        let crc = crc32(&header[..7]);
+        header[7..].copy_from_slice(&crc.to_le_bytes());
+        assert!(matches!(
+            Art::deserialize_from(header.as_slice()),
+            Err(FormatError::UnsupportedVersion(2))
+        ));
+    }
+
+    /// Frame `body` with a header and a trailer whose checksum matches.
+    fn frame(body: &[u8], root: u64, records: u64) -> Vec<u8> {
+        let mut bytes = header().to_vec();
+        let mut rest = body.to_vec();
+        rest.push(END);
+        rest.push(REF_NODE);
+        let distance = rest.len() as u64 - 1 - root;
+        push_varint(&mut rest, distance);
+        push_varint(&mut rest, records);
+        let crc = crc32(&rest);
+        bytes.extend(rest);
+        bytes.extend(crc.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn invalid_trees() {
+        let load = |bytes: Vec<u8>| match Art::deserialize_from(bytes.as_slice()) {
+            Err(FormatError::Corrupted(reason)) => reason,
+            other => panic!("{other:?}"),
+        };
+        let leaf = [LEAF, 2, b'a', b'b', 1];
+
+        // a well formed tree
+        let mut body = leaf.to_vec();
+        body.extend([NODE4, 1, b'a', REF_NONE, 1, b'b', REF_NODE, 5]);
+        let art = Art::deserialize_from(frame(&body, 5, 2).as_slice()).unwrap();
+        assert_eq!(art.get(b"ab"), Some(1));
+
+        let mut body = leaf.to_vec();
+        body.extend([NODE4, 1, b'x', REF_NONE, 1, b'b', REF_NODE, 5]);
+        assert_eq!(
+            load(frame(&body, 5, 2)),
+            "key of a leaf not matching its path"
+        );
+
+        let mut body = leaf.to_vec();
+        body.extend([NODE4, 0, REF_NONE, 2, b'a', REF_NODE, 5, b'b', REF_NODE, 5]);
+        assert_eq!(load(frame(&body, 5, 2)), "node referenced twice");
+
+        let mut body = leaf.to_vec();
+        body.extend([
+            NODE4, 0, REF_NONE, 2, b'b', REF_VALUE, 1, b'a', REF_VALUE, 2,
+        ]);
+        assert_eq!(load(frame(&body, 5, 2)), "children not sorted by key");
+
+        let mut body = leaf.to_vec();
+        body.extend([NODE4, 0, REF_NONE, 1, b'b', REF_VALUE, 1]);
+        assert_eq!(load(frame(&body, 5, 2)), "node not reachable from the root");
+
+        let mut body = b"\x02\x0a0123456789\x00\x01a".to_vec();
+        body.extend([REF_VALUE, 1]);
+        assert_eq!(
+            load(frame(&body, 0, 1)),
+            "value stored below a truncated path"
+        );
+
+        let mut body = leaf.to_vec();
+        body.extend([NODE4, 0, REF_NONE, 1, b'a', REF_NODE, 4]);
+        assert_eq!(
+            load(frame(&body, 5, 2)),
+            "reference to the middle of a record"
+        );
+    }
+
+    #[test]
+    fn deep_trees() {
+        // a chain of Node4s with a single child each, far deeper than the
+        // call stack would allow to recurse through
+        let depth = 200_000;
+        let key = std::vec![b'a'; depth];
+        let mut body = std::vec![LEAF];
+        push_varint(&mut body, depth as u64);
+        body.extend(&key);
+        body.push(1);
+        let mut child = 0;
+        for _ in 0..depth {
+            let start = body.len() as u64;
+            body.extend([NODE4, 0, REF_NONE, 1, b'a', REF_NODE]);
+            push_varint(&mut body, start - child);
+            child = start;
+        }
+        let bytes = frame(&body, child, depth as u64 + 1);
+        let art = Art::deserialize_from(bytes.as_slice()).unwrap();
+        assert_eq!(art.get(&key), Some(1));
+        assert_eq!(art.get(&key[1..]), None);
+
+        let mut again = Vec::new();
+        art.serialize_into(&mut again).unwrap();
+        assert_eq!(bytes, again);
+
+        // the nodes built so far are freed when the input is rejected
+        // once the whole chain is on the stack
+        let mut corrupted = body.clone();
+        // the first byte of the key, after the type and the 3 bytes of length
+        corrupted[4] = b'b';
+        let bytes = frame(&corrupted, child, depth as u64 + 1);
+        assert!(matches!(
+            Art::deserialize_from(bytes.as_slice()),
+            Err(FormatError::Corrupted(
+                "key of a leaf not matching its path"
+            ))
+        ));
+    }
+}
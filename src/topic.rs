@@ -0,0 +1,498 @@
+//! Matching of a concrete topic against stored topic filters, as done by
+//! an MQTT broker to route a message to its subscribers.
+//!
+//! The stored keys are filters made of levels separated by a delimiter. A
+//! level holding only the single-level wildcard matches any one level of
+//! the topic, and a last level holding only the multi-level wildcard
+//! matches its parent level and any number of levels below it. Since a
+//! filter is matched byte by byte, a wildcard followed by other bytes in
+//! its level never matches, like an invalid filter in MQTT.
+//!
+//! The tree is descended along the topic. At the start of a level there
+//! are at most three children to follow: the byte of the topic and the two
+//! wildcards, the other subtrees can't contain a matching filter.
+
+use alloc::vec::Vec;
+
+use crate::{child::ChildRef, Allocator, Art, InnerNode};
+
+/// The bytes separating and replacing the levels of a topic, see
+/// [`Art::matching_subscriptions_with`]. The default is the syntax of MQTT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopicSyntax {
+    /// The byte separating the levels, `/` in MQTT.
+    pub delimiter: u8,
+    /// The wildcard matching exactly one level, `+` in MQTT.
+    pub single_level: u8,
+    /// The wildcard matching any number of trailing levels, `#` in MQTT.
+    pub multi_level: u8,
+}
+
+impl Default for TopicSyntax {
+    fn default() -> Self {
+        TopicSyntax {
+            delimiter: b'/',
+            single_level: b'+',
+            multi_level: b'#',
+        }
+    }
+}
+
+/// How much of the topic is matched by the bytes of a filter.
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// The `pos` first bytes of the topic are matched.
+    At {
+        pos: usize,
+        /// The next byte of the filter starts a level.
+        level_start: bool,
+        /// The topic is exhausted but the filter continued with a
+        /// delimiter, only the multi-level wildcard may follow.
+        parent: bool,
+    },
+    /// A multi-level wildcard matched the rest of the topic.
+    Done,
+}
+
+/// An iterator over the stored filters matching a topic, sorted by key,
+/// see [`Art::matching_subscriptions`].
+pub struct Subscriptions<'a, 't> {
+    topic: &'t [u8],
+    syntax: TopicSyntax,
+    /// The bytes of the path followed to reach the current node.
+    path: Vec<u8>,
+    /// The children left to visit: the length of the path leading to
+    /// their parent, the byte leading to them if any, and the state of the
+    /// matching before it.
+    stack: Vec<(usize, Option<u8>, ChildRef<'a>, State)>,
+}
+
+impl<'a, 't> Subscriptions<'a, 't> {
+    /// Consume the `byte` of a filter, return `None` if the filter can't
+    /// match the topic anymore.
+    fn step(&self, state: State, byte: u8) -> Option<State> {
+        let TopicSyntax {
+            delimiter,
+            single_level,
+            multi_level,
+        } = self.syntax;
+        let State::At {
+            pos,
+            level_start,
+            parent,
+        } = state
+        else {
+            return None;
+        };
+        if parent || (level_start && byte == multi_level) {
+            return (byte == multi_level).then_some(State::Done);
+        }
+        if level_start && byte == single_level {
+            let level = self.topic[pos..].iter().position(|b| *b == delimiter);
+            return Some(State::At {
+                pos: level.map_or(self.topic.len(), |len| pos + len),
+                level_start: false,
+                parent: false,
+            });
+        }
+        match self.topic.get(pos) {
+            Some(b) if *b == byte => Some(State::At {
+                pos: pos + 1,
+                level_start: byte == delimiter,
+                parent: false,
+            }),
+            None if byte == delimiter => Some(State::At {
+                pos,
+                level_start: true,
+                parent: true,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Consume all the `bytes` of a filter and append them to the path.
+    fn push(&mut self, mut state: State, bytes: &[u8]) -> Option<State> {
+        for &byte in bytes {
+            self.path.push(byte);
+            state = self.step(state, byte)?;
+        }
+        Some(state)
+    }
+
+    /// Whether a filter ending in `state` matches the whole topic.
+    fn is_match(&self, state: State) -> bool {
+        match state {
+            State::At { pos, parent, .. } => !parent && pos == self.topic.len(),
+            State::Done => true,
+        }
+    }
+
+    /// Return the filter stored in `child` if it matches, or push the
+    /// children that may lead to a matching filter.
+    fn visit(&mut self, child: ChildRef<'a>, state: State) -> Option<(Vec<u8>, u64)> {
+        let len = self.path.len();
+        let (state, value) = match child {
+            ChildRef::Value(value) => (state, value),
+            ChildRef::Node(node) => match &node.inner {
+                InnerNode::Empty => return None,
+                InnerNode::SingleValueLeaf { key, value } => {
+                    (self.push(state, &key[len..])?, *value)
+                }
+                inner => {
+                    let state = self.push(state, node.path(len))?;
+                    let len = self.path.len();
+                    let mut bytes: Vec<u8> = match state {
+                        State::Done => Vec::new(),
+                        State::At {
+                            pos,
+                            level_start,
+                            parent,
+                        } => {
+                            let mut bytes = Vec::new();
+                            if !parent {
+                                // a delimiter after the end of the topic
+                                // leads to a multi-level wildcard
+                                let byte = self.topic.get(pos);
+                                bytes.push(*byte.unwrap_or(&self.syntax.delimiter));
+                            }
+                            if level_start && !parent {
+                                bytes.push(self.syntax.single_level);
+                            }
+                            if level_start || parent {
+                                bytes.push(self.syntax.multi_level);
+                            }
+                            bytes
+                        }
+                    };
+                    // pushed from the greatest so they are popped in order
+                    bytes.sort_unstable_by(|a, b| b.cmp(a));
+                    bytes.dedup();
+                    for byte in bytes {
+                        if let Some(child) = inner.find_child(byte) {
+                            self.stack.push((len, Some(byte), child.get(), state));
+                        }
+                    }
+                    // the filter ending at this node is the smallest of its subtree
+                    if let Some(end) = &node.end {
+                        self.stack.push((len, None, end.get(), state));
+                    }
+                    return None;
+                }
+            },
+        };
+        self.is_match(state).then(|| (self.path.clone(), value))
+    }
+}
+
+impl Iterator for Subscriptions<'_, '_> {
+    type Item = (Vec<u8>, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((len, byte, child, state)) = self.stack.pop() {
+            self.path.truncate(len);
+            let state = match byte {
+                Some(byte) => match self.push(state, &[byte]) {
+                    Some(state) => state,
+                    None => continue,
+                },
+                None => state,
+            };
+            if let Some(entry) = self.visit(child, state) {
+                return Some(entry);
+            }
+        }
+        None
+    }
+}
+
+impl<A: Allocator> Art<A> {
+    /// Iterate over the stored topic filters matching `topic`, using the
+    /// MQTT syntax: levels separated by `/`, `+` matching one level and a
+    /// trailing `#` matching any number of levels. The filters are sorted
+    /// by key and yielded with their value.
+    pub fn matching_subscriptions<'a, 't>(&'a self, topic: &'t [u8]) -> Subscriptions<'a, 't> {
+        self.matching_subscriptions_with(topic, TopicSyntax::default())
+    }
+
+    /// Iterate over the stored topic filters matching `topic`, with the
+    /// delimiter and wildcards of `syntax`.
+    pub fn matching_subscriptions_with<'a, 't>(
+        &'a self,
+        topic: &'t [u8],
+        syntax: TopicSyntax,
+    ) -> Subscriptions<'a, 't> {
+        let start = State::At {
+            pos: 0,
+            level_start: true,
+            parent: false,
+        };
+        Subscriptions {
+            topic,
+            syntax,
+            path: Vec::new(),
+            stack: Vec::from([(0, None, ChildRef::Node(&self.root), start)]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{string::String, vec::Vec};
+
+    use super::*;
+    use crate::test::test_rng;
+
+    fn subscriptions() -> Art {
+        let mut art = Art::new();
+        for (i, filter) in [
+            "#",
+            "sensors",
+            "sensors/#",
+            "sensors/+",
+            "sensors/+/temp",
+            "sensors/kitchen/temp",
+            "sensors/kitchen/+/raw",
+            "sensors/+/+",
+            "sensors/ki+/temp",
+            "+/+/temp",
+            "+/#",
+            "a/very/long/shared/prefix/+/temp",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            art.insert(filter.as_bytes(), i as u64);
+        }
+        // doesn't fit in a slot
+        art.insert(b"sensors/+/humidity", u64::MAX);
+        art
+    }
+
+    fn matching(art: &Art, topic: &str) -> Vec<(String, u64)> {
+        art.matching_subscriptions(topic.as_bytes())
+            .map(|(filter, value)| (String::from_utf8(filter).unwrap(), value))
+            .collect()
+    }
+
+    #[test]
+    fn matching_subscriptions() {
+        let art = subscriptions();
+        insta::assert_debug_snapshot!(matching(&art, "sensors/kitchen/temp"), @r###"
+        [
+            (
+                "#",
+                0,
+            ),
+            (
+                "+/#",
+                10,
+            ),
+            (
+                "+/+/temp",
+                9,
+            ),
+            (
+                "sensors/#",
+                2,
+            ),
+            (
+                "sensors/+/+",
+                7,
+            ),
+            (
+                "sensors/+/temp",
+                4,
+            ),
+            (
+                "sensors/kitchen/temp",
+                5,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(matching(&art, "sensors/garage/humidity"), @r###"
+        [
+            (
+                "#",
+                0,
+            ),
+            (
+                "+/#",
+                10,
+            ),
+            (
+                "sensors/#",
+                2,
+            ),
+            (
+                "sensors/+/+",
+                7,
+            ),
+            (
+                "sensors/+/humidity",
+                18446744073709551615,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(matching(&art, "sensors"), @r###"
+        [
+            (
+                "#",
+                0,
+            ),
+            (
+                "+/#",
+                10,
+            ),
+            (
+                "sensors",
+                1,
+            ),
+            (
+                "sensors/#",
+                2,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(matching(&art, "sensors/"), @r###"
+        [
+            (
+                "#",
+                0,
+            ),
+            (
+                "+/#",
+                10,
+            ),
+            (
+                "sensors/#",
+                2,
+            ),
+            (
+                "sensors/+",
+                3,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(matching(&art, "sensors/kitchen/oven/raw"), @r###"
+        [
+            (
+                "#",
+                0,
+            ),
+            (
+                "+/#",
+                10,
+            ),
+            (
+                "sensors/#",
+                2,
+            ),
+            (
+                "sensors/kitchen/+/raw",
+                6,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(matching(&art, "a/very/long/shared/prefix/x/temp"), @r###"
+        [
+            (
+                "#",
+                0,
+            ),
+            (
+                "+/#",
+                10,
+            ),
+            (
+                "a/very/long/shared/prefix/+/temp",
+                11,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(matching(&art, "a/very/LONG/shared/prefix/x/temp"), @r###"
+        [
+            (
+                "#",
+                0,
+            ),
+            (
+                "+/#",
+                10,
+            ),
+        ]
+        "###);
+        insta::assert_debug_snapshot!(matching(&Art::new(), "sensors"), @"[]");
+    }
+
+    #[test]
+    fn matching_subscriptions_with() {
+        let mut art = Art::new();
+        for (i, filter) in ["com.*.orders", "com.shop.>", "com.shop.orders"]
+            .into_iter()
+            .enumerate()
+        {
+            art.insert(filter.as_bytes(), i as u64);
+        }
+        let syntax = TopicSyntax {
+            delimiter: b'.',
+            single_level: b'*',
+            multi_level: b'>',
+        };
+        let found: Vec<_> = art
+            .matching_subscriptions_with(b"com.shop.orders", syntax)
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(found, [0, 1, 2]);
+        assert_eq!(art.matching_subscriptions(b"com.shop.orders").count(), 1);
+    }
+
+    /// Whether `filter` matches `topic`, by comparing their levels.
+    fn matches(filter: &[u8], topic: &[u8]) -> bool {
+        let mut filter = filter.split(|b| *b == b'/');
+        let mut topic = topic.split(|b| *b == b'/');
+        loop {
+            match (filter.next(), topic.next()) {
+                (Some(b"#"), _) => return filter.next().is_none(),
+                (Some(b"+"), Some(_)) => (),
+                (Some(f), Some(t)) if f == t => (),
+                (None, None) => return true,
+                _ => return false,
+            }
+        }
+    }
+
+    #[test]
+    fn matching_subscriptions_random_filters() {
+        let mut art = Art::new();
+        let mut map = std::collections::BTreeMap::new();
+        let mut rng = test_rng();
+        let mut next = || rng.next().unwrap();
+        // few distinct levels so many filters match the same topics, and
+        // compressed paths longer than what fits in the header
+        let levels: [&[u8]; 6] = [b"a", b"b", b"", b"a long level", b"+", b"#"];
+        let mut random_key = |wildcards: bool| -> Vec<u8> {
+            let rng = next();
+            let len = 1 + (rng >> 60) as usize % 5;
+            let nb_levels = if wildcards { 6 } else { 4 };
+            let key: Vec<&[u8]> = (0..len)
+                .map(|i| levels[(rng >> (i * 3)) as usize % nb_levels])
+                .collect();
+            key.join(&b'/')
+        };
+        for i in 0..3_000 {
+            let filter = random_key(true);
+            art.insert(&filter, i);
+            map.insert(filter, i);
+        }
+        for _ in 0..500 {
+            let topic = random_key(false);
+            let expected: Vec<_> = map
+                .iter()
+                .filter(|(filter, _)| matches(filter, &topic))
+                .map(|(filter, value)| (filter.clone(), *value))
+                .collect();
+            let found: Vec<_> = art.matching_subscriptions(&topic).collect();
+            assert_eq!(found, expected, "{topic:?}");
+        }
+    }
+}
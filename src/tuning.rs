@@ -0,0 +1,597 @@
+//! Multi-level nodes chosen from access statistics, following "START:
+//! Self-Tuning Adaptive Radix Tree" (`assets/START.pdf`).
+//!
+//! A multi-level node spans the two key bytes following the path of a node:
+//! it maps them directly to the grandchild they lead to, so a lookup skips
+//! the children of the node. It only applies to a node whose children are
+//! all inner nodes with an empty compressed path, otherwise the two bytes
+//! wouldn't be consecutive in the keys.
+//!
+//! The multi-level nodes are built next to the subtrees they span and point
+//! to their grandchildren. A write only moves or frees the children and the
+//! grandchildren of the nodes on the path of its key, so it drops the
+//! multi-level nodes of these nodes and keeps the others. A node finds its
+//! candidate entry through its `tuning` index, which moves with its content
+//! when it is split or merged, so the access statistics survive the writes.
+//! The nodes created since the last registration become candidates the next
+//! time the accesses are tracked or the tree is optimized.
+//!
+//! Which nodes get one is decided by a simple cost model: a node must be
+//! hot, reached by a large enough share of the sampled lookups, and its
+//! grandchildren must be dense enough that the table mapping the two bytes
+//! isn't mostly empty. A node and its parent can't both use one, since the
+//! multi-level node of the parent skips the node. The deepest one is then
+//! preferred: the nodes it skips are less likely to be in the cache.
+//!
+//! Like the nodes, the candidates and the multi-level nodes are allocated by
+//! the allocator of the tree. When it fails, the tree keeps the candidates
+//! and the multi-level nodes it already has.
+
+use alloc::{vec, vec::Vec};
+use core::{
+    mem::take,
+    ptr::null_mut,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering::Relaxed},
+};
+
+use crate::{child::ChildRef, ptr::Ptr, Allocator, Art, InnerNode, Node};
+
+/// A node is hot when it's reached by at least one lookup out of this many.
+const HOT_SHARE: u64 = 64;
+
+/// The table of a multi-level node has at most this many slots per
+/// grandchild.
+const MAX_SLOTS_PER_TARGET: usize = 2;
+
+/// A multi-level node isn't worth it for fewer grandchildren.
+const MIN_TARGETS: usize = 64;
+
+/// A node that could get a multi-level node.
+#[derive(Default)]
+struct Candidate {
+    accesses: AtomicU32,
+    multi_level: Option<MultiLevel>,
+}
+
+/// The grandchildren of a node indexed by the two bytes leading to them.
+struct MultiLevel {
+    /// The two bytes of the first slot.
+    first: u16,
+    /// The tagged pointers of the [`Child`]s of the grandchildren, null for
+    /// an empty slot.
+    ///
+    /// [`Child`]: crate::child::Child
+    slots: Ptr<[*mut Node]>,
+}
+
+/// Whether all the children of `node` are inner nodes with an empty
+/// compressed path, so it could get a multi-level node.
+fn eligible(node: &Node) -> bool {
+    !node.inner.is_leaf()
+        && node.nb_childrens > 0
+        && node.inner.children().all(|(_, child)| match child.get() {
+            ChildRef::Node(child) => !child.inner.is_leaf() && child.prefix_len == 0,
+            ChildRef::Value(_) => false,
+        })
+}
+
+impl MultiLevel {
+    fn build<A: Allocator>(node: &Node, alloc: &A) -> Option<MultiLevel> {
+        // the children are iterated in key order
+        let targets = || {
+            node.inner.children().flat_map(|(byte, child)| {
+                let child = child.as_node().expect("The node is eligible");
+                child
+                    .inner
+                    .children()
+                    .map(move |(next, grandchild)| (u16::from_be_bytes([byte, next]), grandchild))
+            })
+        };
+        let (mut first, mut last, mut count) = (None, 0, 0);
+        for (bytes, _) in targets() {
+            first.get_or_insert(bytes);
+            last = bytes;
+            count += 1;
+        }
+        let first = first?;
+        let len = usize::from(last - first) + 1;
+        if count < MIN_TARGETS || len > count * MAX_SLOTS_PER_TARGET {
+            return None;
+        }
+        let mut slots = Ptr::from_fn_in(len, |_| null_mut(), alloc).ok()?;
+        for (bytes, target) in targets() {
+            slots[usize::from(bytes - first)] = target.as_ptr();
+        }
+        Some(MultiLevel { first, slots })
+    }
+
+    fn find(&self, bytes: u16) -> Option<ChildRef<'_>> {
+        let slot = *self
+            .slots
+            .get(usize::from(bytes.checked_sub(self.first)?))?;
+        if slot.is_null() {
+            None
+        } else if slot.addr() & 1 == 1 {
+            Some(ChildRef::Value((slot.addr() >> 1) as u64))
+        } else {
+            // Safety: the multi-level nodes are dropped before a write goes
+            // through them, the grandchild is still alive
+            Some(ChildRef::Node(unsafe { &*slot }))
+        }
+    }
+}
+
+/// The candidates of a tree and the statistics of its lookups.
+#[derive(Default)]
+pub(crate) struct Tuning {
+    /// Indexed by the `tuning` field of the nodes minus one, `None` until
+    /// the candidates are registered.
+    candidates: Option<Ptr<[Candidate]>>,
+    /// The number of multi-level nodes.
+    built: usize,
+    tracking: bool,
+    lookups: AtomicU64,
+}
+
+// Safety: the pointers of the multi-level nodes only give shared access to
+// the nodes of the tree, like the references of a `&Art` would.
+unsafe impl Send for Tuning {}
+unsafe impl Sync for Tuning {}
+
+/// The state of a registration of the candidates, see [`Tuning::register`].
+struct Registration<'a, A> {
+    old: &'a mut [Candidate],
+    new: &'a mut [Candidate],
+    /// The number of candidates registered so far.
+    len: usize,
+    /// Whether the multi-level nodes are rebuilt from the statistics.
+    optimize: bool,
+    tracking: bool,
+    lookups: u64,
+    built: usize,
+    alloc: &'a A,
+}
+
+impl<A: Allocator> Registration<'_, A> {
+    /// Register the candidates of the subtree `root`, carrying over their
+    /// statistics.
+    ///
+    /// The inner nodes are listed first, so they can be registered children
+    /// first without recursing: a node is blocked when one of its children
+    /// has a multi-level node.
+    fn visit(&mut self, root: &mut Node) {
+        // the inner nodes in post-order, along with the index of their parent
+        let mut nodes: Vec<(*mut Node, Option<usize>)> = Vec::new();
+        let mut stack = vec![(root as *mut Node, None)];
+        while let Some((ptr, parent)) = stack.pop() {
+            // Safety: the nodes are owned by the tree borrowed mutably, and
+            // each one is reached once, through its parent
+            let node = unsafe { &*ptr };
+            let index = nodes.len();
+            // the children pushed last are listed first, so reversing the
+            // list gives the children in key order before their parent
+            let children = node.inner.children().filter_map(|(_, child)| {
+                let inner = child.as_node().is_some_and(|child| !child.inner.is_leaf());
+                inner.then(|| (child.as_ptr(), Some(index)))
+            });
+            stack.extend(children);
+            nodes.push((ptr, parent));
+        }
+        let mut blocked = vec![false; nodes.len()];
+        for (index, (node, parent)) in nodes.into_iter().enumerate().rev() {
+            // Safety: see above, no other reference to the node is alive
+            let node = unsafe { &mut *node };
+            if self.register(node, blocked[index]) {
+                if let Some(parent) = parent {
+                    blocked[parent] = true;
+                }
+            }
+        }
+    }
+
+    /// Register `node` as a candidate if it's eligible, carrying over its
+    /// statistics. `blocked` tells if one of its children has a multi-level
+    /// node. Return whether `node` has a multi-level node.
+    fn register(&mut self, node: &mut Node, blocked: bool) -> bool {
+        let old = node.tuning.checked_sub(1).map(usize::from);
+        node.tuning = 0;
+
+        if !eligible(node) || self.len == self.new.len() {
+            return false;
+        }
+        let candidate = &mut self.new[self.len];
+        self.len += 1;
+        node.tuning = self.len as u16;
+        if let Some(old) = old {
+            let old = &mut self.old[old];
+            *candidate.accesses.get_mut() = *old.accesses.get_mut();
+            candidate.multi_level = old.multi_level.take();
+        }
+        if self.optimize {
+            let accesses = u64::from(take(candidate.accesses.get_mut()));
+            // without statistics every candidate is considered hot
+            let hot = !self.tracking || accesses * HOT_SHARE >= self.lookups;
+            if let Some(multi_level) = candidate.multi_level.take() {
+                // Safety: the multi-level nodes are allocated by `alloc`
+                unsafe { multi_level.slots.free_in(self.alloc) };
+            }
+            if hot && !blocked {
+                candidate.multi_level = MultiLevel::build(node, self.alloc);
+            }
+        }
+        self.built += usize::from(candidate.multi_level.is_some());
+        candidate.multi_level.is_some()
+    }
+}
+
+impl Tuning {
+    /// Count the candidates of the subtree `root`.
+    fn count(root: &Node) -> usize {
+        let mut count = 0;
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            count += usize::from(eligible(node));
+            stack.extend(
+                node.inner
+                    .children()
+                    .filter_map(|(_, child)| child.as_node()),
+            );
+        }
+        count
+    }
+
+    /// Register the candidates of the tree whose root is `root`, the nodes
+    /// keep their statistics. With `optimize`, also rebuild the multi-level
+    /// nodes of the hot candidates and reset the statistics.
+    ///
+    /// The candidates are kept as they are if `alloc` fails.
+    fn register<A: Allocator>(&mut self, root: &mut Node, optimize: bool, alloc: &A) {
+        let len = Tuning::count(root).min(usize::from(u16::MAX));
+        let Ok(mut new) = Ptr::from_fn_in(len, |_| Candidate::default(), alloc) else {
+            return;
+        };
+        let mut old = self.candidates.take();
+        let mut registration = Registration {
+            old: old.as_deref_mut().unwrap_or_default(),
+            new: &mut new,
+            len: 0,
+            optimize,
+            tracking: self.tracking,
+            lookups: *self.lookups.get_mut(),
+            built: 0,
+            alloc,
+        };
+        registration.visit(root);
+        self.built = registration.built;
+        self.candidates = Some(new);
+        if let Some(old) = old {
+            // Safety: the candidates are allocated by `alloc`
+            unsafe { Tuning::free_candidates(old, alloc) };
+        }
+        if optimize {
+            *self.lookups.get_mut() = 0;
+            self.tracking = false;
+        }
+    }
+
+    /// Drop the multi-level nodes of the nodes on the path of `key`, before
+    /// a write goes through them.
+    fn invalidate<A: Allocator>(&mut self, root: &Node, key: &[u8], alloc: &A) {
+        let Some(candidates) = &mut self.candidates else {
+            return;
+        };
+        let mut node = root;
+        let mut depth = 0;
+        while self.built > 0 && !node.inner.is_leaf() {
+            if let Some(index) = node.tuning.checked_sub(1) {
+                if let Some(multi_level) = candidates[usize::from(index)].multi_level.take() {
+                    // Safety: the multi-level nodes are allocated by `alloc`
+                    unsafe { multi_level.slots.free_in(alloc) };
+                    self.built -= 1;
+                }
+            }
+            depth += node.prefix_len as usize + 1;
+            let child = key
+                .get(depth - 1)
+                .and_then(|byte| node.inner.find_child(*byte));
+            match child.and_then(|child| child.as_node()) {
+                Some(child) => node = child,
+                None => return,
+            }
+        }
+    }
+
+    /// Free the candidates and their multi-level nodes.
+    ///
+    /// # Safety
+    ///
+    /// They must have been allocated by `alloc`.
+    unsafe fn free_candidates<A: Allocator>(mut candidates: Ptr<[Candidate]>, alloc: &A) {
+        for candidate in candidates.iter_mut() {
+            if let Some(multi_level) = candidate.multi_level.take() {
+                multi_level.slots.free_in(alloc);
+            }
+        }
+        candidates.free_in(alloc);
+    }
+
+    /// Free the candidates and the multi-level nodes.
+    ///
+    /// # Safety
+    ///
+    /// They must have been allocated by `alloc`.
+    pub(crate) unsafe fn free_in<A: Allocator>(&mut self, alloc: &A) {
+        if let Some(candidates) = self.candidates.take() {
+            Tuning::free_candidates(candidates, alloc);
+        }
+        self.built = 0;
+    }
+
+    /// Look `key` up like [`Node::get`], through the multi-level nodes.
+    fn get(&self, candidates: &[Candidate], root: &Node, key: &[u8]) -> Option<u64> {
+        if self.tracking {
+            self.lookups.fetch_add(1, Relaxed);
+        }
+        let mut node = root;
+        let mut depth = 0;
+        loop {
+            match &node.inner {
+                InnerNode::Empty => return None,
+                InnerNode::SingleValueLeaf {
+                    key: leaf_key,
+                    value,
+                } => return (**leaf_key == *key).then_some(*value),
+                inner => {
+                    let stored = node.stored_prefix();
+                    if key.get(depth..depth + stored.len())? != stored {
+                        return None;
+                    }
+                    depth += node.prefix_len as usize;
+                    let candidate = node
+                        .tuning
+                        .checked_sub(1)
+                        .map(|index| &candidates[usize::from(index)]);
+                    if let Some(candidate) = candidate {
+                        if self.tracking {
+                            candidate.accesses.fetch_add(1, Relaxed);
+                        }
+                        let multi_level = candidate.multi_level.as_ref();
+                        if let (Some(multi_level), Some(&[first, second])) =
+                            (multi_level, key.get(depth..depth + 2))
+                        {
+                            depth += 2;
+                            match multi_level.find(u16::from_be_bytes([first, second]))? {
+                                ChildRef::Node(child) => node = child,
+                                ChildRef::Value(value) => {
+                                    return (key.len() == depth).then_some(value)
+                                }
+                            }
+                            continue;
+                        }
+                    }
+                    let child = match key.get(depth) {
+                        Some(byte) => {
+                            depth += 1;
+                            inner.find_child(*byte)?
+                        }
+                        None if key.len() == depth => node.end.as_ref()?,
+                        None => return None,
+                    };
+                    match child.get() {
+                        ChildRef::Node(child) => node = child,
+                        ChildRef::Value(value) => return (key.len() == depth).then_some(value),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<A: Allocator> Art<A> {
+    /// Count the lookups reaching each node that could get a multi-level
+    /// node, until the next call to [`optimize`](Self::optimize).
+    ///
+    /// Counting makes the lookups slower. The statistics are kept across
+    /// the writes, the nodes created by them are only counted from the next
+    /// call to this method or to [`optimize`](Self::optimize).
+    pub fn track_accesses(&mut self) {
+        self.tuning.register(&mut self.root, false, &self.alloc);
+        self.tuning.tracking = self.tuning.candidates.is_some();
+    }
+
+    /// Rewrite the hot and dense subtrees into multi-level nodes, making the
+    /// lookups reaching them skip a level. Return the number of multi-level
+    /// nodes.
+    ///
+    /// The hot subtrees are the ones reached by many of the lookups counted
+    /// since [`track_accesses`](Self::track_accesses), every subtree is
+    /// considered hot when the accesses aren't tracked. A write drops the
+    /// multi-level nodes of the nodes on its path and keeps the others, this
+    /// is meant for the read-mostly phases of a tree. Tracking and
+    /// optimizing again adapts them to a new workload.
+    pub fn optimize(&mut self) -> usize {
+        self.tuning.register(&mut self.root, true, &self.alloc);
+        self.tuning.built
+    }
+
+    pub(crate) fn tuned_get(&self, key: &[u8]) -> Option<u64> {
+        match &self.tuning.candidates {
+            Some(candidates) => self.tuning.get(candidates, &self.root, key),
+            None => self.root.get(key),
+        }
+    }
+
+    /// Drop the multi-level nodes a write of `key` would go through, before
+    /// it modifies the nodes they point to.
+    pub(crate) fn untune(&mut self, key: &[u8]) {
+        self.tuning.invalidate(&self.root, key, &self.alloc);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, vec::Vec};
+
+    use super::*;
+    use crate::test::test_rng;
+
+    #[test]
+    fn lookups_through_multi_level_nodes() {
+        let mut art = Art::new();
+        let mut map = BTreeMap::new();
+        for i in 0..100_000u64 {
+            art.insert(&i.to_be_bytes(), i);
+            map.insert(i.to_be_bytes().to_vec(), i);
+        }
+        // keys ending in the middle of the spanned bytes, and longer ones
+        for i in (0..100_000u64).step_by(997) {
+            let key = &i.to_be_bytes()[..6];
+            art.insert(key, i);
+            map.insert(key.to_vec(), i);
+            let key = [&i.to_be_bytes()[..], b"tail"].concat();
+            art.insert(&key, i);
+            map.insert(key, i);
+        }
+        assert!(art.optimize() > 0);
+
+        for (key, value) in &map {
+            assert_eq!(art.get(key), Some(*value), "{key:?}");
+        }
+        for i in 100_000..110_000u64 {
+            assert_eq!(art.get(&i.to_be_bytes()), None);
+        }
+        assert_eq!(art.get(&[0; 7]), None);
+        assert_eq!(art.get(&[0; 9]), None);
+        assert_eq!(art.get(&[0, 0, 0, 0, 0, 0, 0, 5, 1]), None);
+        assert_eq!(art.iter().count(), map.len());
+
+        // a write only drops the multi-level nodes on its path
+        let built = art.tuning.built;
+        art.insert(&1u64.to_be_bytes(), 42);
+        assert!(0 < art.tuning.built && art.tuning.built < built);
+        assert_eq!(art.remove(&2u64.to_be_bytes()), Some(2));
+        assert_eq!(art.get(&1u64.to_be_bytes()), Some(42));
+        assert_eq!(art.get(&2u64.to_be_bytes()), None);
+        assert_eq!(art.get(&3u64.to_be_bytes()), Some(3));
+        map.insert(1u64.to_be_bytes().to_vec(), 42);
+        map.remove(&2u64.to_be_bytes()[..]);
+
+        // the multi-level nodes left stay valid through splits, merges and
+        // resizes of the nodes around them
+        for (i, rng) in (0..20_000u64).zip(test_rng()) {
+            let key = (rng % 120_000).to_be_bytes();
+            let key = &key[..6 + (rng >> 62) as usize % 3];
+            if rng & 1 == 0 {
+                assert_eq!(art.remove(key), map.remove(key));
+            } else {
+                assert_eq!(art.insert(key, i), map.insert(key.to_vec(), i));
+            }
+            if i % 5_000 == 4_999 {
+                for (key, value) in &map {
+                    assert_eq!(art.get(key), Some(*value), "{key:?}");
+                }
+            }
+            if i % 1_000 == 0 {
+                assert!(art.optimize() > 0);
+            }
+        }
+        for (key, value) in &map {
+            assert_eq!(art.get(key), Some(*value), "{key:?}");
+        }
+        assert_eq!(art.iter().count(), map.len());
+    }
+
+    #[test]
+    fn only_hot_subtrees() {
+        // 16 nodes of 256 nodes of 4 values under each first byte
+        let key = |first: u8, i: u64| [first, (i >> 10) as u8, (i >> 2) as u8, i as u8 & 3];
+        let mut art = Art::new();
+        for first in [0, 255] {
+            for i in 0..16 * 256 * 4 {
+                art.insert(&key(first, i), i);
+            }
+        }
+        art.track_accesses();
+        for i in 0..16 * 256 * 4 {
+            assert_eq!(art.get(&key(0, i)), Some(i));
+        }
+        // the root and the nodes holding the values aren't dense, and only the
+        // subtree under 0 was read
+        assert_eq!(art.optimize(), 1);
+        assert_eq!(art.get(&[255, 1, 2, 3]), Some(1035));
+        assert_eq!(art.get(&[0, 1, 2, 3]), Some(1035));
+
+        // without statistics every dense subtree is optimized
+        art.insert(&[255, 0, 0, 0], 0);
+        assert_eq!(art.optimize(), 2);
+
+        // the statistics are kept across the writes, in both subtrees
+        art.track_accesses();
+        for i in 0..16 * 256 * 4 {
+            assert_eq!(art.get(&key(255, i)), Some(i));
+        }
+        art.insert(&[255, 0, 0, 0], 1);
+        art.insert(&[0, 15, 255, 3, 0], 2);
+        assert_eq!(art.remove(&[0, 0, 0, 0]), Some(0));
+        assert_eq!(art.optimize(), 1);
+        assert_eq!(art.get(&[0, 0, 0, 0]), None);
+        assert_eq!(art.get(&[0, 15, 255, 3, 0]), Some(2));
+        assert_eq!(art.get(&[255, 0, 0, 0]), Some(1));
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let key = |i: u64| [(i >> 10) as u8, (i >> 2) as u8, i as u8 & 3];
+        let mut built = Vec::new();
+        for budget in (0..150_000).step_by(4_999) {
+            let used = std::rc::Rc::default();
+            let mut art = Art::new_in(crate::test::Budget {
+                used: std::rc::Rc::clone(&used),
+                budget: usize::MAX,
+            });
+            for i in 0..16 * 256 * 4 {
+                art.insert(&key(i), i);
+            }
+            // the tuning state gets what is left of the budget
+            art.alloc.budget = used.get() + budget;
+            art.track_accesses();
+            for i in 0..16 * 256 * 4 {
+                assert_eq!(art.get(&key(i)), Some(i));
+            }
+            built.push(art.optimize());
+            for i in 0..16 * 256 * 4 {
+                assert_eq!(art.get(&key(i)), Some(i));
+            }
+            art.alloc.budget = usize::MAX;
+            art.insert(&key(5), 0);
+            assert_eq!(art.get(&key(5)), Some(0));
+            drop(art);
+            assert_eq!(used.get(), 0);
+        }
+        // only the root is dense, its multi-level node is built once the
+        // budget allows it
+        assert_eq!(built.first(), Some(&0));
+        assert_eq!(built.last(), Some(&1));
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Art>();
+        assert_eq!(core::mem::size_of::<Node>(), 64);
+    }
+
+    #[test]
+    fn deep_trees() {
+        let (mut art, key) = crate::test::deep_chain(200_000);
+        art.track_accesses();
+        assert_eq!(art.get(&key), Some(1));
+        // the single child of each node is far too sparse
+        assert_eq!(art.optimize(), 0);
+        assert_eq!(art.get(&key), Some(1));
+        assert_eq!(art.get(&key[1..]), None);
+        // there are more eligible nodes than the indexes of the candidates
+        let candidates = art.tuning.candidates.as_deref().map(<[_]>::len);
+        assert_eq!(candidates, Some(usize::from(u16::MAX)));
+    }
+}